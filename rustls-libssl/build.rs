@@ -46,6 +46,10 @@ const ENTRYPOINTS: &[&str] = &[
     "d2i_SSL_SESSION",
     "i2d_SSL_SESSION",
     "OPENSSL_init_ssl",
+    "PEM_read_bio_SSL_SESSION",
+    "PEM_read_SSL_SESSION",
+    "PEM_write_bio_SSL_SESSION",
+    "PEM_write_SSL_SESSION",
     "SSL_accept",
     "SSL_alert_desc_string",
     "SSL_alert_desc_string_long",
@@ -59,6 +63,14 @@ const ENTRYPOINTS: &[&str] = &[
     "SSL_CIPHER_get_version",
     "SSL_CIPHER_standard_name",
     "SSL_clear_options",
+    "SSL_client_hello_get0_ciphers",
+    "SSL_client_hello_get0_compression_methods",
+    "SSL_client_hello_get0_ext",
+    "SSL_client_hello_get0_legacy_version",
+    "SSL_client_hello_get0_random",
+    "SSL_client_hello_get0_session_id",
+    "SSL_client_hello_get1_extensions_present",
+    "SSL_client_hello_isv2",
     "SSL_CONF_cmd",
     "SSL_CONF_cmd_value_type",
     "SSL_CONF_CTX_clear_flags",
@@ -80,11 +92,15 @@ const ENTRYPOINTS: &[&str] = &[
     "SSL_CTX_get0_certificate",
     "SSL_CTX_get0_privatekey",
     "SSL_CTX_get_cert_store",
+    "SSL_CTX_get_ciphers",
     "SSL_CTX_get_client_CA_list",
     "SSL_CTX_get_ex_data",
+    "SSL_CTX_get_info_callback",
+    "SSL_CTX_get_keylog_callback",
     "SSL_CTX_get_max_early_data",
     "SSL_CTX_get_num_tickets",
     "SSL_CTX_get_options",
+    "SSL_CTX_get_recv_max_early_data",
     "SSL_CTX_get_timeout",
     "SSL_CTX_get_verify_callback",
     "SSL_CTX_get_verify_depth",
@@ -97,13 +113,16 @@ const ENTRYPOINTS: &[&str] = &[
     "SSL_CTX_sess_set_get_cb",
     "SSL_CTX_sess_set_new_cb",
     "SSL_CTX_sess_set_remove_cb",
+    "SSL_CTX_set_allow_early_data_cb",
     "SSL_CTX_set_alpn_protos",
     "SSL_CTX_set_alpn_select_cb",
     "SSL_CTX_set_cert_cb",
     "SSL_CTX_set_cert_store",
+    "SSL_CTX_set_cert_verify_callback",
     "SSL_CTX_set_cipher_list",
     "SSL_CTX_set_ciphersuites",
     "SSL_CTX_set_client_CA_list",
+    "SSL_CTX_set_client_hello_cb",
     "SSL_CTX_set_default_passwd_cb",
     "SSL_CTX_set_default_passwd_cb_userdata",
     "SSL_CTX_set_default_verify_dir",
@@ -120,6 +139,7 @@ const ENTRYPOINTS: &[&str] = &[
     "SSL_CTX_set_num_tickets",
     "SSL_CTX_set_options",
     "SSL_CTX_set_post_handshake_auth",
+    "SSL_CTX_set_recv_max_early_data",
     "SSL_CTX_set_session_id_context",
     "SSL_CTX_set_srp_password",
     "SSL_CTX_set_srp_username",
@@ -127,12 +147,16 @@ const ENTRYPOINTS: &[&str] = &[
     "SSL_CTX_set_verify",
     "SSL_CTX_set_verify_depth",
     "SSL_CTX_up_ref",
+    "SSL_CTX_use_cert_and_key",
     "SSL_CTX_use_certificate",
+    "SSL_CTX_use_certificate_ASN1",
     "SSL_CTX_use_certificate_chain_file",
     "SSL_CTX_use_certificate_file",
     "SSL_CTX_use_PrivateKey",
+    "SSL_CTX_use_PrivateKey_ASN1",
     "SSL_CTX_use_PrivateKey_file",
     "SSL_do_handshake",
+    "SSL_export_keying_material",
     "SSL_free",
     "SSL_get0_alpn_selected",
     "SSL_get0_next_proto_negotiated",
@@ -141,23 +165,33 @@ const ENTRYPOINTS: &[&str] = &[
     "SSL_get1_peer_certificate",
     "SSL_get1_session",
     "SSL_get_certificate",
+    "SSL_get_ciphers",
     "SSL_get_current_cipher",
     "SSL_get_current_compression",
+    "SSL_get_early_data_status",
     "SSL_get_error",
     "SSL_get_ex_data",
     "SSL_get_ex_data_X509_STORE_CTX_idx",
+    "SSL_get_info_callback",
+    "SSL_get_key_update_type",
+    "SSL_get_max_early_data",
     "SSL_get_num_tickets",
     "SSL_get_options",
     "SSL_get_peer_cert_chain",
     "SSL_get_peer_signature_type_nid",
     "SSL_get_privatekey",
     "SSL_get_rbio",
+    "SSL_get_recv_max_early_data",
     "SSL_get_servername",
     "SSL_get_servername_type",
     "SSL_get_session",
+    "SSL_get_shared_sigalgs",
     "SSL_get_shutdown",
+    "SSL_get_sigalgs",
+    "SSL_get_signature_type_nid",
     "SSL_get_SSL_CTX",
     "SSL_get_state",
+    "SSL_get_verify_callback",
     "SSL_get_verify_depth",
     "SSL_get_verify_mode",
     "SSL_get_verify_result",
@@ -168,19 +202,48 @@ const ENTRYPOINTS: &[&str] = &[
     "SSL_in_init",
     "SSL_is_init_finished",
     "SSL_is_server",
+    "SSL_key_update",
     "SSL_load_client_CA_file",
     "SSL_new",
+    "SSL_peek",
+    "SSL_peek_ex",
     "SSL_pending",
     "SSL_read",
     "SSL_read_early_data",
+    "SSL_read_ex",
     "SSL_select_next_proto",
     "SSL_sendfile",
+    "SSL_SESSION_dup",
     "SSL_SESSION_free",
+    "SSL_SESSION_get0_alpn_selected",
+    "SSL_SESSION_get0_cipher",
+    "SSL_SESSION_get0_hostname",
+    "SSL_SESSION_get0_id_context",
+    "SSL_SESSION_get0_peer",
+    "SSL_SESSION_get0_ticket_appdata",
     "SSL_SESSION_get_id",
+    "SSL_SESSION_get_master_key",
+    "SSL_SESSION_get_max_early_data",
+    "SSL_SESSION_get_protocol_version",
+    "SSL_SESSION_get_ticket_lifetime_hint",
     "SSL_SESSION_get_time",
     "SSL_SESSION_get_timeout",
+    "SSL_SESSION_has_ticket",
+    "SSL_SESSION_is_resumable",
+    "SSL_SESSION_new",
+    "SSL_SESSION_print",
+    "SSL_SESSION_print_fp",
+    "SSL_SESSION_print_keylog",
     "SSL_session_reused",
+    "SSL_SESSION_set1_alpn_selected",
+    "SSL_SESSION_set1_hostname",
+    "SSL_SESSION_set1_id",
     "SSL_SESSION_set1_id_context",
+    "SSL_SESSION_set1_master_key",
+    "SSL_SESSION_set1_ticket_appdata",
+    "SSL_SESSION_set_cipher",
+    "SSL_SESSION_set_max_early_data",
+    "SSL_SESSION_set_protocol_version",
     "SSL_SESSION_set_time",
     "SSL_SESSION_set_timeout",
     "SSL_SESSION_up_ref",
@@ -188,15 +251,22 @@ const ENTRYPOINTS: &[&str] = &[
     "SSL_set0_wbio",
     "SSL_set1_host",
     "SSL_set_accept_state",
+    "SSL_set_allow_early_data_cb",
     "SSL_set_alpn_protos",
     "SSL_set_bio",
+    "SSL_set_cipher_list",
+    "SSL_set_ciphersuites",
     "SSL_set_connect_state",
     "SSL_set_ex_data",
     "SSL_set_fd",
+    "SSL_set_info_callback",
+    "SSL_set_max_early_data",
+    "SSL_set_msg_callback",
     "SSL_set_num_tickets",
     "SSL_set_options",
     "SSL_set_post_handshake_auth",
     "SSL_set_quiet_shutdown",
+    "SSL_set_recv_max_early_data",
     "SSL_set_session",
     "SSL_set_shutdown",
     "SSL_set_SSL_CTX",
@@ -204,13 +274,19 @@ const ENTRYPOINTS: &[&str] = &[
     "SSL_set_verify_depth",
     "SSL_shutdown",
     "SSL_up_ref",
+    "SSL_use_cert_and_key",
     "SSL_use_certificate",
+    "SSL_use_certificate_ASN1",
+    "SSL_use_certificate_chain_file",
+    "SSL_use_certificate_file",
     "SSL_use_PrivateKey",
+    "SSL_use_PrivateKey_ASN1",
     "SSL_use_PrivateKey_file",
     "SSL_version",
     "SSL_want",
     "SSL_write",
     "SSL_write_early_data",
+    "SSL_write_ex",
     "TLS_client_method",
     "TLS_method",
     "TLS_server_method",
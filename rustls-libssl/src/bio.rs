@@ -1,6 +1,12 @@
-use core::ffi::{c_char, c_int, c_long, c_void, CStr};
+use core::ffi::{c_char, c_int, c_long, c_uchar, c_void, CStr};
+use core::{ptr, slice};
 use std::io;
 
+use openssl_sys::OPENSSL_free;
+
+use crate::callbacks::MsgCallbackConfig;
+use crate::entry::pem_password_cb;
+
 // nb. cannot use any BIO types from openssl_sys: it doesn't
 // have the internal type for BIO_METHOD, and once we provide
 // it here their opaque type doesn't match ours.
@@ -13,6 +19,7 @@ use std::io;
 pub struct Bio {
     read: *mut BIO,
     write: *mut BIO,
+    records: RecordObserver,
 }
 
 impl Bio {
@@ -25,7 +32,11 @@ impl Bio {
             BIO_up_ref(bio);
             (bio, bio)
         };
-        Self { read, write }
+        Self {
+            read,
+            write,
+            records: RecordObserver::default(),
+        }
     }
 
     /// Use a pair of raw BIO pointers.
@@ -40,6 +51,7 @@ impl Bio {
         let mut ret = Self {
             read: null_2,
             write: null_2,
+            records: RecordObserver::default(),
         };
         ret.update(rbio, wbio);
         ret
@@ -182,6 +194,18 @@ impl Bio {
     pub fn borrow_write(&self) -> *mut BIO {
         self.write
     }
+
+    /// The `ClientHello` message read through this BIO, if any.
+    ///
+    /// This is only available when the first handshake message received
+    /// is a `ClientHello` -- ie, for servers.
+    pub fn client_hello(&self) -> Option<&[u8]> {
+        self.records
+            .read
+            .first_handshake
+            .as_deref()
+            .filter(|message| message.first() == Some(&HANDSHAKE_TYPE_CLIENT_HELLO))
+    }
 }
 
 impl io::Read for Bio {
@@ -242,6 +266,180 @@ impl io::Write for Bio {
     }
 }
 
+/// Wraps a [`Bio`] for a round of I/O.
+///
+/// This counts the bytes read and written through it, and reports the TLS
+/// records within to the `SSL_CTX_set_msg_callback` callback (if any).
+pub struct ObservedBio<'a> {
+    bio: &'a mut Bio,
+    msg_callback: &'a MsgCallbackConfig,
+    version: c_int,
+    count: usize,
+}
+
+impl<'a> ObservedBio<'a> {
+    /// `version` is reported with record contents: see `SSL_CTX_set_msg_callback`.
+    pub fn new(bio: &'a mut Bio, msg_callback: &'a MsgCallbackConfig, version: c_int) -> Self {
+        Self {
+            bio,
+            msg_callback,
+            version,
+            count: 0,
+        }
+    }
+
+    /// Total bytes read and written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    fn observe(&mut self, write_p: bool, data: &[u8]) {
+        let stream = match write_p {
+            true => &mut self.bio.records.write,
+            false => &mut self.bio.records.read,
+        };
+
+        // nb. this happens even without a callback, so we stay in step with
+        // the record boundaries if one is set later.
+        stream.observe(data, |content_type, bytes| {
+            // like OpenSSL, headers are reported with their record-layer version.
+            let version = match content_type {
+                SSL3_RT_HEADER => u16::from_be_bytes([bytes[1], bytes[2]]) as c_int,
+                _ => self.version,
+            };
+            self.msg_callback
+                .invoke(write_p, version, content_type, bytes)
+        });
+    }
+}
+
+impl io::Read for ObservedBio<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.bio.read(buf)?;
+        self.count += read;
+        self.observe(false, &buf[..read]);
+        Ok(read)
+    }
+}
+
+impl io::Write for ObservedBio<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.bio.write(buf)?;
+        self.count += written;
+        self.observe(true, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.bio.flush()
+    }
+}
+
+/// Tracks TLS records in each direction through a [`Bio`].
+#[derive(Default)]
+struct RecordObserver {
+    read: RecordStream,
+    write: RecordStream,
+}
+
+#[derive(Default)]
+struct RecordStream {
+    /// Start of a record we have not yet seen all of.
+    pending_record: Vec<u8>,
+
+    /// Start of a handshake message we have not yet seen all of.
+    ///
+    /// Handshake messages may be fragmented across records.
+    pending_handshake: Vec<u8>,
+
+    /// The first handshake message seen.
+    first_handshake: Option<Vec<u8>>,
+
+    /// Whether the contents of records in this direction are now encrypted.
+    ///
+    /// Unlike OpenSSL, we report only the header of encrypted records: we have
+    /// no way to see their contents.  So there are no `SSL3_RT_INNER_CONTENT_TYPE`
+    /// reports, nor any for the TLS1.3 handshake messages after `ServerHello`.
+    encrypted: bool,
+}
+
+impl RecordStream {
+    /// Feed in `data`, calling `report` with each header and plaintext
+    /// message it completes.
+    fn observe(&mut self, data: &[u8], mut report: impl FnMut(c_int, &[u8])) {
+        self.pending_record.extend_from_slice(data);
+
+        while self.pending_record.len() >= RECORD_HEADER_LEN {
+            let body_len =
+                u16::from_be_bytes([self.pending_record[3], self.pending_record[4]]) as usize;
+            if self.pending_record.len() < RECORD_HEADER_LEN + body_len {
+                break;
+            }
+
+            let record = self
+                .pending_record
+                .drain(..RECORD_HEADER_LEN + body_len)
+                .collect::<Vec<u8>>();
+            let (header, body) = record.split_at(RECORD_HEADER_LEN);
+            report(SSL3_RT_HEADER, header);
+            self.observe_record(header[0] as c_int, body, &mut report);
+        }
+    }
+
+    fn observe_record(
+        &mut self,
+        content_type: c_int,
+        body: &[u8],
+        mut report: impl FnMut(c_int, &[u8]),
+    ) {
+        match content_type {
+            _ if self.encrypted => {}
+            SSL3_RT_CHANGE_CIPHER_SPEC => {
+                report(content_type, body);
+                self.encrypted = true;
+            }
+            SSL3_RT_ALERT => report(content_type, body),
+            SSL3_RT_HANDSHAKE => {
+                self.pending_handshake.extend_from_slice(body);
+
+                while self.pending_handshake.len() >= HANDSHAKE_HEADER_LEN {
+                    let body_len = u32::from_be_bytes([
+                        0,
+                        self.pending_handshake[1],
+                        self.pending_handshake[2],
+                        self.pending_handshake[3],
+                    ]) as usize;
+                    if self.pending_handshake.len() < HANDSHAKE_HEADER_LEN + body_len {
+                        break;
+                    }
+
+                    let message = self
+                        .pending_handshake
+                        .drain(..HANDSHAKE_HEADER_LEN + body_len)
+                        .collect::<Vec<u8>>();
+                    report(content_type, &message);
+                    if self.first_handshake.is_none() {
+                        self.first_handshake = Some(message);
+                    }
+                }
+            }
+            // TLS1.3 encrypts everything after the `ServerHello` into
+            // "application data" records.
+            _ => self.encrypted = true,
+        }
+    }
+}
+
+const RECORD_HEADER_LEN: usize = 5;
+const HANDSHAKE_HEADER_LEN: usize = 4;
+const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 1;
+
+// See SSL3_RT_* from ssl3.h
+const SSL3_RT_CHANGE_CIPHER_SPEC: c_int = 20;
+const SSL3_RT_ALERT: c_int = 21;
+const SSL3_RT_HANDSHAKE: c_int = 22;
+const SSL3_RT_HEADER: c_int = 0x100;
+
 impl Drop for Bio {
     fn drop(&mut self) {
         unsafe {
@@ -316,6 +514,15 @@ pub struct bio_method_st {
 unsafe impl Send for bio_method_st {}
 unsafe impl Sync for bio_method_st {}
 
+#[repr(C)]
+pub struct OpaqueFile {
+    _private: [u8; 0],
+}
+
+/// C stdio `FILE`.
+#[allow(clippy::upper_case_acronyms)]
+pub type FILE = OpaqueFile;
+
 #[allow(non_camel_case_types)]
 pub type BIO_info_cb =
     Option<unsafe extern "C" fn(arg1: *mut BIO, arg2: c_int, arg3: c_int) -> c_int>;
@@ -332,6 +539,103 @@ pub type BIO = OpaqueBio;
 #[allow(clippy::upper_case_acronyms)]
 pub type BIO_METHOD = bio_method_st;
 
+/// Owning wrapper around a BIO that reads and writes a C stdio `FILE*`.
+///
+/// The `FILE*` is not closed on drop.
+pub struct FileBio(*mut BIO);
+
+impl FileBio {
+    pub fn new(fp: *mut FILE) -> Option<Self> {
+        const BIO_NOCLOSE: c_int = 0x00;
+        let bio = unsafe { BIO_new_fp(fp, BIO_NOCLOSE) };
+        match bio.is_null() {
+            true => None,
+            false => Some(Self(bio)),
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut BIO {
+        self.0
+    }
+}
+
+impl Drop for FileBio {
+    fn drop(&mut self) {
+        unsafe { BIO_free_all(self.0) };
+    }
+}
+
+/// Read the contents of the next PEM section named `label` from `bio`.
+///
+/// Failures are reported on the error stack by libcrypto.
+pub fn read_pem(
+    bio: *mut BIO,
+    label: &CStr,
+    callback: pem_password_cb,
+    user_data: *mut c_void,
+) -> Option<Vec<u8>> {
+    let mut data = ptr::null_mut();
+    let mut len: c_long = 0;
+    let rc = unsafe {
+        PEM_bytes_read_bio(
+            &mut data,
+            &mut len,
+            ptr::null_mut(),
+            label.as_ptr(),
+            bio,
+            callback,
+            user_data,
+        )
+    };
+    if rc != 1 || data.is_null() {
+        return None;
+    }
+
+    let contents = unsafe { slice::from_raw_parts(data, len as usize) }.to_vec();
+    unsafe { OPENSSL_free(data as *mut c_void) };
+    Some(contents)
+}
+
+/// Write `data` to `bio` as a PEM section named `label`.
+pub fn write_pem(bio: *mut BIO, label: &CStr, data: &[u8]) -> bool {
+    unsafe {
+        PEM_write_bio(
+            bio,
+            label.as_ptr(),
+            c"".as_ptr(),
+            data.as_ptr(),
+            data.len() as c_long,
+        ) > 0
+    }
+}
+
+/// Write all of `data` to `bio`.
+pub fn write_all(bio: *mut BIO, data: &[u8]) -> bool {
+    let mut written = 0;
+    data.is_empty()
+        || unsafe {
+            BIO_write_ex(
+                bio,
+                data.as_ptr() as *const c_void,
+                data.len(),
+                &mut written,
+            )
+        } == 1
+            && written == data.len()
+}
+
+/// Write a hex dump of `data` to `bio`, with each line indented by `indent` spaces.
+pub fn dump_indent(bio: *mut BIO, data: &[u8], indent: c_int) -> bool {
+    unsafe {
+        BIO_dump_indent(
+            bio,
+            data.as_ptr() as *const c_void,
+            data.len() as c_int,
+            indent,
+        ) > 0
+    }
+}
+
 fn bio_should_retry_read(b: *const BIO) -> bool {
     const BIO_FLAGS_READ: c_int = 0x01;
     const BIO_SHOULD_RETRY: c_int = 0x08;
@@ -358,4 +662,61 @@ extern "C" {
     fn BIO_up_ref(b: *mut BIO) -> c_int;
     fn BIO_test_flags(b: *const BIO, flags: c_int) -> c_int;
     fn BIO_s_null() -> *const BIO_METHOD;
+    fn BIO_new_fp(stream: *mut FILE, close_flag: c_int) -> *mut BIO;
+    fn BIO_dump_indent(bp: *mut BIO, s: *const c_void, len: c_int, indent: c_int) -> c_int;
+    fn PEM_bytes_read_bio(
+        pdata: *mut *mut c_uchar,
+        plen: *mut c_long,
+        pnm: *mut *mut c_char,
+        name: *const c_char,
+        bp: *mut BIO,
+        cb: pem_password_cb,
+        u: *mut c_void,
+    ) -> c_int;
+    fn PEM_write_bio(
+        bp: *mut BIO,
+        name: *const c_char,
+        hdr: *const c_char,
+        data: *const c_uchar,
+        len: c_long,
+    ) -> c_int;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_stream() {
+        let mut stream = RecordStream::default();
+        let mut seen = vec![];
+
+        // a handshake message fragmented over two records, each delivered in pieces
+        let data = [
+            &[22, 3, 3, 0, 4, 1, 0, 0, 2][..],
+            &[22, 3, 3, 0, 2, 0xaa, 0xbb][..],
+            // ChangeCipherSpec, after which contents are not reported
+            &[20, 3, 3, 0, 1, 1][..],
+            &[22, 3, 3, 0, 4, 20, 0, 0, 0][..],
+        ];
+        for chunk in data.iter().flat_map(|d| d.chunks(3)) {
+            stream.observe(chunk, |content_type, bytes| {
+                seen.push((content_type, bytes.to_vec()))
+            });
+        }
+
+        assert_eq!(
+            seen,
+            vec![
+                (SSL3_RT_HEADER, vec![22, 3, 3, 0, 4]),
+                (SSL3_RT_HEADER, vec![22, 3, 3, 0, 2]),
+                (SSL3_RT_HANDSHAKE, vec![1, 0, 0, 2, 0xaa, 0xbb]),
+                (SSL3_RT_HEADER, vec![20, 3, 3, 0, 1]),
+                (SSL3_RT_CHANGE_CIPHER_SPEC, vec![1]),
+                (SSL3_RT_HEADER, vec![22, 3, 3, 0, 4]),
+            ]
+        );
+        assert!(stream.pending_record.is_empty());
+        assert_eq!(stream.first_handshake, Some(vec![1, 0, 0, 2, 0xaa, 0xbb]));
+    }
 }
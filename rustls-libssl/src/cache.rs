@@ -1,17 +1,26 @@
-use core::ptr;
-use std::collections::BTreeSet;
+use core::{mem, ptr};
+use std::collections::{BTreeSet, VecDeque};
+use std::ffi::CString;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
-use rustls::client::ClientSessionMemoryCache;
-use rustls::client::ClientSessionStore;
+use rustls::client::{
+    ClientSessionMemoryCache, ClientSessionStore, ResolvesClientCert, Tls12ClientSessionValue,
+    Tls13ClientSessionValue,
+};
+use rustls::crypto::SecureRandom;
+use rustls::pki_types::{CertificateDer, ServerName};
 use rustls::server::StoresServerSessions;
+use rustls::{NamedGroup, ProtocolVersion};
 
 use crate::entry::{
     SSL_CTX_new_session_cb, SSL_CTX_sess_get_cb, SSL_CTX_sess_remove_cb, SSL_CTX, SSL_SESSION,
 };
 use crate::not_thread_safe::NotThreadSafe;
+use crate::session::SessionProperties;
+use crate::sign::SigSchemeLog;
+use crate::verifier::{ServerVerifier, VerifyLog};
 use crate::{callbacks, SslSession, SslSessionLookup};
 
 /// A container for session caches that can live inside
@@ -20,7 +29,7 @@ pub struct SessionCaches {
     max_size: usize,
 
     /// the underlying client store. This outlives any given connection.
-    client: Option<Arc<dyn ClientSessionStore + Send + Sync>>,
+    client: Option<Arc<ClientSessionStorage>>,
 
     /// the underlying server store. This outlives any given connection.
     server: Arc<ServerSessionStorage>,
@@ -46,15 +55,53 @@ impl SessionCaches {
         self.server.set_ssl_ctx(ptr);
     }
 
-    /// Get a cache that can be used for an in-construction `ClientConnection`
-    pub fn get_client(&mut self) -> Arc<dyn ClientSessionStore + Send + Sync> {
-        Arc::clone(self.client.get_or_insert_with(|| {
-            Arc::new(ClientSessionMemoryCache::new(if self.max_size == 0 {
+    /// Get a cache that can be used for a single `ClientConnection`.
+    ///
+    /// `seeded` is a session from `SSL_set_session`, which is offered in
+    /// preference to anything in the shared store.
+    pub fn get_client(
+        &mut self,
+        origin: ClientSessionOrigin,
+        seeded: Option<Arc<NotThreadSafe<SslSession>>>,
+        random: &'static dyn SecureRandom,
+    ) -> Arc<SingleClientCache> {
+        let parent = Arc::clone(self.client.get_or_insert_with(|| {
+            Arc::new(ClientSessionStorage::new(if self.max_size == 0 {
                 usize::MAX
             } else {
                 self.max_size
             }))
-        }))
+        }));
+        Arc::new(SingleClientCache {
+            parent,
+            shared: self.server.clone(),
+            origin,
+            seeded,
+            random,
+            most_recent_session: Mutex::new(None),
+            pending: Mutex::default(),
+        })
+    }
+
+    /// Find the origin of a stored TLS1.3 session for `server_name` that a
+    /// connection configured with `verifier` and `client_chain` can adopt,
+    /// and the verify result recorded in that session.
+    ///
+    /// The connection's cache then offers that session's ticket.
+    ///
+    /// divergence: OpenSSL clients only resume sessions given to `SSL_set_session`.
+    pub fn find_client_origin(
+        &self,
+        server_name: &ServerName<'_>,
+        verifier: &ServerVerifier,
+        client_chain: Option<&[CertificateDer<'static>]>,
+    ) -> Option<(ClientSessionOrigin, i64)> {
+        if self.server.mode() & CACHE_MODE_NO_INTERNAL_LOOKUP != 0 {
+            return None;
+        }
+        self.client
+            .as_ref()?
+            .find_origin(server_name, verifier, client_chain)
     }
 
     /// Get a cache that can be used for a single `ServerConnection`
@@ -220,6 +267,10 @@ impl ServerSessionStorage {
             .unwrap_or_default()
     }
 
+    fn has_new_callback(&self) -> bool {
+        self.callbacks().new_callback.is_some()
+    }
+
     fn invoke_new_callback(&self, sess: Arc<NotThreadSafe<SslSession>>) -> bool {
         callbacks::invoke_session_new_callback(self.callbacks().new_callback, sess)
     }
@@ -388,10 +439,15 @@ impl CacheParameters {
 /// A `StoresServerSessions` implementor that is bound to a single `SSL`,
 /// and tracks which `SSL_SESSION` was most recently used, to allow
 /// `SSL_get_session` to work.
+///
+/// The values rustls gives us are opaque, so (as for clients) the application
+/// is only told of a new session once the connection has completed its properties.
 #[derive(Debug)]
 pub struct SingleServerCache {
     parent: Arc<ServerSessionStorage>,
     most_recent_session: Mutex<Option<Arc<NotThreadSafe<SslSession>>>>,
+    /// Sessions awaiting `complete_new_sessions`.
+    pending: Mutex<Vec<Arc<NotThreadSafe<SslSession>>>>,
 }
 
 impl SingleServerCache {
@@ -399,6 +455,21 @@ impl SingleServerCache {
         Self {
             parent,
             most_recent_session: Mutex::new(None),
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Fill in the properties of sessions made since the last call, and offer
+    /// them to the application.
+    pub fn complete_new_sessions(&self, complete: impl Fn(&mut SslSession)) {
+        let pending = match self.pending.lock() {
+            Ok(mut pending) => mem::take(&mut *pending),
+            Err(_) => return,
+        };
+
+        for sess in pending {
+            complete(sess.get_mut());
+            self.parent.invoke_new_callback(sess);
         }
     }
 
@@ -441,7 +512,11 @@ impl StoresServerSessions for SingleServerCache {
 
         self.save_most_recent_session(sess.clone());
 
-        let possibly_stored_elsewhere = self.parent.invoke_new_callback(sess.clone());
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.push(sess.clone());
+        }
+
+        let possibly_stored_elsewhere = self.parent.has_new_callback();
 
         if self.parent.mode() & CACHE_MODE_NO_INTERNAL_STORE == 0 {
             self.parent.insert(sess) || possibly_stored_elsewhere
@@ -516,6 +591,430 @@ impl StoresServerSessions for SingleServerCache {
     }
 }
 
+/// The parts of a client connection's configuration that rustls requires
+/// to be identical (by pointer) before a session is resumed.
+///
+/// Each `SslSession` made by a client carries the origin of the connection
+/// that made it, so a later connection given that session by `SSL_set_session`
+/// can adopt the same origin.
+#[derive(Clone, Debug)]
+pub struct ClientSessionOrigin {
+    pub verifier: Arc<ServerVerifier>,
+    pub resolver: Arc<dyn ResolvesClientCert>,
+    /// Where `verifier` and `resolver` record what they see: these belong to
+    /// the connection that made this origin.
+    pub logs: ClientLogs,
+    client_chain: Option<Vec<CertificateDer<'static>>>,
+}
+
+impl ClientSessionOrigin {
+    pub fn new(
+        verifier: Arc<ServerVerifier>,
+        resolver: Arc<dyn ResolvesClientCert>,
+        logs: ClientLogs,
+        client_chain: Option<Vec<CertificateDer<'static>>>,
+    ) -> Self {
+        Self {
+            verifier,
+            resolver,
+            logs,
+            client_chain,
+        }
+    }
+
+    /// Have this origin's verifier and resolver record into `logs` instead.
+    ///
+    /// A connection that adopts this origin does this, so it does not see (or
+    /// change) what the origin's connection recorded.  That connection has
+    /// finished its handshake by the time its session can be adopted, so its
+    /// verifier and resolver have nothing more to record for it.
+    ///
+    /// divergence: if two connections adopting one origin verify a server
+    /// at the same time, the first may see what the second records.
+    pub fn record_into(&self, logs: &ClientLogs) {
+        if Arc::ptr_eq(&self.logs.verify, &logs.verify) {
+            return;
+        }
+        self.logs.verify.redirect_to(logs.verify.clone());
+        self.logs.sig_schemes.redirect_to(logs.sig_schemes.clone());
+    }
+
+    /// Whether a connection configured with `verifier` and `client_chain`
+    /// may adopt this origin.
+    ///
+    /// If the server declines resumption, the full handshake that follows
+    /// uses this origin's verifier and client certificate, so these must not
+    /// differ from what the connection would otherwise have used.
+    ///
+    /// divergence: openssl offers the session regardless.
+    pub fn compatible_with(
+        &self,
+        verifier: &ServerVerifier,
+        client_chain: Option<&[CertificateDer<'static>]>,
+    ) -> bool {
+        self.verifier.same_policy(verifier) && self.client_chain.as_deref() == client_chain
+    }
+}
+
+/// What a client connection's verifier and client certificate resolver
+/// have recorded.
+#[derive(Clone, Debug, Default)]
+pub struct ClientLogs {
+    pub verify: Arc<VerifyLog>,
+    pub sig_schemes: Arc<SigSchemeLog>,
+}
+
+/// Client resumption state held by an `SslSession`.
+#[derive(Debug)]
+pub struct ClientSessionData {
+    value: ClientSessionValue,
+    origin: ClientSessionOrigin,
+}
+
+impl ClientSessionData {
+    pub fn origin(&self) -> &ClientSessionOrigin {
+        &self.origin
+    }
+
+    /// The properties that rustls exposes for this session.
+    ///
+    /// The cipher suite of a TLS1.2 session is instead taken from the
+    /// connection; see `SingleClientCache::complete_new_sessions`.
+    ///
+    /// divergence: rustls does not expose the master secret or ticket
+    /// lifetime hint.
+    fn properties(&self) -> SessionProperties {
+        match &self.value {
+            ClientSessionValue::Tls12(_) => SessionProperties {
+                version: u16::from(ProtocolVersion::TLSv1_2),
+                ..SessionProperties::default()
+            },
+            ClientSessionValue::Tls13(ticket) => ticket.with(|value| SessionProperties {
+                version: u16::from(ProtocolVersion::TLSv1_3),
+                cipher: value
+                    .map(|value| u16::from(value.suite().common.suite))
+                    .unwrap_or_default(),
+                max_early_data: value
+                    .map(|value| value.max_early_data_size())
+                    .unwrap_or_default(),
+                has_ticket: true,
+                ..SessionProperties::default()
+            }),
+        }
+    }
+
+    pub fn is_resumable(&self) -> bool {
+        match &self.value {
+            ClientSessionValue::Tls12(_) => true,
+            ClientSessionValue::Tls13(ticket) => ticket.with(|value| value.is_some()),
+        }
+    }
+
+    /// Copy this for `SSL_SESSION_dup`.
+    ///
+    /// divergence: TLS1.3 tickets cannot be copied, so the copy of a
+    /// TLS1.3 session is not resumable.
+    pub fn dup(&self) -> Self {
+        Self {
+            value: match &self.value {
+                ClientSessionValue::Tls12(value) => ClientSessionValue::Tls12(value.clone()),
+                ClientSessionValue::Tls13(_) => ClientSessionValue::Tls13(TicketSlot::default()),
+            },
+            origin: self.origin.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ClientSessionValue {
+    Tls12(Tls12ClientSessionValue),
+    Tls13(TicketSlot),
+}
+
+/// A TLS1.3 ticket.
+///
+/// Tickets are single-use, so this is empty once the ticket has been
+/// offered in a `ClientHello`.  An `SslSession` may be shared between
+/// connections, so this is taken through a lock.
+#[derive(Debug, Default)]
+struct TicketSlot(Mutex<Option<Tls13ClientSessionValue>>);
+
+impl TicketSlot {
+    fn new(value: Tls13ClientSessionValue) -> Self {
+        Self(Mutex::new(Some(value)))
+    }
+
+    fn take(&self) -> Option<Tls13ClientSessionValue> {
+        self.0.lock().ok()?.take()
+    }
+
+    fn with<T>(&self, f: impl FnOnce(Option<&Tls13ClientSessionValue>) -> T) -> T {
+        match self.0.lock() {
+            Ok(value) => f(value.as_ref()),
+            Err(_) => f(None),
+        }
+    }
+}
+
+/// The client sessions shared by all connections of an `SSL_CTX`.
+///
+/// TLS1.2 sessions and key exchange hints are kept by rustls.  TLS1.3 tickets
+/// cannot be copied, so the `SslSession` holding each is kept here instead:
+/// a later connection to the same server adopts that session's origin, and
+/// takes its ticket unless another connection got there first.
+#[derive(Debug)]
+pub struct ClientSessionStorage {
+    rustls: ClientSessionMemoryCache,
+    max_size: usize,
+    /// Oldest first.
+    tls13: Mutex<VecDeque<(ServerName<'static>, Arc<NotThreadSafe<SslSession>>)>>,
+}
+
+impl ClientSessionStorage {
+    fn new(max_size: usize) -> Self {
+        Self {
+            rustls: ClientSessionMemoryCache::new(max_size),
+            max_size,
+            tls13: Mutex::default(),
+        }
+    }
+
+    fn insert_tls13_session(
+        &self,
+        server_name: ServerName<'static>,
+        sess: Arc<NotThreadSafe<SslSession>>,
+    ) {
+        let Ok(mut tls13) = self.tls13.lock() else {
+            return;
+        };
+
+        tls13.retain(|(_, sess)| sess.get().is_resumable());
+        tls13.push_back((server_name, sess));
+        while tls13.len() > self.max_size {
+            tls13.pop_front();
+        }
+    }
+
+    fn find_origin(
+        &self,
+        server_name: &ServerName<'_>,
+        verifier: &ServerVerifier,
+        client_chain: Option<&[CertificateDer<'static>]>,
+    ) -> Option<(ClientSessionOrigin, i64)> {
+        let tls13 = self.tls13.lock().ok()?;
+        tls13
+            .iter()
+            .rev()
+            .filter(|(name, _)| name == server_name)
+            .map(|(_, sess)| sess.get())
+            .filter(|sess| sess.is_resumable())
+            .find_map(|sess| {
+                let origin = sess.client_origin()?;
+                origin
+                    .compatible_with(verifier, client_chain)
+                    .then(|| (origin.clone(), sess.properties().verify_result))
+            })
+    }
+
+    /// Take the newest ticket for `server_name` that was made with `origin`.
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'_>,
+        origin: &ClientSessionOrigin,
+    ) -> Option<Tls13ClientSessionValue> {
+        let mut tls13 = self.tls13.lock().ok()?;
+        let index = tls13.iter().rposition(|(name, sess)| {
+            name == server_name
+                && sess
+                    .get()
+                    .client_origin()
+                    .is_some_and(|made_by| Arc::ptr_eq(&made_by.verifier, &origin.verifier))
+        })?;
+
+        let (_, sess) = tls13.remove(index)?;
+        let sess = sess.get();
+        match &sess.client.as_ref()?.value {
+            ClientSessionValue::Tls13(ticket) => ticket.take(),
+            ClientSessionValue::Tls12(_) => None,
+        }
+    }
+}
+
+/// A `ClientSessionStore` implementor that is bound to a single `SSL`.
+///
+/// This wraps each new TLS1.2 session or TLS1.3 ticket in an `SslSession`,
+/// tracks the most recent for `SSL_get_session`, and (once the connection has
+/// completed its properties) informs the application via the new session callback.
+#[derive(Debug)]
+pub struct SingleClientCache {
+    /// The store shared by all clients of an `SSL_CTX`.
+    parent: Arc<ClientSessionStorage>,
+
+    /// Holds the cache mode, context, timeout and callbacks, which OpenSSL shares
+    /// between the client and server sides of an `SSL_CTX`.
+    shared: Arc<ServerSessionStorage>,
+
+    origin: ClientSessionOrigin,
+    seeded: Option<Arc<NotThreadSafe<SslSession>>>,
+    random: &'static dyn SecureRandom,
+    most_recent_session: Mutex<Option<Arc<NotThreadSafe<SslSession>>>>,
+    /// Sessions awaiting `complete_new_sessions`.
+    pending: Mutex<Vec<Arc<NotThreadSafe<SslSession>>>>,
+}
+
+impl SingleClientCache {
+    pub fn get_most_recent_session(&self) -> Option<Arc<NotThreadSafe<SslSession>>> {
+        self.most_recent_session
+            .lock()
+            .ok()
+            .and_then(|inner| inner.clone())
+    }
+
+    pub fn borrow_most_recent_session(&self) -> *mut SSL_SESSION {
+        if let Ok(inner) = self.most_recent_session.lock() {
+            inner
+                .as_ref()
+                .map(|sess| Arc::as_ptr(sess) as *mut SSL_SESSION)
+                .unwrap_or_else(ptr::null_mut)
+        } else {
+            ptr::null_mut()
+        }
+    }
+
+    fn mode(&self) -> u32 {
+        self.shared.mode()
+    }
+
+    /// Fill in the properties of sessions made since the last call, and offer
+    /// them to the application.
+    ///
+    /// rustls does not expose the peer certificate or ALPN protocol of a session,
+    /// so `complete` takes these from the connection once rustls has stored it.
+    pub fn complete_new_sessions(&self, complete: impl Fn(&mut SslSession)) {
+        let pending = match self.pending.lock() {
+            Ok(mut pending) => mem::take(&mut *pending),
+            Err(_) => return,
+        };
+
+        for sess in pending {
+            complete(sess.get_mut());
+            if self.mode() & CACHE_MODE_CLIENT == CACHE_MODE_CLIENT {
+                self.shared.invoke_new_callback(sess);
+            }
+        }
+    }
+
+    /// Wrap `value` in a new `SslSession`, make it the most recent, and
+    /// hold it for `complete_new_sessions`.
+    fn new_session(
+        &self,
+        server_name: &ServerName<'_>,
+        value: ClientSessionValue,
+    ) -> Option<Arc<NotThreadSafe<SslSession>>> {
+        // divergence: openssl uses the TLS1.2 session id here, but rustls does not expose it
+        let mut id = vec![0u8; SSL_MAX_SSL_SESSION_ID_LENGTH];
+        if self.random.fill(&mut id).is_err() {
+            return None;
+        }
+
+        let client = ClientSessionData {
+            value,
+            origin: self.origin.clone(),
+        };
+        let mut properties = client.properties();
+        if let ServerName::DnsName(name) = server_name {
+            properties.hostname = CString::new(name.as_ref()).ok();
+        }
+
+        let sess = Arc::new(NotThreadSafe::new(SslSession::new_client(
+            id,
+            self.shared.get_context(),
+            TimeBase::now(),
+            self.shared.get_timeout(),
+            properties,
+            client,
+        )));
+
+        if let Ok(mut old) = self.most_recent_session.lock() {
+            *old = Some(sess.clone());
+        }
+
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.push(sess.clone());
+        }
+
+        Some(sess)
+    }
+
+    fn seeded_tls12(&self) -> Option<Tls12ClientSessionValue> {
+        let sess = self.seeded.as_ref()?.get();
+        match &sess.client.as_ref()?.value {
+            ClientSessionValue::Tls12(value) => Some(value.clone()),
+            ClientSessionValue::Tls13(_) => None,
+        }
+    }
+
+    fn take_seeded_tls13(&self) -> Option<Tls13ClientSessionValue> {
+        let sess = self.seeded.as_ref()?.get();
+        match &sess.client.as_ref()?.value {
+            ClientSessionValue::Tls13(ticket) => ticket.take(),
+            ClientSessionValue::Tls12(_) => None,
+        }
+    }
+}
+
+impl ClientSessionStore for SingleClientCache {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.parent.rustls.set_kx_hint(server_name, group);
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.parent.rustls.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: ServerName<'static>, value: Tls12ClientSessionValue) {
+        if self.mode() & CACHE_MODE_NO_INTERNAL_STORE == 0 {
+            self.parent
+                .rustls
+                .set_tls12_session(server_name.clone(), value.clone());
+        }
+        self.new_session(&server_name, ClientSessionValue::Tls12(value));
+    }
+
+    fn tls12_session(&self, server_name: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+        self.seeded_tls12()
+            .or_else(|| self.parent.rustls.tls12_session(server_name))
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.parent.rustls.remove_tls12_session(server_name);
+    }
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: Tls13ClientSessionValue,
+    ) {
+        let value = ClientSessionValue::Tls13(TicketSlot::new(value));
+        if let Some(sess) = self.new_session(&server_name, value) {
+            if self.mode() & CACHE_MODE_NO_INTERNAL_STORE == 0 {
+                self.parent.insert_tls13_session(server_name, sess);
+            }
+        }
+    }
+
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'static>,
+    ) -> Option<Tls13ClientSessionValue> {
+        self.take_seeded_tls13()
+            .or_else(|| self.parent.take_tls13_ticket(server_name, &self.origin))
+    }
+}
+
+const SSL_MAX_SSL_SESSION_ID_LENGTH: usize = 32;
+
+const CACHE_MODE_CLIENT: u32 = 0x01;
 const CACHE_MODE_SERVER: u32 = 0x02;
 const CACHE_MODE_NO_AUTO_CLEAR: u32 = 0x080;
 const CACHE_MODE_NO_INTERNAL_LOOKUP: u32 = 0x100;
@@ -1,19 +1,28 @@
 use core::cell::RefCell;
-use core::ffi::{c_int, c_uchar, c_void};
+use core::ffi::{c_char, c_int, c_uchar, c_void};
+use core::fmt::Write;
 use core::{ptr, slice};
-use std::sync::Arc;
+use std::ffi::CString;
+use std::sync::{Arc, OnceLock};
 
-use openssl_sys::{SSL_TLSEXT_ERR_NOACK, SSL_TLSEXT_ERR_OK};
-use rustls::AlertDescription;
+use openssl_sys::{
+    SSL_CLIENT_HELLO_RETRY, SSL_CLIENT_HELLO_SUCCESS, SSL_TLSEXT_ERR_NOACK, SSL_TLSEXT_ERR_OK,
+    X509_V_ERR_APPLICATION_VERIFICATION, X509_V_ERR_UNSPECIFIED, X509_V_OK,
+};
+use rustls::pki_types::CertificateDer;
+use rustls::{AlertDescription, KeyLog, KeyLogFile};
 
 use crate::entry::{
-    SSL_CTX_alpn_select_cb_func, SSL_CTX_cert_cb_func, SSL_CTX_new_session_cb,
-    SSL_CTX_servername_callback_func, SSL_CTX_sess_get_cb, SSL_CTX_sess_remove_cb,
-    _SSL_SESSION_free, SSL, SSL_CTX, SSL_SESSION,
+    _SSL_SESSION_free, SSL_CTX_alpn_select_cb_func, SSL_CTX_cert_cb_func,
+    SSL_CTX_cert_verify_cb_func, SSL_CTX_keylog_cb_func, SSL_CTX_msg_cb_func,
+    SSL_CTX_new_session_cb, SSL_CTX_servername_callback_func, SSL_CTX_sess_get_cb,
+    SSL_CTX_sess_remove_cb, SSL_allow_early_data_cb_fn, SSL_client_hello_cb_fn,
+    SSL_info_callback_func, SSL_verify_cb, SSL, SSL_CTX, SSL_SESSION,
 };
 use crate::error::Error;
 use crate::ffi;
 use crate::not_thread_safe::NotThreadSafe;
+use crate::x509::{OwnedX509Store, OwnedX509StoreCtx};
 
 /// Smuggling SSL* pointers from the outer entrypoint into the
 /// callback call site.
@@ -141,6 +150,84 @@ impl Default for CertCallbackConfig {
     }
 }
 
+/// Configuration needed to call [`SSL_allow_early_data_cb_fn`] later
+#[derive(Debug, Clone)]
+pub struct AllowEarlyDataCallbackConfig {
+    pub cb: SSL_allow_early_data_cb_fn,
+    pub context: *mut c_void,
+}
+
+impl AllowEarlyDataCallbackConfig {
+    /// Returns whether early data is allowed; it is when there is no callback.
+    pub fn invoke(&self) -> bool {
+        let callback = match self.cb {
+            Some(callback) => callback,
+            None => {
+                return true;
+            }
+        };
+        let ssl = SslCallbackContext::ssl_ptr();
+
+        let result = unsafe { callback(ssl, self.context) };
+        result != 0
+    }
+}
+
+impl Default for AllowEarlyDataCallbackConfig {
+    fn default() -> Self {
+        Self {
+            cb: None,
+            context: ptr::null_mut(),
+        }
+    }
+}
+
+/// Configuration needed to call [`SSL_client_hello_cb_fn`] later
+#[derive(Debug, Clone)]
+pub struct ClientHelloCallbackConfig {
+    pub cb: SSL_client_hello_cb_fn,
+    pub context: *mut c_void,
+}
+
+impl ClientHelloCallbackConfig {
+    /// Returns `Ok(false)` if the callback asked to be called again later.
+    pub fn invoke(&self) -> Result<bool, Error> {
+        let callback = match self.cb {
+            Some(callback) => callback,
+            None => {
+                return Ok(true);
+            }
+        };
+
+        let ssl = SslCallbackContext::ssl_ptr();
+
+        let internal_error = u8::from(AlertDescription::InternalError) as c_int;
+        let mut alert = internal_error;
+        let result = unsafe { callback(ssl, &mut alert as *mut c_int, self.context) };
+
+        if alert != internal_error {
+            log::trace!("NYI: customised alert during client hello callback");
+        }
+
+        match result {
+            SSL_CLIENT_HELLO_SUCCESS => Ok(true),
+            SSL_CLIENT_HELLO_RETRY => Ok(false),
+            _ => Err(Error::not_supported(
+                "SSL_client_hello_cb_fn returned error",
+            )),
+        }
+    }
+}
+
+impl Default for ClientHelloCallbackConfig {
+    fn default() -> Self {
+        Self {
+            cb: None,
+            context: ptr::null_mut(),
+        }
+    }
+}
+
 /// Configuration needed to call [`invoke_servername_callback`] later
 #[derive(Debug, Clone)]
 pub struct ServerNameCallbackConfig {
@@ -185,6 +272,155 @@ impl Default for ServerNameCallbackConfig {
     }
 }
 
+/// Configuration needed to call [`MsgCallbackConfig::invoke`] later
+#[derive(Debug, Clone)]
+pub struct MsgCallbackConfig {
+    pub cb: SSL_CTX_msg_cb_func,
+    pub context: *mut c_void,
+}
+
+impl MsgCallbackConfig {
+    /// Call a `SSL_CTX_msg_cb_func` callback, if there is one.
+    pub fn invoke(&self, write_p: bool, version: c_int, content_type: c_int, buf: &[u8]) {
+        let callback = match self.cb {
+            Some(callback) => callback,
+            None => {
+                return;
+            }
+        };
+
+        unsafe {
+            callback(
+                write_p as c_int,
+                version,
+                content_type,
+                buf.as_ptr() as *const c_void,
+                buf.len(),
+                SslCallbackContext::ssl_ptr(),
+                self.context,
+            )
+        };
+    }
+}
+
+impl Default for MsgCallbackConfig {
+    fn default() -> Self {
+        Self {
+            cb: None,
+            context: ptr::null_mut(),
+        }
+    }
+}
+
+/// Configuration needed to call [`CertVerifyCallbackConfig::invoke`] later
+#[derive(Debug, Clone)]
+pub struct CertVerifyCallbackConfig {
+    pub cb: SSL_CTX_cert_verify_cb_func,
+    pub context: *mut c_void,
+}
+
+impl CertVerifyCallbackConfig {
+    /// Call a `SSL_CTX_set_cert_verify_callback` callback, in place of
+    /// our own verification of `chain`.
+    ///
+    /// `chain` starts with the end-entity certificate.  `verify_callback` is
+    /// installed in the `X509_STORE_CTX`, for use by `X509_verify_cert`.
+    ///
+    /// Returns `None` if there is no callback.  Otherwise, returns the
+    /// `X509_V_*` result and whether the callback accepted the chain.
+    pub fn invoke(
+        &self,
+        chain: &[CertificateDer<'_>],
+        store: &OwnedX509Store,
+        verify_callback: SSL_verify_cb,
+    ) -> Option<(c_int, bool)> {
+        let callback = self.cb?;
+
+        let store_ctx = match new_store_ctx(chain, store, verify_callback) {
+            Ok(store_ctx) => store_ctx,
+            Err(err) => {
+                log::warn!("cannot construct X509_STORE_CTX for verify callback: {err:?}");
+                return Some((X509_V_ERR_UNSPECIFIED, false));
+            }
+        };
+
+        let result = unsafe { callback(store_ctx.pointer(), self.context) };
+
+        // a rejection without a specific error must still be reported as a
+        // failure by `SSL_get_verify_result`.
+        match (result > 0, store_ctx.error()) {
+            (false, X509_V_OK) => Some((X509_V_ERR_APPLICATION_VERIFICATION, false)),
+            (accepted, error) => Some((error, accepted)),
+        }
+    }
+}
+
+impl Default for CertVerifyCallbackConfig {
+    fn default() -> Self {
+        Self {
+            cb: None,
+            context: ptr::null_mut(),
+        }
+    }
+}
+
+// `context` is not Send or Sync, but we don't dereference it: it is only
+// passed back to the callback.
+unsafe impl Send for CertVerifyCallbackConfig {}
+unsafe impl Sync for CertVerifyCallbackConfig {}
+
+/// Make the rustls [`KeyLog`] for a connection.
+///
+/// This calls `callback` if set by `SSL_CTX_set_keylog_callback`, and
+/// otherwise writes to the file named by the `SSLKEYLOGFILE` environment
+/// variable (if any).  That file is opened once per process.
+pub fn key_log(callback: SSL_CTX_keylog_cb_func) -> Arc<dyn KeyLog> {
+    static KEY_LOG_FILE: OnceLock<Arc<KeyLogFile>> = OnceLock::new();
+
+    match callback {
+        Some(callback) => Arc::new(KeyLogCallback(callback)),
+        None => KEY_LOG_FILE
+            .get_or_init(|| Arc::new(KeyLogFile::new()))
+            .clone(),
+    }
+}
+
+/// Calls a `SSL_CTX_keylog_cb_func` with lines in NSS key log format.
+#[derive(Debug)]
+struct KeyLogCallback(unsafe extern "C" fn(ssl: *const SSL, line: *const c_char));
+
+impl KeyLog for KeyLogCallback {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let mut line =
+            String::with_capacity(label.len() + 2 + 2 * (client_random.len() + secret.len()));
+        line.push_str(label);
+        line.push(' ');
+        for byte in client_random {
+            let _ = write!(line, "{byte:02x}");
+        }
+        line.push(' ');
+        for byte in secret {
+            let _ = write!(line, "{byte:02x}");
+        }
+
+        let Ok(line) = CString::new(line) else {
+            return;
+        };
+
+        unsafe { (self.0)(SslCallbackContext::ssl_ptr(), line.as_ptr()) };
+    }
+}
+
+/// Call an `SSL_info_callback_func` callback, if there is one.
+///
+/// `where_` is a combination of `SSL_CB_*` flags; the meaning of `ret`
+/// depends on that.
+pub fn invoke_info_callback(callback: SSL_info_callback_func, where_: c_int, ret: c_int) {
+    if let Some(callback) = callback {
+        unsafe { callback(SslCallbackContext::ssl_ptr(), where_, ret) };
+    }
+}
+
 /// Returns true if a callback was actually called.
 ///
 /// It is unknowable if this means something was stored externally.
@@ -257,3 +493,81 @@ pub fn invoke_session_remove_callback(
 
     _SSL_SESSION_free(sess_ptr);
 }
+
+/// Call a `SSL_verify_cb` callback over the peer's certificate chain.
+///
+/// `chain` starts with the end-entity certificate. `openssl_rv` is the
+/// `X509_V_*` result of our own verification of that chain, and
+/// `error_depth` is the index into `chain` of the certificate it is about.
+///
+/// This approximates the order of calls made by OpenSSL: first for the
+/// failing certificate (if any) with `preverify_ok` of zero, then for
+/// every certificate from the top of the chain down to depth 0.
+///
+/// Returns the final `X509_V_*` result, and whether the callback accepted
+/// the chain.  If there is no callback, the chain is accepted only if
+/// `openssl_rv` is `X509_V_OK`.
+pub fn invoke_verify_callback(
+    callback: SSL_verify_cb,
+    chain: &[CertificateDer<'_>],
+    store: &OwnedX509Store,
+    openssl_rv: c_int,
+    error_depth: usize,
+) -> (c_int, bool) {
+    let callback = match callback {
+        Some(callback) => callback,
+        None => {
+            return (openssl_rv, openssl_rv == X509_V_OK);
+        }
+    };
+
+    let mut store_ctx = match new_store_ctx(chain, store, None) {
+        Ok(store_ctx) => store_ctx,
+        Err(err) => {
+            log::warn!("cannot construct X509_STORE_CTX for verify callback: {err:?}");
+            return (openssl_rv, false);
+        }
+    };
+    store_ctx.set_verified_chain();
+
+    let top = chain.len() - 1;
+
+    // like OpenSSL, a rejection without a specific error is unspecified.
+    let rejected = |store_ctx: &OwnedX509StoreCtx| match store_ctx.error() {
+        X509_V_OK => (X509_V_ERR_UNSPECIFIED, false),
+        error => (error, false),
+    };
+
+    if openssl_rv != X509_V_OK {
+        store_ctx.set_error(openssl_rv);
+        store_ctx.set_current(error_depth);
+        if unsafe { callback(0, store_ctx.pointer()) } == 0 {
+            return rejected(&store_ctx);
+        }
+    }
+
+    for depth in (0..=top).rev() {
+        store_ctx.set_current(depth);
+        if unsafe { callback(1, store_ctx.pointer()) } == 0 {
+            return rejected(&store_ctx);
+        }
+    }
+
+    (store_ctx.error(), true)
+}
+
+/// Make an `X509_STORE_CTX` for `chain`, which can find the current `SSL*`
+/// via `SSL_get_ex_data_X509_STORE_CTX_idx`.
+fn new_store_ctx(
+    chain: &[CertificateDer<'_>],
+    store: &OwnedX509Store,
+    verify_callback: SSL_verify_cb,
+) -> Result<OwnedX509StoreCtx, Error> {
+    let mut store_ctx = OwnedX509StoreCtx::new(chain, store)?;
+    store_ctx.set_ex_data(
+        crate::ex_data::ssl_x509_store_ctx_idx(),
+        SslCallbackContext::ssl_ptr() as *mut c_void,
+    );
+    store_ctx.set_verify_callback(verify_callback);
+    Ok(store_ctx)
+}
@@ -0,0 +1,419 @@
+//! Parsing of OpenSSL-style TLS1.2 cipher lists, and TLS1.3 ciphersuite lists.
+//!
+//! See `man 1 openssl-ciphers` for the format.  This emulates the
+//! behaviour of OpenSSL's `ssl_create_cipher_list()`, but only over the
+//! ciphersuites we support.
+
+use core::ffi::c_void;
+
+use openssl_sys::{
+    stack_st_SSL_CIPHER, OPENSSL_sk_free, OPENSSL_sk_new_null, OPENSSL_sk_push, OPENSSL_STACK,
+};
+
+use crate::error::Error;
+use crate::SslCipher;
+
+// Attributes of a cipher, used for matching against aliases.
+pub const TLS12: u32 = 1 << 0;
+pub const KX_ECDHE: u32 = 1 << 1;
+pub const AUTH_RSA: u32 = 1 << 2;
+pub const AUTH_ECDSA: u32 = 1 << 3;
+pub const ENC_AES128GCM: u32 = 1 << 4;
+pub const ENC_AES256GCM: u32 = 1 << 5;
+pub const ENC_CHACHA20POLY1305: u32 = 1 << 6;
+pub const FIPS: u32 = 1 << 7;
+
+/// Aliases, and the attributes they select.  A cipher matches an alias
+/// if it has any of the alias's attributes.
+///
+/// Aliases absent from here that OpenSSL knows (eg `aNULL`, `MD5`, `SHA256`)
+/// select none of our ciphers, so they can be treated like unknown words.
+static ALIASES: &[(&str, u32)] = &[
+    ("ALL", TLS12),
+    ("DEFAULT", TLS12),
+    ("HIGH", TLS12),
+    ("TLSv1.2", TLS12),
+    ("FIPS", FIPS),
+    ("ECDH", KX_ECDHE),
+    ("ECDHE", KX_ECDHE),
+    ("EECDH", KX_ECDHE),
+    ("kECDHE", KX_ECDHE),
+    ("kEECDH", KX_ECDHE),
+    ("aRSA", AUTH_RSA),
+    ("aECDSA", AUTH_ECDSA),
+    ("ECDSA", AUTH_ECDSA),
+    ("AES", ENC_AES128GCM | ENC_AES256GCM),
+    ("AESGCM", ENC_AES128GCM | ENC_AES256GCM),
+    ("AES128", ENC_AES128GCM),
+    ("AES256", ENC_AES256GCM),
+    ("CHACHA20", ENC_CHACHA20POLY1305),
+    // not known to OpenSSL, but all our ciphers are AEADs.
+    ("AEAD", ENC_AES128GCM | ENC_AES256GCM | ENC_CHACHA20POLY1305),
+];
+
+/// Parse `rule_str` into an ordered list of TLS1.2 ciphers.
+///
+/// `all` is the list of candidate ciphers, in default preference order.
+///
+/// Fails if `rule_str` is malformed, or if it selects no ciphers.
+pub fn parse(rule_str: &str, all: &[&'static SslCipher]) -> Result<Vec<&'static SslCipher>, Error> {
+    let mut list = all
+        .iter()
+        .map(|cipher| Entry {
+            cipher,
+            active: false,
+        })
+        .collect::<Vec<_>>();
+
+    for element in rule_str
+        .split([':', ' ', ';', ','])
+        .filter(|e| !e.is_empty())
+    {
+        let (op, rest) = match element.as_bytes()[0] {
+            b'!' => (Op::Kill, &element[1..]),
+            b'-' => (Op::Delete, &element[1..]),
+            b'+' => (Op::Order, &element[1..]),
+            _ => (Op::Add, element),
+        };
+
+        if let Some(command) = rest.strip_prefix('@') {
+            apply_command(&mut list, command)?;
+            continue;
+        }
+
+        let words = rest.split('+').collect::<Vec<_>>();
+        if words.iter().any(|w| !valid_word(w)) {
+            return Err(Error::invalid_command(element));
+        }
+
+        apply(&mut list, op, |cipher| {
+            words.iter().all(|word| word_matches(word, cipher))
+        });
+    }
+
+    let result = list
+        .into_iter()
+        .filter(|entry| entry.active)
+        .map(|entry| entry.cipher)
+        .collect::<Vec<_>>();
+
+    match result.is_empty() {
+        true => Err(Error::no_cipher_match()),
+        false => Ok(result),
+    }
+}
+
+/// Parse `names` into an ordered list of TLS1.3 ciphersuites.
+///
+/// This is a colon-separated list of ciphersuite names.  Unknown names
+/// are ignored; the empty string means no TLS1.3 ciphersuites.
+///
+/// Fails if `names` is non-empty, but contains no known names.
+pub fn parse_ciphersuites(
+    names: &str,
+    all: &[&'static SslCipher],
+) -> Result<Vec<&'static SslCipher>, Error> {
+    let mut result: Vec<&'static SslCipher> = vec![];
+
+    for name in names.split(':') {
+        if let Some(cipher) = all
+            .iter()
+            .find(|cipher| cipher.standard_name.to_bytes() == name.as_bytes())
+        {
+            if !result
+                .iter()
+                .any(|c| c.protocol_id() == cipher.protocol_id())
+            {
+                result.push(cipher);
+            }
+        }
+    }
+
+    match result.is_empty() && !names.is_empty() {
+        true => Err(Error::no_cipher_match()),
+        false => Ok(result),
+    }
+}
+
+/// Owning wrapper around an OpenSSL `STACK_OF(SSL_CIPHER)` object.
+///
+/// The items are static, so are not owned by the stack.
+pub struct OwnedCipherStack {
+    raw: *mut stack_st_SSL_CIPHER,
+}
+
+impl OwnedCipherStack {
+    /// Make a stack of the TLS1.3 then TLS1.2 ciphers, like `SSL_get_ciphers`.
+    pub fn new(tls13: &[&'static SslCipher], tls12: &[&'static SslCipher]) -> Self {
+        let raw = unsafe { OPENSSL_sk_new_null() as *mut stack_st_SSL_CIPHER };
+        for cipher in tls13.iter().chain(tls12) {
+            unsafe {
+                OPENSSL_sk_push(
+                    raw as *mut OPENSSL_STACK,
+                    *cipher as *const SslCipher as *const c_void,
+                );
+            }
+        }
+        Self { raw }
+    }
+
+    /// Leaks our pointer to the caller.
+    ///
+    /// We retain ownership.
+    pub fn pointer(&self) -> *mut stack_st_SSL_CIPHER {
+        self.raw
+    }
+}
+
+impl Drop for OwnedCipherStack {
+    fn drop(&mut self) {
+        unsafe { OPENSSL_sk_free(self.raw as *mut OPENSSL_STACK) };
+    }
+}
+
+struct Entry {
+    cipher: &'static SslCipher,
+    active: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    /// Append matching inactive ciphers to the end of the list.
+    Add,
+    /// Move matching active ciphers to the end of the list.
+    Order,
+    /// Deactivate matching ciphers, and move them to the start of the list.
+    ///
+    /// They may be added again later.
+    Delete,
+    /// Remove matching ciphers, permanently.
+    Kill,
+}
+
+fn apply(list: &mut Vec<Entry>, op: Op, matches: impl Fn(&SslCipher) -> bool) {
+    // nb. partitioning preserves the relative order of each part
+    let (selected, others): (Vec<_>, Vec<_>) = list.drain(..).partition(|entry| {
+        matches(entry.cipher)
+            && match op {
+                Op::Add => !entry.active,
+                Op::Order | Op::Delete => entry.active,
+                Op::Kill => true,
+            }
+    });
+
+    match op {
+        Op::Add | Op::Order => {
+            list.extend(others);
+            list.extend(selected.into_iter().map(|entry| Entry {
+                active: true,
+                ..entry
+            }));
+        }
+        Op::Delete => {
+            list.extend(selected.into_iter().map(|entry| Entry {
+                active: false,
+                ..entry
+            }));
+            list.extend(others);
+        }
+        Op::Kill => {
+            list.extend(others);
+        }
+    }
+}
+
+fn apply_command(list: &mut Vec<Entry>, command: &str) -> Result<(), Error> {
+    match command {
+        "STRENGTH" => {
+            let mut bits = list
+                .iter()
+                .filter(|entry| entry.active)
+                .map(|entry| entry.cipher.bits)
+                .collect::<Vec<_>>();
+            bits.sort_unstable_by(|a, b| b.cmp(a));
+            bits.dedup();
+
+            for b in bits {
+                apply(list, Op::Order, |cipher| cipher.bits == b);
+            }
+            Ok(())
+        }
+        // We have no security levels: all our ciphers are acceptable at
+        // any level, so this is accepted and otherwise ignored.
+        level
+            if level
+                .strip_prefix("SECLEVEL=")
+                .is_some_and(|n| n.len() == 1 && n.as_bytes()[0].is_ascii_digit()) =>
+        {
+            Ok(())
+        }
+        _ => Err(Error::invalid_command(command)),
+    }
+}
+
+fn valid_word(word: &str) -> bool {
+    !word.is_empty()
+        && word
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'='))
+}
+
+fn word_matches(word: &str, cipher: &SslCipher) -> bool {
+    if cipher.openssl_name.to_bytes() == word.as_bytes()
+        || cipher.standard_name.to_bytes() == word.as_bytes()
+    {
+        return true;
+    }
+
+    ALIASES
+        .iter()
+        .find(|(name, _)| *name == word)
+        .is_some_and(|(_, attrs)| cipher.attrs & attrs != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TLS12_CIPHERS, TLS13_CIPHERS};
+
+    fn names(rule_str: &str) -> Option<Vec<&'static str>> {
+        parse(rule_str, TLS12_CIPHERS).ok().map(|list| {
+            list.iter()
+                .map(|c| c.openssl_name.to_str().unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn default_order() {
+        let expect = vec![
+            "ECDHE-ECDSA-AES256-GCM-SHA384",
+            "ECDHE-RSA-AES256-GCM-SHA384",
+            "ECDHE-ECDSA-CHACHA20-POLY1305",
+            "ECDHE-RSA-CHACHA20-POLY1305",
+            "ECDHE-ECDSA-AES128-GCM-SHA256",
+            "ECDHE-RSA-AES128-GCM-SHA256",
+        ];
+        assert_eq!(names("DEFAULT").unwrap(), expect);
+        assert_eq!(names("ALL").unwrap(), expect);
+        assert_eq!(names("HIGH:!aNULL:!MD5").unwrap(), expect);
+        assert_eq!(names("ECDHE:@SECLEVEL=2").unwrap(), expect);
+        assert_eq!(names("AEAD").unwrap(), expect);
+    }
+
+    #[test]
+    fn operators() {
+        let chacha_first = vec![
+            "ECDHE-ECDSA-CHACHA20-POLY1305",
+            "ECDHE-RSA-CHACHA20-POLY1305",
+            "ECDHE-ECDSA-AES256-GCM-SHA384",
+            "ECDHE-RSA-AES256-GCM-SHA384",
+            "ECDHE-ECDSA-AES128-GCM-SHA256",
+            "ECDHE-RSA-AES128-GCM-SHA256",
+        ];
+        assert_eq!(names("ECDHE:-AESGCM:AESGCM").unwrap(), chacha_first);
+        assert_eq!(names("ECDHE:+AESGCM").unwrap(), chacha_first);
+        assert_eq!(
+            names("ECDHE+AESGCM:ECDHE+CHACHA20").unwrap(),
+            vec![
+                "ECDHE-ECDSA-AES256-GCM-SHA384",
+                "ECDHE-RSA-AES256-GCM-SHA384",
+                "ECDHE-ECDSA-AES128-GCM-SHA256",
+                "ECDHE-RSA-AES128-GCM-SHA256",
+                "ECDHE-ECDSA-CHACHA20-POLY1305",
+                "ECDHE-RSA-CHACHA20-POLY1305",
+            ]
+        );
+        assert_eq!(
+            names("ECDHE:!ECDSA").unwrap(),
+            vec![
+                "ECDHE-RSA-AES256-GCM-SHA384",
+                "ECDHE-RSA-CHACHA20-POLY1305",
+                "ECDHE-RSA-AES128-GCM-SHA256",
+            ]
+        );
+        assert_eq!(
+            names("AES256+ECDSA").unwrap(),
+            vec!["ECDHE-ECDSA-AES256-GCM-SHA384"]
+        );
+    }
+
+    #[test]
+    fn strength() {
+        assert_eq!(
+            names("AES128:CHACHA20:AES256:@STRENGTH").unwrap(),
+            vec![
+                "ECDHE-ECDSA-CHACHA20-POLY1305",
+                "ECDHE-RSA-CHACHA20-POLY1305",
+                "ECDHE-ECDSA-AES256-GCM-SHA384",
+                "ECDHE-RSA-AES256-GCM-SHA384",
+                "ECDHE-ECDSA-AES128-GCM-SHA256",
+                "ECDHE-RSA-AES128-GCM-SHA256",
+            ]
+        );
+    }
+
+    #[test]
+    fn names_and_unknown_words() {
+        assert_eq!(
+            names("FOO:ECDHE-RSA-CHACHA20-POLY1305").unwrap(),
+            vec!["ECDHE-RSA-CHACHA20-POLY1305"]
+        );
+        assert_eq!(
+            names("TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256").unwrap(),
+            vec!["ECDHE-RSA-AES128-GCM-SHA256"]
+        );
+        assert_eq!(
+            names("ECDHE-RSA-AES128-GCM-SHA256+aRSA").unwrap(),
+            vec!["ECDHE-RSA-AES128-GCM-SHA256"]
+        );
+        assert_eq!(
+            names("ECDHE+FOO,AES128 CHACHA20").unwrap(),
+            vec![
+                "ECDHE-ECDSA-AES128-GCM-SHA256",
+                "ECDHE-RSA-AES128-GCM-SHA256",
+                "ECDHE-ECDSA-CHACHA20-POLY1305",
+                "ECDHE-RSA-CHACHA20-POLY1305",
+            ]
+        );
+    }
+
+    #[test]
+    fn failures() {
+        assert_eq!(names(""), None);
+        assert_eq!(names("SHA256"), None);
+        assert_eq!(names("AES128-SHA"), None);
+        assert_eq!(names("aRSA+ECDSA"), None);
+        assert_eq!(names("TLS_AES_128_GCM_SHA256"), None);
+        assert_eq!(names("ECDHE:@FOO"), None);
+        assert_eq!(names("ECDHE:!"), None);
+        assert_eq!(names("ECDHE:AES$"), None);
+    }
+
+    fn suite_names(names: &str) -> Option<Vec<&'static str>> {
+        parse_ciphersuites(names, TLS13_CIPHERS).ok().map(|list| {
+            list.iter()
+                .map(|c| c.standard_name.to_str().unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn ciphersuites() {
+        assert_eq!(suite_names("").unwrap(), Vec::<&str>::new());
+        assert_eq!(
+            suite_names("TLS_CHACHA20_POLY1305_SHA256:TLS_AES_128_GCM_SHA256").unwrap(),
+            vec!["TLS_CHACHA20_POLY1305_SHA256", "TLS_AES_128_GCM_SHA256"]
+        );
+        assert_eq!(
+            suite_names("FOO:TLS_AES_128_GCM_SHA256:TLS_AES_128_GCM_SHA256").unwrap(),
+            vec!["TLS_AES_128_GCM_SHA256"]
+        );
+        assert_eq!(suite_names("FOO"), None);
+        assert_eq!(suite_names("ECDHE-RSA-AES128-GCM-SHA256"), None);
+        assert_eq!(
+            suite_names("TLS_AES_128_GCM_SHA256,TLS_AES_256_GCM_SHA384"),
+            None
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Parsing of the raw `ClientHello` message, for `SSL_client_hello_get0_*`.
+//!
+//! rustls only exposes a processed subset of the `ClientHello`, but these
+//! APIs offer its fields as they appeared on the wire.
+
+/// The fields of a `ClientHello` message, undecoded.
+#[derive(Debug, Default, PartialEq)]
+pub struct ClientHello {
+    pub legacy_version: u16,
+    pub random: Vec<u8>,
+    pub session_id: Vec<u8>,
+    /// Big-endian 16-bit cipher suite identifiers.
+    pub cipher_suites: Vec<u8>,
+    pub compression_methods: Vec<u8>,
+    /// Extension types and their bodies, in the order received.
+    pub extensions: Vec<(u16, Vec<u8>)>,
+}
+
+impl ClientHello {
+    /// Parse a complete handshake message, including its header.
+    ///
+    /// Returns `None` if `message` is not a well-formed `ClientHello`.
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let mut r = Reader(message);
+        if r.u8()? != HANDSHAKE_TYPE_CLIENT_HELLO {
+            return None;
+        }
+        let mut body = Reader(r.vec(3)?);
+
+        let mut ret = Self {
+            legacy_version: u16::from_be_bytes(body.take(2)?.try_into().ok()?),
+            random: body.take(32)?.to_vec(),
+            session_id: body.vec(1)?.to_vec(),
+            cipher_suites: body.vec(2)?.to_vec(),
+            compression_methods: body.vec(1)?.to_vec(),
+            extensions: vec![],
+        };
+
+        // the extensions block is absent in some older `ClientHello`s.
+        if body.0.is_empty() {
+            return Some(ret);
+        }
+
+        let mut extensions = Reader(body.vec(2)?);
+        while !extensions.0.is_empty() {
+            let typ = u16::from_be_bytes(extensions.take(2)?.try_into().ok()?);
+            let data = extensions.vec(2)?;
+            ret.extensions.push((typ, data.to_vec()));
+        }
+
+        match body.0.is_empty() {
+            true => Some(ret),
+            false => None,
+        }
+    }
+
+    /// Whether the client offered `version` in its `supported_versions` extension.
+    pub fn offers_version(&self, version: u16) -> bool {
+        let Some(body) = self.extension(EXTENSION_TYPE_SUPPORTED_VERSIONS) else {
+            return false;
+        };
+        let mut body = Reader(body);
+        let Some(versions) = body.vec(1) else {
+            return false;
+        };
+        versions
+            .chunks_exact(2)
+            .any(|v| u16::from_be_bytes([v[0], v[1]]) == version)
+    }
+
+    /// Whether the client offered early data, with an `early_data` extension.
+    pub fn offers_early_data(&self) -> bool {
+        self.extension(EXTENSION_TYPE_EARLY_DATA).is_some()
+    }
+
+    /// Return the body of the first extension of type `typ`, if present.
+    pub fn extension(&self, typ: u16) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|(t, _)| *t == typ)
+            .map(|(_, data)| data.as_slice())
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    /// Take a vector prefixed with a big-endian length of `len_bytes`.
+    fn vec(&mut self, len_bytes: usize) -> Option<&'a [u8]> {
+        let len = self
+            .take(len_bytes)?
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        self.take(len)
+    }
+}
+
+const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 1;
+const EXTENSION_TYPE_EARLY_DATA: u16 = 42;
+const EXTENSION_TYPE_SUPPORTED_VERSIONS: u16 = 43;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0xaa; 32]);
+        body.extend_from_slice(&[2, 0x01, 0x02]);
+        body.extend_from_slice(&[0, 4, 0x13, 0x01, 0xc0, 0x2b]);
+        body.extend_from_slice(&[1, 0]);
+        body.extend_from_slice(&[0, 9, 0, 0x17, 0, 0, 0xff, 0x01, 0, 1, 0]);
+        let mut message = vec![1, 0, 0, body.len() as u8];
+        message.extend_from_slice(&body);
+
+        let hello = ClientHello::parse(&message).unwrap();
+        assert_eq!(hello.legacy_version, 0x0303);
+        assert_eq!(hello.random, vec![0xaa; 32]);
+        assert_eq!(hello.session_id, vec![0x01, 0x02]);
+        assert_eq!(hello.cipher_suites, vec![0x13, 0x01, 0xc0, 0x2b]);
+        assert_eq!(hello.compression_methods, vec![0]);
+        assert_eq!(hello.extensions, vec![(0x17, vec![]), (0xff01, vec![0])]);
+        assert_eq!(hello.extension(0xff01), Some(&[0][..]));
+        assert_eq!(hello.extension(0x10), None);
+        assert!(!hello.offers_version(0x0304));
+
+        // truncated, or not a `ClientHello`
+        assert_eq!(ClientHello::parse(&message[..message.len() - 1]), None);
+        message[0] = 2;
+        assert_eq!(ClientHello::parse(&message), None);
+    }
+}
@@ -190,12 +190,10 @@ impl SslConfigCtx {
                 ctx.get_mut().stage_certificate_chain(cert_chain);
                 ActionResult::Applied
             }
-            State::ApplyingToSsl(_) => {
-                // the "Certificate" command after `SSL_CONF_CTX_set_ssl` is documented as using
-                // `SSL_use_certificate_file` - this is NYI for rustls-libssl.
-                return Err(Error::not_supported(
-                    "Certificate with SSL structure not supported",
-                ));
+            State::ApplyingToSsl(ssl) => {
+                // like OpenSSL, we use `SSL_use_certificate_chain_file` here.
+                ssl.get_mut().stage_certificate_chain(cert_chain);
+                ActionResult::Applied
             }
         })
     }
@@ -299,6 +297,90 @@ impl SslConfigCtx {
         Ok(ActionResult::Applied)
     }
 
+    fn cipher_string(&mut self, rule_str: Option<&str>) -> Result<ActionResult, Error> {
+        let rule_str = match rule_str {
+            Some(rule_str) => rule_str,
+            None => return Ok(ActionResult::ValueRequired),
+        };
+
+        match &self.state {
+            State::Validating => {
+                crate::cipher_list::parse(rule_str, crate::TLS12_CIPHERS)?;
+            }
+            State::ApplyingToCtx(ctx) => ctx.get_mut().set_cipher_list(rule_str)?,
+            State::ApplyingToSsl(ssl) => ssl.get_mut().set_cipher_list(rule_str)?,
+        };
+
+        Ok(ActionResult::Applied)
+    }
+
+    fn ciphersuites(&mut self, names: Option<&str>) -> Result<ActionResult, Error> {
+        let names = match names {
+            Some(names) => names,
+            None => return Ok(ActionResult::ValueRequired),
+        };
+
+        match &self.state {
+            State::Validating => {
+                crate::cipher_list::parse_ciphersuites(names, crate::TLS13_CIPHERS)?;
+            }
+            State::ApplyingToCtx(ctx) => ctx.get_mut().set_ciphersuites(names)?,
+            State::ApplyingToSsl(ssl) => ssl.get_mut().set_ciphersuites(names)?,
+        };
+
+        Ok(ActionResult::Applied)
+    }
+
+    fn groups(&mut self, list: Option<&str>) -> Result<ActionResult, Error> {
+        let list = match list {
+            Some(list) => list,
+            None => return Ok(ActionResult::ValueRequired),
+        };
+        let groups = crate::parse_groups_list(list)?;
+
+        match &self.state {
+            State::Validating => {
+                crate::select_kx_groups(&groups)?;
+            }
+            State::ApplyingToCtx(ctx) => ctx.get_mut().set_groups(&groups)?,
+            State::ApplyingToSsl(ssl) => ssl.get_mut().set_groups(&groups)?,
+        };
+
+        Ok(ActionResult::Applied)
+    }
+
+    fn signature_algorithms(&mut self, list: Option<&str>) -> Result<ActionResult, Error> {
+        let list = match list {
+            Some(list) => list,
+            None => return Ok(ActionResult::ValueRequired),
+        };
+        let sigalgs = crate::parse_sigalgs_list(list)?;
+
+        match &self.state {
+            State::Validating => {}
+            State::ApplyingToCtx(ctx) => ctx.get_mut().set_sigalgs(sigalgs),
+            State::ApplyingToSsl(ssl) => ssl.get_mut().set_sigalgs(sigalgs),
+        };
+
+        Ok(ActionResult::Applied)
+    }
+
+    fn client_signature_algorithms(&mut self, list: Option<&str>) -> Result<ActionResult, Error> {
+        let list = match list {
+            Some(list) => list,
+            None => return Ok(ActionResult::ValueRequired),
+        };
+        let sigalgs = crate::parse_sigalgs_list(list)?;
+
+        match &self.state {
+            State::Validating => {}
+            State::ApplyingToCtx(ctx) => ctx.get_mut().set_client_sigalgs(sigalgs),
+            State::ApplyingToSsl(ssl) => ssl.get_mut().set_client_sigalgs(sigalgs),
+        };
+
+        Ok(ActionResult::Applied)
+    }
+
     fn session_ticket_option(&mut self, flag: OptionFlag) -> Result<(), Error> {
         if !self.flags.is_server() {
             return Err(Error::bad_data(
@@ -592,12 +674,51 @@ const SUPPORTED_COMMANDS: &[Command] = &[
         value_type: ValueType::String,
         action: SslConfigCtx::options,
     },
+    Command {
+        name_file: Some("CipherString"),
+        name_cmdline: Some("cipher"),
+        flags: Flags(Flags::ANY),
+        value_type: ValueType::String,
+        action: SslConfigCtx::cipher_string,
+    },
+    Command {
+        name_file: Some("Ciphersuites"),
+        name_cmdline: Some("ciphersuites"),
+        flags: Flags(Flags::ANY),
+        value_type: ValueType::String,
+        action: SslConfigCtx::ciphersuites,
+    },
+    Command {
+        name_file: Some("Groups"),
+        name_cmdline: Some("groups"),
+        flags: Flags(Flags::ANY),
+        value_type: ValueType::String,
+        action: SslConfigCtx::groups,
+    },
+    Command {
+        name_file: Some("Curves"),
+        name_cmdline: Some("curves"),
+        flags: Flags(Flags::ANY),
+        value_type: ValueType::String,
+        action: SslConfigCtx::groups,
+    },
+    Command {
+        name_file: Some("SignatureAlgorithms"),
+        name_cmdline: Some("sigalgs"),
+        flags: Flags(Flags::ANY),
+        value_type: ValueType::String,
+        action: SslConfigCtx::signature_algorithms,
+    },
+    Command {
+        name_file: Some("ClientSignatureAlgorithms"),
+        name_cmdline: Some("client_sigalgs"),
+        flags: Flags(Flags::ANY),
+        value_type: ValueType::String,
+        action: SslConfigCtx::client_signature_algorithms,
+    },
     // Some commands that would be reasonable to implement in the future:
     //  - ClientCAFile/ClientCAPath
     //  - Options
     //    - CANames (?)
-    //  - Groups/-groups
-    //  - SignatureAlgorithms/-sigalgs
     //  - RequestCAFile
-    //  - Ciphersuites/-ciphersuites
 ];
@@ -1,7 +1,10 @@
 use core::ffi::{c_int, CStr};
 use openssl_sys::{
-    NID_X9_62_prime256v1, NID_rsaEncryption, NID_rsassaPss, NID_secp384r1, NID_secp521r1,
-    NID_ED25519, NID_ED448, NID_X25519, NID_X448,
+    NID_X9_62_id_ecPublicKey, NID_X9_62_prime256v1, NID_ecdsa_with_SHA1, NID_ecdsa_with_SHA256,
+    NID_ecdsa_with_SHA384, NID_ecdsa_with_SHA512, NID_rsaEncryption, NID_rsassaPss, NID_secp384r1,
+    NID_secp521r1, NID_sha1, NID_sha1WithRSAEncryption, NID_sha256, NID_sha256WithRSAEncryption,
+    NID_sha384, NID_sha384WithRSAEncryption, NID_sha512, NID_sha512WithRSAEncryption, NID_ED25519,
+    NID_ED448, NID_X25519, NID_X448,
 };
 
 use rustls::{AlertDescription, NamedGroup, SignatureScheme};
@@ -88,21 +91,99 @@ pub fn alert_desc_to_short_string(value: c_int) -> &'static CStr {
     }
 }
 
+/// The signature algorithm NID for `scheme`, like `SSL_get_signature_type_nid`.
 pub fn sig_scheme_to_nid(scheme: SignatureScheme) -> Option<c_int> {
     use SignatureScheme::*;
     match scheme {
-        RSA_PKCS1_SHA256 | RSA_PKCS1_SHA384 | RSA_PKCS1_SHA512 => Some(NID_rsaEncryption),
+        RSA_PKCS1_SHA1 | RSA_PKCS1_SHA256 | RSA_PKCS1_SHA384 | RSA_PKCS1_SHA512 => {
+            Some(NID_rsaEncryption)
+        }
         RSA_PSS_SHA256 | RSA_PSS_SHA384 | RSA_PSS_SHA512 => Some(NID_rsassaPss),
-        ECDSA_NISTP256_SHA256 => Some(NID_X9_62_prime256v1),
-        ECDSA_NISTP384_SHA384 => Some(NID_secp384r1),
-        ECDSA_NISTP521_SHA512 => Some(NID_secp521r1),
+        ECDSA_SHA1_Legacy
+        | ECDSA_NISTP256_SHA256
+        | ECDSA_NISTP384_SHA384
+        | ECDSA_NISTP521_SHA512 => Some(NID_X9_62_id_ecPublicKey),
         ED25519 => Some(NID_ED25519),
         ED448 => Some(NID_ED448),
-        // Omitted: SHA1 legacy schemes.
         _ => None,
     }
 }
 
+/// The digest NID for `scheme`.
+///
+/// This is `None` for schemes without a separate digest, such as `ED25519`.
+pub fn sig_scheme_to_hash_nid(scheme: SignatureScheme) -> Option<c_int> {
+    use SignatureScheme::*;
+    match scheme {
+        RSA_PKCS1_SHA1 | ECDSA_SHA1_Legacy => Some(NID_sha1),
+        RSA_PKCS1_SHA256 | RSA_PSS_SHA256 | ECDSA_NISTP256_SHA256 => Some(NID_sha256),
+        RSA_PKCS1_SHA384 | RSA_PSS_SHA384 | ECDSA_NISTP384_SHA384 => Some(NID_sha384),
+        RSA_PKCS1_SHA512 | RSA_PSS_SHA512 | ECDSA_NISTP521_SHA512 => Some(NID_sha512),
+        _ => None,
+    }
+}
+
+/// The combined signature-and-digest NID for `scheme`.
+///
+/// Like OpenSSL, this is `None` for RSA-PSS and EdDSA schemes.
+pub fn sig_scheme_to_sign_hash_nid(scheme: SignatureScheme) -> Option<c_int> {
+    use SignatureScheme::*;
+    match scheme {
+        RSA_PKCS1_SHA1 => Some(NID_sha1WithRSAEncryption),
+        RSA_PKCS1_SHA256 => Some(NID_sha256WithRSAEncryption),
+        RSA_PKCS1_SHA384 => Some(NID_sha384WithRSAEncryption),
+        RSA_PKCS1_SHA512 => Some(NID_sha512WithRSAEncryption),
+        ECDSA_SHA1_Legacy => Some(NID_ecdsa_with_SHA1),
+        ECDSA_NISTP256_SHA256 => Some(NID_ecdsa_with_SHA256),
+        ECDSA_NISTP384_SHA384 => Some(NID_ecdsa_with_SHA384),
+        ECDSA_NISTP521_SHA512 => Some(NID_ecdsa_with_SHA512),
+        _ => None,
+    }
+}
+
+// See SSL_CB_* and SSL_ST_* from ssl.h - openssl-sys does not
+// have constants for these to import.
+pub const SSL_ST_CONNECT: c_int = 0x1000;
+pub const SSL_ST_ACCEPT: c_int = 0x2000;
+pub const SSL_CB_LOOP: c_int = 0x01;
+pub const SSL_CB_EXIT: c_int = 0x02;
+pub const SSL_CB_READ: c_int = 0x04;
+pub const SSL_CB_WRITE: c_int = 0x08;
+pub const SSL_CB_ALERT: c_int = 0x4000;
+pub const SSL_CB_READ_ALERT: c_int = SSL_CB_ALERT | SSL_CB_READ;
+pub const SSL_CB_WRITE_ALERT: c_int = SSL_CB_ALERT | SSL_CB_WRITE;
+pub const SSL_CB_HANDSHAKE_START: c_int = 0x10;
+pub const SSL_CB_HANDSHAKE_DONE: c_int = 0x20;
+
+// See SSL3_AL_* from ssl3.h.
+pub const SSL3_AL_WARNING: c_int = 1;
+pub const SSL3_AL_FATAL: c_int = 2;
+
+/// The `val` given to an info callback for an alert.
+pub fn alert_to_info_value(level: c_int, desc: AlertDescription) -> c_int {
+    level << 8 | u8::from(desc) as c_int
+}
+
+/// The alert rustls sends to the peer when it fails with `err`, if known.
+pub fn sent_alert_for_error(err: &rustls::Error) -> Option<AlertDescription> {
+    use rustls::Error::*;
+    match err {
+        InappropriateMessage { .. } | InappropriateHandshakeMessage { .. } => {
+            Some(AlertDescription::UnexpectedMessage)
+        }
+        InvalidMessage(err) => Some(AlertDescription::from(*err)),
+        InvalidCertificate(err) => Some(AlertDescription::from(err.clone())),
+        PeerIncompatible(_) => Some(AlertDescription::HandshakeFailure),
+        DecryptError => Some(AlertDescription::BadRecordMac),
+        NoApplicationProtocol => Some(AlertDescription::NoApplicationProtocol),
+        _ => None,
+    }
+}
+
+// See TLSEXT_nid_unknown from tls1.h - openssl-sys does not
+// have a constant for this to import.
+pub const TLSEXT_NID_UNKNOWN: c_int = 0x1000000;
+
 pub fn named_group_to_nid(group: NamedGroup) -> Option<c_int> {
     use NamedGroup::*;
 
@@ -114,10 +195,6 @@ pub fn named_group_to_nid(group: NamedGroup) -> Option<c_int> {
     const NID_FFDHE6144: c_int = 1129;
     const NID_FFDHE8192: c_int = 1130;
 
-    // See TLSEXT_nid_unknown from tls1.h - openssl-sys does not
-    // have a constant for this to import.
-    const TLSEXT_NID_UNKNOWN: c_int = 0x1000000;
-
     match group {
         secp256r1 => Some(NID_X9_62_prime256v1),
         secp384r1 => Some(NID_secp384r1),
@@ -133,3 +210,93 @@ pub fn named_group_to_nid(group: NamedGroup) -> Option<c_int> {
         _ => None,
     }
 }
+
+pub fn nid_to_named_group(nid: c_int) -> Option<NamedGroup> {
+    if nid & TLSEXT_NID_UNKNOWN == TLSEXT_NID_UNKNOWN {
+        return Some(NamedGroup::from(nid as u16));
+    }
+
+    use NamedGroup::*;
+    [
+        secp256r1, secp384r1, secp521r1, X25519, X448, FFDHE2048, FFDHE3072, FFDHE4096, FFDHE6144,
+        FFDHE8192,
+    ]
+    .into_iter()
+    .find(|group| named_group_to_nid(*group) == Some(nid))
+}
+
+/// Map an OpenSSL group name to a `NamedGroup`.
+///
+/// This covers the names and aliases known to OpenSSL's default provider,
+/// even if rustls does not support them.  Names are case-insensitive.
+pub fn named_group_from_name(name: &str) -> Option<NamedGroup> {
+    use NamedGroup::*;
+    Some(match name.to_ascii_lowercase().as_str() {
+        "secp256r1" | "p-256" | "prime256v1" => secp256r1,
+        "secp384r1" | "p-384" => secp384r1,
+        "secp521r1" | "p-521" => secp521r1,
+        "x25519" => X25519,
+        "x448" => X448,
+        "ffdhe2048" => FFDHE2048,
+        "ffdhe3072" => FFDHE3072,
+        "ffdhe4096" => FFDHE4096,
+        "ffdhe6144" => FFDHE6144,
+        "ffdhe8192" => FFDHE8192,
+        // These are the names used by OpenSSL 3.5 and later.
+        "mlkem768" => NamedGroup::from(0x0201),
+        "mlkem1024" => NamedGroup::from(0x0202),
+        "secp256r1mlkem768" => NamedGroup::from(0x11eb),
+        "x25519mlkem768" => NamedGroup::from(0x11ec),
+        _ => return None,
+    })
+}
+
+/// Map an OpenSSL signature algorithm name to a `SignatureScheme`.
+///
+/// This accepts both the TLS1.3-style names (eg. `rsa_pss_rsae_sha256`)
+/// and the `ALGORITHM+HASH` form (eg. `RSA-PSS+SHA256`).
+pub fn sig_scheme_from_name(name: &str) -> Option<SignatureScheme> {
+    use SignatureScheme::*;
+
+    if let Some((sig, hash)) = name.split_once('+') {
+        let hash = match hash {
+            "SHA1" | "sha1" => 1,
+            "SHA256" | "sha256" => 256,
+            "SHA384" | "sha384" => 384,
+            "SHA512" | "sha512" => 512,
+            _ => return None,
+        };
+
+        return Some(match (sig, hash) {
+            ("RSA", 1) => RSA_PKCS1_SHA1,
+            ("RSA", 256) => RSA_PKCS1_SHA256,
+            ("RSA", 384) => RSA_PKCS1_SHA384,
+            ("RSA", 512) => RSA_PKCS1_SHA512,
+            ("RSA-PSS" | "PSS", 256) => RSA_PSS_SHA256,
+            ("RSA-PSS" | "PSS", 384) => RSA_PSS_SHA384,
+            ("RSA-PSS" | "PSS", 512) => RSA_PSS_SHA512,
+            ("ECDSA", 1) => ECDSA_SHA1_Legacy,
+            ("ECDSA", 256) => ECDSA_NISTP256_SHA256,
+            ("ECDSA", 384) => ECDSA_NISTP384_SHA384,
+            ("ECDSA", 512) => ECDSA_NISTP521_SHA512,
+            _ => return None,
+        });
+    }
+
+    Some(match name {
+        "rsa_pkcs1_sha1" => RSA_PKCS1_SHA1,
+        "rsa_pkcs1_sha256" => RSA_PKCS1_SHA256,
+        "rsa_pkcs1_sha384" => RSA_PKCS1_SHA384,
+        "rsa_pkcs1_sha512" => RSA_PKCS1_SHA512,
+        "rsa_pss_rsae_sha256" => RSA_PSS_SHA256,
+        "rsa_pss_rsae_sha384" => RSA_PSS_SHA384,
+        "rsa_pss_rsae_sha512" => RSA_PSS_SHA512,
+        "ecdsa_sha1" => ECDSA_SHA1_Legacy,
+        "ecdsa_secp256r1_sha256" => ECDSA_NISTP256_SHA256,
+        "ecdsa_secp384r1_sha384" => ECDSA_NISTP384_SHA384,
+        "ecdsa_secp521r1_sha512" => ECDSA_NISTP521_SHA512,
+        "ed25519" => ED25519,
+        "ed448" => ED448,
+        _ => return None,
+    })
+}
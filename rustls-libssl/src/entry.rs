@@ -3,33 +3,42 @@
 //! It should mainly be concerned with mapping these calls up to
 //! the safe APIs implemented elsewhere.
 
+use core::ffi::CStr;
 use core::{mem, ptr};
 use std::io::{self, Read};
-use std::os::raw::{c_char, c_int, c_long, c_uchar, c_uint, c_void};
+use std::os::raw::{c_char, c_int, c_long, c_uchar, c_uint, c_ulong, c_void};
 use std::sync::Arc;
 use std::{fs, path::PathBuf};
 
 use openssl_sys::{
-    stack_st_X509, stack_st_X509_NAME, NID_undef, OPENSSL_malloc, TLSEXT_NAMETYPE_host_name,
-    EVP_PKEY, OPENSSL_NPN_NEGOTIATED, OPENSSL_NPN_NO_OVERLAP, X509, X509_STORE, X509_STORE_CTX,
+    stack_st_SSL_CIPHER, stack_st_X509, stack_st_X509_NAME, NID_undef, OPENSSL_malloc,
+    TLSEXT_NAMETYPE_host_name, EVP_PKEY, OPENSSL_NPN_NEGOTIATED, OPENSSL_NPN_NO_OVERLAP,
+    SSL_READ_EARLY_DATA_FINISH, SSL_READ_EARLY_DATA_SUCCESS, X509, X509_STORE, X509_STORE_CTX,
+    X509_V_ERR_UNSPECIFIED,
 };
 use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use rustls::{NamedGroup, SignatureScheme};
 
-use crate::bio::{Bio, BIO, BIO_METHOD};
+use crate::bio::{self, Bio, FileBio, BIO, BIO_METHOD, FILE};
 use crate::callbacks::SslCallbackContext;
-use crate::constants::{named_group_to_nid, sig_scheme_to_nid};
+use crate::client_hello::ClientHello;
+use crate::constants::{
+    named_group_to_nid, nid_to_named_group, sig_scheme_to_hash_nid, sig_scheme_to_nid,
+    sig_scheme_to_sign_hash_nid, TLSEXT_NID_UNKNOWN,
+};
 use crate::error::{ffi_panic_boundary, Error, MysteriouslyOppositeReturnValue};
 use crate::evp_pkey::EvpPkey;
 use crate::ex_data::ExData;
 use crate::ffi::{
     clone_arc, free_arc, free_arc_into_inner, free_box, str_from_cstring, string_from_cstring,
-    to_arc_mut_ptr, to_boxed_mut_ptr, try_clone_arc, try_from, try_mut_slice_int, try_ref_from_ptr,
-    try_slice, try_slice_int, try_str, Castable, OwnershipArc, OwnershipBox, OwnershipRef,
+    to_arc_mut_ptr, to_boxed_mut_ptr, try_clone_arc, try_from, try_mut_slice, try_mut_slice_int,
+    try_ref_from_ptr, try_slice, try_slice_int, try_str, Castable, OwnershipArc, OwnershipBox,
+    OwnershipRef,
 };
 use crate::not_thread_safe::NotThreadSafe;
 use crate::sign::OpenSslCertifiedKey;
 use crate::x509::{load_certs, OwnedX509, OwnedX509Stack};
-use crate::{conf, HandshakeState, ShutdownResult};
+use crate::{conf, EarlyDataStatus, HandshakeState, ShutdownResult};
 
 /// Makes a entry function definition.
 ///
@@ -195,13 +204,11 @@ entry! {
         let ctx = try_clone_arc!(ctx);
 
         match SslCtrl::try_from(cmd) {
-            Ok(SslCtrl::Mode) => {
-                log::warn!("unimplemented SSL_CTX_set_mode()");
-                0
-            }
+            Ok(SslCtrl::Mode) => ctx.get_mut().set_mode(larg as u32) as c_long,
+            Ok(SslCtrl::ClearMode) => ctx.get_mut().clear_mode(larg as u32) as c_long,
             Ok(SslCtrl::SetMsgCallbackArg) => {
-                log::warn!("unimplemented SSL_CTX_set_msg_callback_arg()");
-                0
+                ctx.get_mut().set_msg_callback_context(parg);
+                C_INT_SUCCESS as c_long
             }
             Ok(SslCtrl::SetMinProtoVersion) => {
                 if larg < 0 || larg > u16::MAX.into() {
@@ -222,10 +229,53 @@ entry! {
             Ok(SslCtrl::SetTlsExtHostname)
             | Ok(SslCtrl::SetTlsExtServerNameCallback)
             | Ok(SslCtrl::SetTlsExtTicketKeyCallback)
+            | Ok(SslCtrl::GetGroups)
+            | Ok(SslCtrl::GetSharedGroup)
             | Ok(SslCtrl::GetNegotiatedGroup) => {
                 // not a defined operation in the OpenSSL API
                 0
             }
+            Ok(SslCtrl::SetGroups) => {
+                // this is `SSL_CTX_set1_groups` (aka `SSL_CTX_set1_curves`)
+                match groups_from_nids(parg as *const c_int, larg)
+                    .and_then(|groups| ctx.get_mut().set_groups(&groups))
+                {
+                    Ok(()) => C_INT_SUCCESS as c_long,
+                    Err(e) => e.raise().into(),
+                }
+            }
+            Ok(SslCtrl::SetGroupsList) => {
+                // this is `SSL_CTX_set1_groups_list` (aka `SSL_CTX_set1_curves_list`)
+                let list = try_str!(parg as *const c_char);
+                match crate::parse_groups_list(list)
+                    .and_then(|groups| ctx.get_mut().set_groups(&groups))
+                {
+                    Ok(()) => C_INT_SUCCESS as c_long,
+                    Err(e) => e.raise().into(),
+                }
+            }
+            Ok(SslCtrl::SetSigalgsList) => {
+                // this is `SSL_CTX_set1_sigalgs_list`
+                let list = try_str!(parg as *const c_char);
+                match crate::parse_sigalgs_list(list) {
+                    Ok(sigalgs) => {
+                        ctx.get_mut().set_sigalgs(sigalgs);
+                        C_INT_SUCCESS as c_long
+                    }
+                    Err(e) => e.raise().into(),
+                }
+            }
+            Ok(SslCtrl::SetClientSigalgsList) => {
+                // this is `SSL_CTX_set1_client_sigalgs_list`
+                let list = try_str!(parg as *const c_char);
+                match crate::parse_sigalgs_list(list) {
+                    Ok(sigalgs) => {
+                        ctx.get_mut().set_client_sigalgs(sigalgs);
+                        C_INT_SUCCESS as c_long
+                    }
+                    Err(e) => e.raise().into(),
+                }
+            }
             Ok(SslCtrl::SetChain) => {
                 let chain = if parg.is_null() {
                     // this is `SSL_CTX_clear_chain_certs`
@@ -242,6 +292,14 @@ entry! {
                 ctx.get_mut().stage_certificate_chain(chain);
                 C_INT_SUCCESS as i64
             }
+            Ok(SslCtrl::SelectCurrentCert) => {
+                ctx.get_mut().select_current_cert(parg as *mut X509) as c_long
+            }
+            Ok(SslCtrl::SetCurrentCert) => match larg {
+                SSL_CERT_SET_FIRST => ctx.get_mut().set_current_cert(false) as c_long,
+                SSL_CERT_SET_NEXT => ctx.get_mut().set_current_cert(true) as c_long,
+                _ => 0,
+            },
             Ok(SslCtrl::SetTlsExtServerNameArg) => {
                 ctx.get_mut().set_servername_callback_context(parg);
                 C_INT_SUCCESS as c_long
@@ -269,16 +327,15 @@ entry! {
 
 entry! {
     pub fn _SSL_CTX_set_verify(ctx: *mut SSL_CTX, mode: c_int, callback: SSL_verify_cb) {
-        if callback.is_some() {
-            // supporting verify callbacks would mean we need to fully use
-            // the openssl certificate verifier, because X509_STORE and
-            // X509_STORE_CTX are both in libcrypto.
-            return Error::not_supported("verify callback").raise().into();
-        }
+        let ctx = try_clone_arc!(ctx);
+        ctx.get_mut().set_verify(crate::VerifyMode::from(mode));
+        ctx.get_mut().set_verify_callback(callback);
+    }
+}
 
-        try_clone_arc!(ctx)
-            .get_mut()
-            .set_verify(crate::VerifyMode::from(mode));
+entry! {
+    pub fn _SSL_get_ex_data_X509_STORE_CTX_idx() -> c_int {
+        crate::ex_data::ssl_x509_store_ctx_idx()
     }
 }
 
@@ -471,6 +528,47 @@ pub(crate) fn use_cert_chain_file(file_name: &str) -> Result<Vec<CertificateDer<
     Ok(chain)
 }
 
+entry! {
+    pub fn _SSL_CTX_use_certificate_file(
+        ctx: *mut SSL_CTX,
+        file_name: *const c_char,
+        file_type: c_int,
+    ) -> c_int {
+        let ctx = try_clone_arc!(ctx);
+        let file_name = try_str!(file_name);
+
+        match use_cert_file(file_name, file_type) {
+            Ok(ee) => {
+                ctx.get_mut().stage_certificate_end_entity(ee);
+                C_INT_SUCCESS
+            }
+            Err(err) => err.raise().into(),
+        }
+    }
+}
+
+/// Load the first certificate in `file_name`, for `SSL_CTX_use_certificate_file`
+/// and `SSL_use_certificate_file`.
+fn use_cert_file(file_name: &str, file_type: c_int) -> Result<CertificateDer<'static>, Error> {
+    match file_type {
+        FILETYPE_PEM => match use_cert_chain_file(file_name)?.into_iter().next() {
+            Some(ee) => Ok(ee),
+            None => {
+                log::trace!("No certificates found in {file_name:?}");
+                Err(Error::bad_data("pem file"))
+            }
+        },
+        FILETYPE_DER => match fs::read(file_name) {
+            Ok(data) => certificate_from_der(&data),
+            Err(err) => {
+                log::trace!("Failed to read {file_name:?}: {err:?}");
+                Err(Error::from_io(err))
+            }
+        },
+        _ => Err(Error::not_supported("file_type not in (PEM, DER)")),
+    }
+}
+
 entry! {
     pub fn _SSL_CTX_use_certificate(ctx: *mut SSL_CTX, x: *mut X509) -> c_int {
         let ctx = try_clone_arc!(ctx);
@@ -568,6 +666,124 @@ entry! {
     }
 }
 
+entry! {
+    pub fn _SSL_CTX_use_certificate_ASN1(
+        ctx: *mut SSL_CTX,
+        len: c_int,
+        d: *const c_uchar,
+    ) -> c_int {
+        let ctx = try_clone_arc!(ctx);
+        let der = try_slice_int!(d, len);
+
+        match certificate_from_der(der) {
+            Ok(ee) => {
+                ctx.get_mut().stage_certificate_end_entity(ee);
+                C_INT_SUCCESS
+            }
+            Err(e) => e.raise().into(),
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_CTX_use_PrivateKey_ASN1(
+        pk: c_int,
+        ctx: *mut SSL_CTX,
+        d: *const c_uchar,
+        len: c_long,
+    ) -> c_int {
+        let ctx = try_clone_arc!(ctx);
+        let der = try_slice_int!(d, len);
+
+        match private_key_from_der(pk, der).and_then(|key| ctx.get_mut().commit_private_key(key)) {
+            Err(e) => e.raise().into(),
+            Ok(()) => C_INT_SUCCESS,
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_CTX_use_cert_and_key(
+        ctx: *mut SSL_CTX,
+        x509: *mut X509,
+        privatekey: *mut EVP_PKEY,
+        chain: *mut stack_st_X509,
+        override_: c_int,
+    ) -> c_int {
+        let ctx = try_clone_arc!(ctx);
+
+        match cert_and_key(x509, privatekey, chain).and_then(|(ee, chain, key)| {
+            ctx.get_mut()
+                .use_cert_and_key(ee, chain, key, override_ != 0)
+        }) {
+            Err(e) => e.raise().into(),
+            Ok(()) => C_INT_SUCCESS,
+        }
+    }
+}
+
+/// Parse a DER certificate, for `SSL_CTX_use_certificate_ASN1` and friends.
+fn certificate_from_der(der: &[u8]) -> Result<CertificateDer<'static>, Error> {
+    match OwnedX509::parse_der(der) {
+        Some(x509) => Ok(CertificateDer::from(x509.der_bytes())),
+        None => Err(Error::bad_data("certificate")),
+    }
+}
+
+/// Parse a DER private key, for `SSL_CTX_use_PrivateKey_ASN1` and friends.
+///
+/// `pk` is the expected `EVP_PKEY_*` type.
+fn private_key_from_der(pk: c_int, der: &[u8]) -> Result<EvpPkey, Error> {
+    // nb. like the DER case of `use_private_key_file`, the PKCS#8 wrapper only
+    // carries the bytes: `d2i_AutoPrivateKey` also accepts PKCS#1 and SEC1.
+    let key = EvpPkey::new_from_der_bytes(PrivatePkcs8KeyDer::from(der.to_vec()).into())
+        .ok_or_else(|| Error::bad_data("invalid key format"))?;
+
+    match key.id() == pk {
+        true => Ok(key),
+        false => Err(Error::bad_data(&format!(
+            "key type {} is not the expected {pk}",
+            key.id()
+        ))),
+    }
+}
+
+/// Check and convert the arguments to `SSL_CTX_use_cert_and_key` and friends.
+///
+/// `privatekey` may be NULL, leaving the certificate to await a key.
+fn cert_and_key(
+    x509: *mut X509,
+    privatekey: *mut EVP_PKEY,
+    chain: *mut stack_st_X509,
+) -> Result<
+    (
+        CertificateDer<'static>,
+        Vec<CertificateDer<'static>>,
+        Option<EvpPkey>,
+    ),
+    Error,
+> {
+    if x509.is_null() {
+        return Err(Error::null_pointer());
+    }
+
+    let ee = CertificateDer::from(OwnedX509::new_incref(x509).der_bytes());
+    let chain = match chain.is_null() {
+        true => vec![],
+        false => OwnedX509Stack::new_copy(chain).to_rustls(),
+    };
+    if privatekey.is_null() {
+        return Ok((ee, chain, None));
+    }
+    let key = EvpPkey::new_incref(privatekey);
+
+    if !OpenSslCertifiedKey::new(vec![ee.clone()], key.clone())?.keys_match() {
+        return Err(Error::bad_data("private key does not match certificate"));
+    }
+
+    Ok((ee, chain, Some(key)))
+}
+
 entry! {
     pub fn _SSL_CTX_get0_certificate(ctx: *const SSL_CTX) -> *mut X509 {
         try_clone_arc!(ctx).get().get_certificate()
@@ -587,6 +803,21 @@ entry! {
     }
 }
 
+entry! {
+    pub fn _SSL_CTX_set_cert_verify_callback(
+        ctx: *mut SSL_CTX,
+        cb: SSL_CTX_cert_verify_cb_func,
+        arg: *mut c_void,
+    ) {
+        try_clone_arc!(ctx)
+            .get_mut()
+            .set_cert_verify_callback(cb, arg);
+    }
+}
+
+pub type SSL_CTX_cert_verify_cb_func =
+    Option<unsafe extern "C" fn(x509_ctx: *mut X509_STORE_CTX, arg: *mut c_void) -> c_int>;
+
 pub type SSL_CTX_alpn_select_cb_func = Option<
     unsafe extern "C" fn(
         ssl: *mut SSL,
@@ -619,6 +850,20 @@ entry! {
     }
 }
 
+pub type SSL_client_hello_cb_fn =
+    Option<unsafe extern "C" fn(ssl: *mut SSL, al: *mut c_int, arg: *mut c_void) -> c_int>;
+
+entry! {
+    pub fn _SSL_CTX_set_client_hello_cb(
+        ctx: *mut SSL_CTX,
+        cb: SSL_client_hello_cb_fn,
+        arg: *mut c_void,
+    ) {
+        let ctx = try_clone_arc!(ctx);
+        ctx.get_mut().set_client_hello_cb(cb, arg);
+    }
+}
+
 // nb. calls into SSL_CTX_callback_ctrl cast away the real function pointer type,
 // and then cast back to the real type based on `cmd`.
 pub type SSL_CTX_any_func = Option<unsafe extern "C" fn()>;
@@ -664,16 +909,63 @@ entry! {
 }
 
 entry! {
-    pub fn _SSL_CTX_set_cipher_list(_ctx: *mut SSL_CTX, s: *const c_char) -> c_int {
-        match try_str!(s) {
-            "HIGH:!aNULL:!MD5" => C_INT_SUCCESS,
-            _ => Error::not_supported("SSL_CTX_set_cipher_list")
-                .raise()
-                .into(),
+    pub fn _SSL_CTX_get_recv_max_early_data(ctx: *const SSL_CTX) -> u32 {
+        try_clone_arc!(ctx).get().get_recv_max_early_data()
+    }
+}
+
+// Divergence: this is recorded but not enforced. rustls applies the `max_early_data`
+// limit to early data it receives.
+entry! {
+    pub fn _SSL_CTX_set_recv_max_early_data(ctx: *mut SSL_CTX, recv_max_early_data: u32) -> c_int {
+        try_clone_arc!(ctx)
+            .get_mut()
+            .set_recv_max_early_data(recv_max_early_data);
+        C_INT_SUCCESS
+    }
+}
+
+entry! {
+    pub fn _SSL_CTX_set_allow_early_data_cb(
+        ctx: *mut SSL_CTX,
+        cb: SSL_allow_early_data_cb_fn,
+        arg: *mut c_void,
+    ) {
+        try_clone_arc!(ctx)
+            .get_mut()
+            .set_allow_early_data_cb(cb, arg);
+    }
+}
+
+pub type SSL_allow_early_data_cb_fn =
+    Option<unsafe extern "C" fn(ssl: *mut SSL, arg: *mut c_void) -> c_int>;
+
+entry! {
+    pub fn _SSL_CTX_set_cipher_list(ctx: *mut SSL_CTX, s: *const c_char) -> c_int {
+        let rule_str = try_str!(s);
+        match try_clone_arc!(ctx).get_mut().set_cipher_list(rule_str) {
+            Ok(()) => C_INT_SUCCESS,
+            Err(e) => e.raise().into(),
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_CTX_set_ciphersuites(ctx: *mut SSL_CTX, s: *const c_char) -> c_int {
+        let names = try_str!(s);
+        match try_clone_arc!(ctx).get_mut().set_ciphersuites(names) {
+            Ok(()) => C_INT_SUCCESS,
+            Err(e) => e.raise().into(),
         }
     }
 }
 
+entry! {
+    pub fn _SSL_CTX_get_ciphers(ctx: *const SSL_CTX) -> *mut stack_st_SSL_CIPHER {
+        try_clone_arc!(ctx).get().get_ciphers()
+    }
+}
+
 entry! {
     pub fn _SSL_CTX_set_session_id_context(
         ctx: *mut SSL_CTX,
@@ -817,13 +1109,11 @@ entry! {
         let ssl = try_clone_arc!(ssl);
 
         match SslCtrl::try_from(cmd) {
-            Ok(SslCtrl::Mode) => {
-                log::warn!("unimplemented SSL_set_mode()");
-                0
-            }
+            Ok(SslCtrl::Mode) => ssl.get_mut().set_mode(larg as u32) as c_long,
+            Ok(SslCtrl::ClearMode) => ssl.get_mut().clear_mode(larg as u32) as c_long,
             Ok(SslCtrl::SetMsgCallbackArg) => {
-                log::warn!("unimplemented SSL_set_msg_callback_arg()");
-                0
+                ssl.get_mut().set_msg_callback_context(parg);
+                C_INT_SUCCESS as c_long
             }
             Ok(SslCtrl::SetMinProtoVersion) => {
                 if larg < 0 || larg > u16::MAX.into() {
@@ -861,11 +1151,87 @@ entry! {
                 ssl.get_mut().stage_certificate_chain(chain);
                 C_INT_SUCCESS as i64
             }
+            Ok(SslCtrl::SelectCurrentCert) => {
+                ssl.get_mut().select_current_cert(parg as *mut X509) as c_long
+            }
+            Ok(SslCtrl::SetCurrentCert) => match larg {
+                SSL_CERT_SET_FIRST => ssl.get_mut().set_current_cert(false) as c_long,
+                SSL_CERT_SET_NEXT => ssl.get_mut().set_current_cert(true) as c_long,
+                _ => 0,
+            },
             Ok(SslCtrl::GetNegotiatedGroup) => ssl
                 .get()
                 .get_negotiated_key_exchange_group()
                 .and_then(|group| named_group_to_nid(group.name()))
                 .unwrap_or(NID_undef) as c_long,
+            Ok(SslCtrl::SetGroups) => {
+                // this is `SSL_set1_groups` (aka `SSL_set1_curves`)
+                match groups_from_nids(parg as *const c_int, larg)
+                    .and_then(|groups| ssl.get_mut().set_groups(&groups))
+                {
+                    Ok(()) => C_INT_SUCCESS as c_long,
+                    Err(e) => e.raise().into(),
+                }
+            }
+            Ok(SslCtrl::SetGroupsList) => {
+                // this is `SSL_set1_groups_list` (aka `SSL_set1_curves_list`)
+                let list = try_str!(parg as *const c_char);
+                match crate::parse_groups_list(list)
+                    .and_then(|groups| ssl.get_mut().set_groups(&groups))
+                {
+                    Ok(()) => C_INT_SUCCESS as c_long,
+                    Err(e) => e.raise().into(),
+                }
+            }
+            Ok(SslCtrl::SetSigalgsList) => {
+                // this is `SSL_set1_sigalgs_list`
+                let list = try_str!(parg as *const c_char);
+                match crate::parse_sigalgs_list(list) {
+                    Ok(sigalgs) => {
+                        ssl.get_mut().set_sigalgs(sigalgs);
+                        C_INT_SUCCESS as c_long
+                    }
+                    Err(e) => e.raise().into(),
+                }
+            }
+            Ok(SslCtrl::SetClientSigalgsList) => {
+                // this is `SSL_set1_client_sigalgs_list`
+                let list = try_str!(parg as *const c_char);
+                match crate::parse_sigalgs_list(list) {
+                    Ok(sigalgs) => {
+                        ssl.get_mut().set_client_sigalgs(sigalgs);
+                        C_INT_SUCCESS as c_long
+                    }
+                    Err(e) => e.raise().into(),
+                }
+            }
+            Ok(SslCtrl::GetGroups) => {
+                // this is `SSL_get1_groups` (aka `SSL_get1_curves`): `parg` may be
+                // NULL to query the length, otherwise it must be large enough.
+                let ssl = ssl.get();
+                let groups = ssl.get_peer_groups();
+                if !parg.is_null() {
+                    let out = unsafe {
+                        core::slice::from_raw_parts_mut(parg as *mut c_int, groups.len())
+                    };
+                    for (nid, group) in out.iter_mut().zip(groups) {
+                        *nid = group_to_nid(*group);
+                    }
+                }
+                groups.len() as c_long
+            }
+            Ok(SslCtrl::GetSharedGroup) => {
+                // this is `SSL_get_shared_group` (aka `SSL_get_shared_curve`)
+                let shared = ssl.get().get_shared_groups();
+                match larg {
+                    -1 => shared.len() as c_long,
+                    n => usize::try_from(n)
+                        .ok()
+                        .and_then(|n| shared.get(n))
+                        .map(|group| group_to_nid(*group))
+                        .unwrap_or(NID_undef) as c_long,
+                }
+            }
             // not a defined operation in the OpenSSL API
             Ok(SslCtrl::SetTlsExtServerNameCallback)
             | Ok(SslCtrl::SetTlsExtTicketKeyCallback)
@@ -1012,6 +1378,7 @@ entry! {
 
 entry! {
     pub fn _SSL_connect(ssl: *mut SSL) -> c_int {
+        let _callbacks = SslCallbackContext::new(ssl);
         match try_clone_arc!(ssl).get_mut().connect() {
             Err(e) => e.raise().into(),
             Ok(()) => C_INT_SUCCESS,
@@ -1044,6 +1411,7 @@ entry! {
 entry! {
     pub fn _SSL_write(ssl: *mut SSL, buf: *const c_void, num: c_int) -> c_int {
         const ERROR: c_int = -1;
+        let _callbacks = SslCallbackContext::new(ssl);
         let ssl = try_clone_arc!(ssl, ERROR);
         let slice = try_slice_int!(buf as *const u8, num, ERROR);
 
@@ -1064,6 +1432,7 @@ entry! {
 entry! {
     pub fn _SSL_read(ssl: *mut SSL, buf: *mut c_void, num: c_int) -> c_int {
         const ERROR: c_int = 0;
+        let _callbacks = SslCallbackContext::new(ssl);
         let ssl = try_clone_arc!(ssl, ERROR);
         let slice = try_mut_slice_int!(buf as *mut u8, num, ERROR);
 
@@ -1078,82 +1447,293 @@ entry! {
 }
 
 entry! {
-    pub fn _SSL_want(ssl: *const SSL) -> c_int {
-        let want = try_clone_arc!(ssl).get().want();
+    pub fn _SSL_write_early_data(
+        ssl: *mut SSL,
+        buf: *const c_void,
+        num: usize,
+        written: *mut usize,
+    ) -> c_int {
+        let _callbacks = SslCallbackContext::new(ssl);
+        let ssl = try_clone_arc!(ssl);
+        let slice = try_slice!(buf as *const u8, num);
+        if written.is_null() {
+            return Error::null_pointer().raise().into();
+        }
 
-        if want.read {
-            SSL_READING
-        } else if want.write {
-            SSL_WRITING
-        } else {
-            SSL_NOTHING
+        match ssl.get_mut().write_early_data(slice) {
+            Err(e) => e.raise().into(),
+            Ok(wr) => {
+                unsafe { *written = wr };
+                C_INT_SUCCESS
+            }
         }
     }
 }
 
-pub const SSL_NOTHING: i32 = 1;
-pub const SSL_WRITING: i32 = 2;
-pub const SSL_READING: i32 = 3;
-
 entry! {
-    pub fn _SSL_shutdown(ssl: *mut SSL) -> c_int {
-        const ERROR: c_int = -1;
-        match try_clone_arc!(ssl, ERROR).get_mut().try_shutdown() {
-            Err(e) => {
-                e.raise();
-                ERROR
+    pub fn _SSL_read_early_data(
+        ssl: *mut SSL,
+        buf: *mut c_void,
+        num: usize,
+        readbytes: *mut usize,
+    ) -> c_int {
+        // nb. errors are `SSL_READ_EARLY_DATA_ERROR`, which is zero.
+        let _callbacks = SslCallbackContext::new(ssl);
+        let ssl = try_clone_arc!(ssl);
+        let slice = try_mut_slice!(buf as *mut u8, num);
+        if readbytes.is_null() {
+            return Error::null_pointer().raise().into();
+        }
+
+        match ssl.get_mut().read_early_data(slice) {
+            Err(e) => e.raise().into(),
+            Ok(Some(read)) => {
+                unsafe { *readbytes = read };
+                SSL_READ_EARLY_DATA_SUCCESS
+            }
+            Ok(None) => {
+                unsafe { *readbytes = 0 };
+                SSL_READ_EARLY_DATA_FINISH
             }
-            Ok(result) => match result {
-                ShutdownResult::Sent => 0 as c_int,
-                ShutdownResult::Received => 1 as c_int,
-            },
         }
     }
 }
 
 entry! {
-    pub fn _SSL_get_shutdown(ssl: *const SSL) -> c_int {
-        try_clone_arc!(ssl).get().get_shutdown()
+    pub fn _SSL_get_early_data_status(ssl: *const SSL) -> c_int {
+        match try_clone_arc!(ssl).get_mut().get_early_data_status() {
+            EarlyDataStatus::NotSent => SSL_EARLY_DATA_NOT_SENT,
+            EarlyDataStatus::Rejected => SSL_EARLY_DATA_REJECTED,
+            EarlyDataStatus::Accepted => SSL_EARLY_DATA_ACCEPTED,
+        }
     }
 }
 
+const SSL_EARLY_DATA_NOT_SENT: c_int = 0;
+const SSL_EARLY_DATA_REJECTED: c_int = 1;
+const SSL_EARLY_DATA_ACCEPTED: c_int = 2;
+
 entry! {
-    pub fn _SSL_set_shutdown(ssl: *mut SSL, flags: c_int) {
-        try_clone_arc!(ssl).get_mut().set_shutdown(flags)
+    pub fn _SSL_get_max_early_data(ssl: *const SSL) -> u32 {
+        try_clone_arc!(ssl).get().get_max_early_data()
     }
 }
 
 entry! {
-    pub fn _SSL_set_quiet_shutdown(ssl: *mut SSL, mode: c_int) {
-        try_clone_arc!(ssl).get_mut().set_quiet_shutdown(mode != 0)
+    pub fn _SSL_set_max_early_data(ssl: *mut SSL, max_early_data: u32) -> c_int {
+        try_clone_arc!(ssl)
+            .get_mut()
+            .set_max_early_data(max_early_data);
+        C_INT_SUCCESS
     }
 }
 
 entry! {
-    pub fn _SSL_pending(ssl: *const SSL) -> c_int {
-        try_clone_arc!(ssl).get_mut().get_pending_plaintext() as c_int
+    pub fn _SSL_get_recv_max_early_data(ssl: *const SSL) -> u32 {
+        try_clone_arc!(ssl).get().get_recv_max_early_data()
     }
 }
 
+// Divergence: as for `SSL_CTX_set_recv_max_early_data`.
 entry! {
-    pub fn _SSL_has_pending(ssl: *const SSL) -> c_int {
-        (_SSL_pending(ssl) > 0) as c_int
+    pub fn _SSL_set_recv_max_early_data(ssl: *mut SSL, recv_max_early_data: u32) -> c_int {
+        try_clone_arc!(ssl)
+            .get_mut()
+            .set_recv_max_early_data(recv_max_early_data);
+        C_INT_SUCCESS
     }
 }
 
 entry! {
-    pub fn _SSL_get_error(ssl: *const SSL, _ret_code: c_int) -> c_int {
-        try_clone_arc!(ssl).get_mut().get_error() as c_int
+    pub fn _SSL_set_allow_early_data_cb(
+        ssl: *mut SSL,
+        cb: SSL_allow_early_data_cb_fn,
+        arg: *mut c_void,
+    ) {
+        try_clone_arc!(ssl)
+            .get_mut()
+            .set_allow_early_data_cb(cb, arg);
     }
 }
 
 entry! {
-    pub fn _SSL_get0_alpn_selected(ssl: *const SSL, data: *mut *const c_uchar, len: *mut c_uint) {
-        if data.is_null() || len.is_null() {
-            return;
-        }
-
-        match try_clone_arc!(ssl).get().get_agreed_alpn() {
+    pub fn _SSL_write_ex(
+        ssl: *mut SSL,
+        buf: *const c_void,
+        num: usize,
+        written: *mut usize,
+    ) -> c_int {
+        let _callbacks = SslCallbackContext::new(ssl);
+        let ssl = try_clone_arc!(ssl);
+        let slice = try_slice!(buf as *const u8, num);
+        if written.is_null() {
+            return Error::null_pointer().raise().into();
+        }
+
+        match ssl.get_mut().write(slice) {
+            Err(e) => e.raise().into(),
+            Ok(wr) => {
+                unsafe { *written = wr };
+                C_INT_SUCCESS
+            }
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_read_ex(
+        ssl: *mut SSL,
+        buf: *mut c_void,
+        num: usize,
+        readbytes: *mut usize,
+    ) -> c_int {
+        let _callbacks = SslCallbackContext::new(ssl);
+        let ssl = try_clone_arc!(ssl);
+        let slice = try_mut_slice!(buf as *mut u8, num);
+        if readbytes.is_null() {
+            return Error::null_pointer().raise().into();
+        }
+
+        match ssl.get_mut().read(slice) {
+            Err(e) => e.raise().into(),
+            // success means at least one byte
+            Ok(0) => 0,
+            Ok(read) => {
+                unsafe { *readbytes = read };
+                C_INT_SUCCESS
+            }
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_peek(ssl: *mut SSL, buf: *mut c_void, num: c_int) -> c_int {
+        const ERROR: c_int = 0;
+        let _callbacks = SslCallbackContext::new(ssl);
+        let ssl = try_clone_arc!(ssl, ERROR);
+        let slice = try_mut_slice_int!(buf as *mut u8, num, ERROR);
+
+        match ssl.get_mut().peek(slice) {
+            Err(e) => {
+                e.raise();
+                ERROR
+            }
+            Ok(read) => read as c_int,
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_peek_ex(
+        ssl: *mut SSL,
+        buf: *mut c_void,
+        num: usize,
+        readbytes: *mut usize,
+    ) -> c_int {
+        let _callbacks = SslCallbackContext::new(ssl);
+        let ssl = try_clone_arc!(ssl);
+        let slice = try_mut_slice!(buf as *mut u8, num);
+        if readbytes.is_null() {
+            return Error::null_pointer().raise().into();
+        }
+
+        match ssl.get_mut().peek(slice) {
+            Err(e) => e.raise().into(),
+            // as for `SSL_read_ex`
+            Ok(0) => 0,
+            Ok(read) => {
+                unsafe { *readbytes = read };
+                C_INT_SUCCESS
+            }
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_want(ssl: *const SSL) -> c_int {
+        let ssl = try_clone_arc!(ssl);
+        let want = ssl.get().want();
+
+        if ssl.get().client_hello_retry() {
+            SSL_CLIENT_HELLO_CB
+        } else if want.read {
+            SSL_READING
+        } else if want.write {
+            SSL_WRITING
+        } else {
+            SSL_NOTHING
+        }
+    }
+}
+
+pub const SSL_NOTHING: i32 = 1;
+pub const SSL_WRITING: i32 = 2;
+pub const SSL_READING: i32 = 3;
+pub const SSL_CLIENT_HELLO_CB: i32 = 7;
+
+const SSL_CERT_SET_FIRST: c_long = 1;
+const SSL_CERT_SET_NEXT: c_long = 2;
+
+entry! {
+    pub fn _SSL_shutdown(ssl: *mut SSL) -> c_int {
+        const ERROR: c_int = -1;
+        let _callbacks = SslCallbackContext::new(ssl);
+        match try_clone_arc!(ssl, ERROR).get_mut().try_shutdown() {
+            Err(e) => {
+                e.raise();
+                ERROR
+            }
+            Ok(result) => match result {
+                ShutdownResult::Sent => 0 as c_int,
+                ShutdownResult::Received => 1 as c_int,
+            },
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_get_shutdown(ssl: *const SSL) -> c_int {
+        try_clone_arc!(ssl).get().get_shutdown()
+    }
+}
+
+entry! {
+    pub fn _SSL_set_shutdown(ssl: *mut SSL, flags: c_int) {
+        try_clone_arc!(ssl).get_mut().set_shutdown(flags)
+    }
+}
+
+entry! {
+    pub fn _SSL_set_quiet_shutdown(ssl: *mut SSL, mode: c_int) {
+        try_clone_arc!(ssl).get_mut().set_quiet_shutdown(mode != 0)
+    }
+}
+
+entry! {
+    pub fn _SSL_pending(ssl: *const SSL) -> c_int {
+        try_clone_arc!(ssl).get_mut().get_pending_plaintext() as c_int
+    }
+}
+
+entry! {
+    pub fn _SSL_has_pending(ssl: *const SSL) -> c_int {
+        (_SSL_pending(ssl) > 0) as c_int
+    }
+}
+
+entry! {
+    pub fn _SSL_get_error(ssl: *const SSL, _ret_code: c_int) -> c_int {
+        try_clone_arc!(ssl).get_mut().get_error() as c_int
+    }
+}
+
+entry! {
+    pub fn _SSL_get0_alpn_selected(ssl: *const SSL, data: *mut *const c_uchar, len: *mut c_uint) {
+        if data.is_null() || len.is_null() {
+            return;
+        }
+
+        match try_clone_arc!(ssl).get().get_agreed_alpn() {
             Some(slice) => unsafe {
                 // nb. alpn protocols are limited to 255 octets
                 ptr::write(len, slice.len() as u32);
@@ -1198,6 +1778,107 @@ entry! {
     }
 }
 
+entry! {
+    pub fn _SSL_get_signature_type_nid(ssl: *const SSL, psigtype_nid: *mut c_int) -> c_int {
+        if psigtype_nid.is_null() {
+            return 0;
+        }
+
+        let sigalg_nid = try_clone_arc!(ssl)
+            .get()
+            .get_sig_scheme()
+            .and_then(sig_scheme_to_nid);
+
+        match sigalg_nid {
+            Some(nid) => {
+                unsafe { ptr::write(psigtype_nid, nid) };
+                C_INT_SUCCESS
+            }
+            None => 0,
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_get_sigalgs(
+        ssl: *mut SSL,
+        idx: c_int,
+        psign: *mut c_int,
+        phash: *mut c_int,
+        psignhash: *mut c_int,
+        rsig: *mut c_uchar,
+        rhash: *mut c_uchar,
+    ) -> c_int {
+        let sigalgs = try_clone_arc!(ssl).get().get_peer_sigalgs();
+
+        if idx >= 0 {
+            match sigalgs.get(idx as usize) {
+                Some(scheme) => write_sigalg(*scheme, psign, phash, psignhash, rsig, rhash),
+                None => return 0,
+            }
+        }
+
+        sigalgs.len() as c_int
+    }
+}
+
+entry! {
+    pub fn _SSL_get_shared_sigalgs(
+        ssl: *mut SSL,
+        idx: c_int,
+        psign: *mut c_int,
+        phash: *mut c_int,
+        psignhash: *mut c_int,
+        rsig: *mut c_uchar,
+        rhash: *mut c_uchar,
+    ) -> c_int {
+        let sigalgs = try_clone_arc!(ssl).get().get_shared_sigalgs();
+
+        // nb. unlike `SSL_get_sigalgs`, a negative `idx` is not a count query.
+        if idx < 0 {
+            return 0;
+        }
+
+        match sigalgs.get(idx as usize) {
+            Some(scheme) => write_sigalg(*scheme, psign, phash, psignhash, rsig, rhash),
+            None => return 0,
+        }
+
+        sigalgs.len() as c_int
+    }
+}
+
+/// Describe `scheme` into the (optional) out-parameters of `SSL_get_sigalgs`
+/// and `SSL_get_shared_sigalgs`.
+fn write_sigalg(
+    scheme: SignatureScheme,
+    psign: *mut c_int,
+    phash: *mut c_int,
+    psignhash: *mut c_int,
+    rsig: *mut c_uchar,
+    rhash: *mut c_uchar,
+) {
+    let [hash, sig] = u16::from(scheme).to_be_bytes();
+    let nids = [
+        (psign, sig_scheme_to_nid(scheme)),
+        (phash, sig_scheme_to_hash_nid(scheme)),
+        (psignhash, sig_scheme_to_sign_hash_nid(scheme)),
+    ];
+
+    for (out, nid) in nids {
+        if !out.is_null() {
+            unsafe { ptr::write(out, nid.unwrap_or(NID_undef)) };
+        }
+    }
+
+    if !rsig.is_null() {
+        unsafe { ptr::write(rsig, sig) };
+    }
+    if !rhash.is_null() {
+        unsafe { ptr::write(rhash, hash) };
+    }
+}
+
 entry! {
     pub fn _SSL_get0_verified_chain(ssl: *const SSL) -> *mut stack_st_X509 {
         _SSL_get_peer_cert_chain(ssl)
@@ -1302,6 +1983,77 @@ entry! {
     }
 }
 
+entry! {
+    pub fn _SSL_export_keying_material(
+        ssl: *mut SSL,
+        out: *mut c_uchar,
+        olen: usize,
+        label: *const c_char,
+        llen: usize,
+        context: *const c_uchar,
+        contextlen: usize,
+        use_context: c_int,
+    ) -> c_int {
+        const NOT_READY: c_int = -1;
+        let ssl = try_clone_arc!(ssl, NOT_READY);
+        let output = try_mut_slice!(out, olen);
+        let label = try_slice!(label as *const u8, llen);
+
+        // Like OpenSSL, `context` is ignored unless `use_context` is set, and then
+        // an empty context is distinct from none (for TLS1.2).
+        let context = match (use_context, contextlen) {
+            (0, _) => None,
+            (_, 0) => Some(&[][..]),
+            (_, _) => Some(try_slice!(context, contextlen)),
+        };
+
+        let ssl = ssl.get_mut();
+        if ssl.handshake_state() != HandshakeState::Finished {
+            Error::bad_data("handshake not complete").raise();
+            return NOT_READY;
+        }
+
+        match ssl.export_keying_material(output, label, context) {
+            Ok(()) => C_INT_SUCCESS,
+            Err(e) => e.raise().into(),
+        }
+    }
+}
+
+// Divergence: rustls always asks the peer to update its keys too, so
+// `SSL_KEY_UPDATE_NOT_REQUESTED` is not supported.
+entry! {
+    pub fn _SSL_key_update(ssl: *mut SSL, updatetype: c_int) -> c_int {
+        match updatetype {
+            SSL_KEY_UPDATE_REQUESTED => {}
+            SSL_KEY_UPDATE_NOT_REQUESTED => {
+                return Error::not_supported("SSL_KEY_UPDATE_NOT_REQUESTED")
+                    .raise()
+                    .into();
+            }
+            _ => return Error::bad_data("invalid key update type").raise().into(),
+        }
+
+        match try_clone_arc!(ssl).get_mut().key_update(updatetype) {
+            Ok(()) => C_INT_SUCCESS,
+            Err(e) => e.raise().into(),
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_get_key_update_type(ssl: *const SSL) -> c_int {
+        try_clone_arc!(ssl)
+            .get()
+            .get_key_update_type()
+            .unwrap_or(SSL_KEY_UPDATE_NONE)
+    }
+}
+
+const SSL_KEY_UPDATE_NONE: c_int = -1;
+const SSL_KEY_UPDATE_NOT_REQUESTED: c_int = 0;
+const SSL_KEY_UPDATE_REQUESTED: c_int = 1;
+
 entry! {
     pub fn _SSL_set_SSL_CTX(ssl: *mut SSL, ctx_ptr: *mut SSL_CTX) -> *mut SSL_CTX {
         let ctx = try_clone_arc!(ctx_ptr);
@@ -1310,6 +2062,38 @@ entry! {
     }
 }
 
+entry! {
+    pub fn _SSL_use_certificate_file(
+        ssl: *mut SSL,
+        file_name: *const c_char,
+        file_type: c_int,
+    ) -> c_int {
+        let ssl = try_clone_arc!(ssl);
+        let file_name = try_str!(file_name);
+
+        match use_cert_file(file_name, file_type) {
+            Ok(ee) => {
+                ssl.get_mut().stage_certificate_end_entity(ee);
+                C_INT_SUCCESS
+            }
+            Err(err) => err.raise().into(),
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_use_certificate_chain_file(ssl: *mut SSL, file_name: *const c_char) -> c_int {
+        let ssl = try_clone_arc!(ssl);
+        let chain = match use_cert_chain_file(try_str!(file_name)) {
+            Ok(chain) => chain,
+            Err(err) => return err.raise().into(),
+        };
+
+        ssl.get_mut().stage_certificate_chain(chain);
+        C_INT_SUCCESS
+    }
+}
+
 entry! {
     pub fn _SSL_use_certificate(ssl: *mut SSL, x: *mut X509) -> c_int {
         let ssl = try_clone_arc!(ssl);
@@ -1344,30 +2128,82 @@ entry! {
 }
 
 entry! {
-    pub fn _SSL_use_PrivateKey_file(
-        ssl: *mut SSL,
-        file_name: *const c_char,
-        file_type: c_int,
-    ) -> c_int {
+    pub fn _SSL_use_certificate_ASN1(ssl: *mut SSL, d: *const c_uchar, len: c_int) -> c_int {
         let ssl = try_clone_arc!(ssl);
-        let file_name = try_str!(file_name);
+        let der = try_slice_int!(d, len);
 
-        let key = match use_private_key_file(file_name, file_type) {
-            Ok(key) => key,
-            Err(err) => {
-                return err.raise().into();
+        match certificate_from_der(der) {
+            Ok(ee) => {
+                ssl.get_mut().stage_certificate_end_entity(ee);
+                C_INT_SUCCESS
             }
-        };
-
-        match ssl.get_mut().commit_private_key(key) {
             Err(e) => e.raise().into(),
-            Ok(()) => C_INT_SUCCESS,
         }
     }
 }
 
 entry! {
-    pub fn _SSL_check_private_key(_ssl: *const SSL) -> c_int {
+    pub fn _SSL_use_PrivateKey_ASN1(
+        pk: c_int,
+        ssl: *mut SSL,
+        d: *const c_uchar,
+        len: c_long,
+    ) -> c_int {
+        let ssl = try_clone_arc!(ssl);
+        let der = try_slice_int!(d, len);
+
+        match private_key_from_der(pk, der).and_then(|key| ssl.get_mut().commit_private_key(key)) {
+            Err(e) => e.raise().into(),
+            Ok(()) => C_INT_SUCCESS,
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_use_cert_and_key(
+        ssl: *mut SSL,
+        x509: *mut X509,
+        privatekey: *mut EVP_PKEY,
+        chain: *mut stack_st_X509,
+        override_: c_int,
+    ) -> c_int {
+        let ssl = try_clone_arc!(ssl);
+
+        match cert_and_key(x509, privatekey, chain).and_then(|(ee, chain, key)| {
+            ssl.get_mut()
+                .use_cert_and_key(ee, chain, key, override_ != 0)
+        }) {
+            Err(e) => e.raise().into(),
+            Ok(()) => C_INT_SUCCESS,
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_use_PrivateKey_file(
+        ssl: *mut SSL,
+        file_name: *const c_char,
+        file_type: c_int,
+    ) -> c_int {
+        let ssl = try_clone_arc!(ssl);
+        let file_name = try_str!(file_name);
+
+        let key = match use_private_key_file(file_name, file_type) {
+            Ok(key) => key,
+            Err(err) => {
+                return err.raise().into();
+            }
+        };
+
+        match ssl.get_mut().commit_private_key(key) {
+            Err(e) => e.raise().into(),
+            Ok(()) => C_INT_SUCCESS,
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_check_private_key(_ssl: *const SSL) -> c_int {
         log::trace!("not implemented: _SSL_check_private_key, returning success");
         C_INT_SUCCESS
     }
@@ -1394,17 +2230,148 @@ entry! {
 }
 
 entry! {
-    pub fn _SSL_set_verify(ssl: *mut SSL, mode: c_int, callback: SSL_verify_cb) {
+    pub fn _SSL_client_hello_isv2(ssl: *mut SSL) -> c_int {
+        let _ssl = try_clone_arc!(ssl);
+        // rustls does not accept SSLv2-format `ClientHello`s
+        0
+    }
+}
+
+entry! {
+    pub fn _SSL_client_hello_get0_legacy_version(ssl: *mut SSL) -> c_uint {
+        try_clone_arc!(ssl)
+            .get()
+            .client_hello()
+            .map(|hello| hello.legacy_version as c_uint)
+            .unwrap_or_default()
+    }
+}
+
+entry! {
+    pub fn _SSL_client_hello_get0_random(ssl: *mut SSL, out: *mut *const c_uchar) -> usize {
+        client_hello_field(ssl, out, |hello| &hello.random)
+    }
+}
+
+entry! {
+    pub fn _SSL_client_hello_get0_session_id(ssl: *mut SSL, out: *mut *const c_uchar) -> usize {
+        client_hello_field(ssl, out, |hello| &hello.session_id)
+    }
+}
+
+entry! {
+    pub fn _SSL_client_hello_get0_ciphers(ssl: *mut SSL, out: *mut *const c_uchar) -> usize {
+        client_hello_field(ssl, out, |hello| &hello.cipher_suites)
+    }
+}
+
+entry! {
+    pub fn _SSL_client_hello_get0_compression_methods(
+        ssl: *mut SSL,
+        out: *mut *const c_uchar,
+    ) -> usize {
+        client_hello_field(ssl, out, |hello| &hello.compression_methods)
+    }
+}
+
+/// Point `out` at the `field` of the `ClientHello`, and return its length.
+///
+/// Returns zero if there is no `ClientHello`.
+fn client_hello_field(
+    ssl: *mut SSL,
+    out: *mut *const c_uchar,
+    field: impl Fn(&ClientHello) -> &Vec<u8>,
+) -> usize {
+    let ssl = try_clone_arc!(ssl);
+    let ssl = ssl.get();
+    let Some(data) = ssl.client_hello().map(field) else {
+        return 0;
+    };
+
+    if !out.is_null() {
+        unsafe { ptr::write(out, data.as_ptr()) };
+    }
+    data.len()
+}
+
+entry! {
+    pub fn _SSL_client_hello_get1_extensions_present(
+        ssl: *mut SSL,
+        out: *mut *mut c_int,
+        outlen: *mut usize,
+    ) -> c_int {
+        if out.is_null() || outlen.is_null() {
+            return Error::null_pointer().raise().into();
+        }
+
+        let ssl = try_clone_arc!(ssl);
+        let ssl = ssl.get();
+        let Some(hello) = ssl.client_hello() else {
+            return Error::not_supported("no ClientHello available")
+                .raise()
+                .into();
+        };
+
+        if hello.extensions.is_empty() {
+            unsafe {
+                ptr::write(out, ptr::null_mut());
+                ptr::write(outlen, 0);
+            }
+            return C_INT_SUCCESS;
+        }
+
+        let count = hello.extensions.len();
+        let allocd = unsafe { OPENSSL_malloc(count * mem::size_of::<c_int>()) as *mut c_int };
+        if allocd.is_null() {
+            return Error::bad_data("SSL_client_hello_get1_extensions_present allocation failed")
+                .raise()
+                .into();
+        }
+
+        for (i, (typ, _)) in hello.extensions.iter().enumerate() {
+            unsafe { ptr::write(allocd.add(i), *typ as c_int) };
+        }
+        unsafe {
+            ptr::write(out, allocd);
+            ptr::write(outlen, count);
+        }
+        C_INT_SUCCESS
+    }
+}
+
+entry! {
+    pub fn _SSL_client_hello_get0_ext(
+        ssl: *mut SSL,
+        typ: c_uint,
+        out: *mut *const c_uchar,
+        outlen: *mut usize,
+    ) -> c_int {
         let ssl = try_clone_arc!(ssl);
+        let ssl = ssl.get();
+        let Some(data) = ssl
+            .client_hello()
+            .and_then(|hello| hello.extension(typ as u16))
+        else {
+            return 0;
+        };
 
-        if callback.is_some() {
-            // supporting verify callbacks would mean we need to fully use
-            // the openssl certificate verifier, because X509_STORE and
-            // X509_STORE_CTX are both in libcrypto.
-            return Error::not_supported("verify callback").raise().into();
+        unsafe {
+            if !out.is_null() {
+                ptr::write(out, data.as_ptr());
+            }
+            if !outlen.is_null() {
+                ptr::write(outlen, data.len());
+            }
         }
+        C_INT_SUCCESS
+    }
+}
 
+entry! {
+    pub fn _SSL_set_verify(ssl: *mut SSL, mode: c_int, callback: SSL_verify_cb) {
+        let ssl = try_clone_arc!(ssl);
         ssl.get_mut().set_verify(crate::VerifyMode::from(mode));
+        ssl.get_mut().set_verify_callback(callback);
     }
 }
 
@@ -1414,6 +2381,12 @@ entry! {
     }
 }
 
+entry! {
+    pub fn _SSL_get_verify_callback(ssl: *const SSL) -> SSL_verify_cb {
+        try_clone_arc!(ssl).get().get_verify_callback()
+    }
+}
+
 entry! {
     pub fn _SSL_set_verify_depth(ssl: *mut SSL, depth: c_int) {
         try_clone_arc!(ssl).get_mut().set_verify_depth(depth)
@@ -1426,6 +2399,32 @@ entry! {
     }
 }
 
+entry! {
+    pub fn _SSL_set_cipher_list(ssl: *mut SSL, s: *const c_char) -> c_int {
+        let rule_str = try_str!(s);
+        match try_clone_arc!(ssl).get_mut().set_cipher_list(rule_str) {
+            Ok(()) => C_INT_SUCCESS,
+            Err(e) => e.raise().into(),
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_set_ciphersuites(ssl: *mut SSL, s: *const c_char) -> c_int {
+        let names = try_str!(s);
+        match try_clone_arc!(ssl).get_mut().set_ciphersuites(names) {
+            Ok(()) => C_INT_SUCCESS,
+            Err(e) => e.raise().into(),
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_get_ciphers(ssl: *const SSL) -> *mut stack_st_SSL_CIPHER {
+        try_clone_arc!(ssl).get().get_ciphers()
+    }
+}
+
 entry! {
     pub fn _SSL_get_current_compression(_ssl: *const SSL) -> *const c_void {
         ptr::null()
@@ -1454,6 +2453,18 @@ entry! {
     }
 }
 
+entry! {
+    pub fn _SSL_set_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int {
+        let ssl = try_clone_arc!(ssl);
+        let session = match session.is_null() {
+            true => None,
+            false => Some(try_clone_arc!(session)),
+        };
+        ssl.get_mut().set_session(session);
+        C_INT_SUCCESS
+    }
+}
+
 impl Castable for SSL {
     type Ownership = OwnershipArc;
     type RustType = NotThreadSafe<SSL>;
@@ -1548,7 +2559,7 @@ entry! {
         }
 
         unsafe {
-            ptr::copy_nonoverlapping(description.as_ptr(), buf, required_len as usize);
+            ptr::copy_nonoverlapping(description.as_ptr(), buf, required_len);
         };
         buf
     }
@@ -1626,6 +2637,29 @@ entry! {
 
 pub type SSL_SESSION = crate::SslSession;
 
+entry! {
+    pub fn _SSL_SESSION_new() -> *mut SSL_SESSION {
+        let mut sess = SSL_SESSION::new(
+            vec![],
+            vec![],
+            vec![],
+            crate::cache::TimeBase::now(),
+            SSL_SESSION_DEFAULT_TIMEOUT,
+        );
+        // like openssl, this is not `X509_V_OK` until a peer is verified
+        sess.properties_mut().verify_result = X509_V_ERR_UNSPECIFIED as i64;
+        to_arc_mut_ptr(NotThreadSafe::new(sess))
+    }
+}
+
+entry! {
+    pub fn _SSL_SESSION_dup(src: *const SSL_SESSION) -> *mut SSL_SESSION {
+        let src = try_clone_arc!(src);
+        let dup = src.get().dup();
+        to_arc_mut_ptr(NotThreadSafe::new(dup))
+    }
+}
+
 entry! {
     pub fn _SSL_SESSION_get_id(sess: *const SSL_SESSION, len: *mut c_uint) -> *const c_uchar {
         if len.is_null() {
@@ -1697,719 +2731,3205 @@ entry! {
 }
 
 entry! {
-    pub fn _d2i_SSL_SESSION(
-        a: *mut *mut SSL_SESSION,
-        pp: *mut *const c_uchar,
-        length: c_long,
-    ) -> *mut SSL_SESSION {
-        if !a.is_null() {
-            return Error::not_supported("d2i_SSL_SESSION with a != NULL")
-                .raise()
-                .into();
-        }
-
-        if pp.is_null() {
-            return Error::bad_data("d2i_SSL_SESSION with pp == NULL")
-                .raise()
-                .into();
+    pub fn _SSL_SESSION_get0_id_context(
+        sess: *const SSL_SESSION,
+        len: *mut c_uint,
+    ) -> *const c_uchar {
+        let sess = try_clone_arc!(sess);
+        let context = sess.get().get_context();
+        if !len.is_null() {
+            unsafe { *len = context.len() as c_uint };
         }
-
-        let ptr = unsafe { ptr::read(pp) };
-        let slice = try_slice!(ptr, length);
-
-        let (sess, rest) = match SSL_SESSION::decode(slice) {
-            Some(r) => r,
-            None => {
-                return Error::bad_data("cannot decode SSL_SESSION").raise().into();
-            }
-        };
-        let consumed_bytes = slice.len() - rest.len();
-
-        // move along *pp
-        unsafe { ptr::write(pp, ptr.add(consumed_bytes)) };
-        to_arc_mut_ptr(NotThreadSafe::new(sess))
+        context.as_ptr()
     }
 }
 
 entry! {
-    pub fn _i2d_SSL_SESSION(sess: *const SSL_SESSION, pp: *mut *mut c_uchar) -> c_int {
-        let sess = try_clone_arc!(sess);
-        let encoded = sess.get().encode();
-
-        if !pp.is_null() {
-            let ptr = unsafe { ptr::read(pp) };
-            unsafe {
-                ptr::copy_nonoverlapping(encoded.as_ptr(), ptr, encoded.len());
-                ptr::write(pp, ptr.add(encoded.len()));
-            }
+    pub fn _SSL_SESSION_set1_id(
+        sess: *mut SSL_SESSION,
+        sid: *const c_uchar,
+        sid_len: c_uint,
+    ) -> c_int {
+        let slice = try_slice!(sid, sid_len);
+        if slice.len() > SSL_MAX_SSL_SESSION_ID_LENGTH {
+            return Error::bad_data("session id too long").raise().into();
         }
-        encoded.len() as c_int
+        try_clone_arc!(sess).get_mut().set_id(slice);
+        C_INT_SUCCESS
     }
 }
 
 entry! {
-    pub fn _SSL_SESSION_free(sess: *mut SSL_SESSION) {
-        free_arc(sess);
+    pub fn _SSL_SESSION_get0_cipher(sess: *const SSL_SESSION) -> *const SSL_CIPHER {
+        try_clone_arc!(sess)
+            .get()
+            .get_cipher()
+            .map(|cipher| cipher as *const SSL_CIPHER)
+            .unwrap_or_else(ptr::null)
     }
 }
 
-impl Castable for SSL_SESSION {
-    type Ownership = OwnershipArc;
-    type RustType = NotThreadSafe<SSL_SESSION>;
+entry! {
+    pub fn _SSL_SESSION_set_cipher(sess: *mut SSL_SESSION, cipher: *const SSL_CIPHER) -> c_int {
+        let cipher = unsafe { cipher.as_ref() };
+        try_clone_arc!(sess).get_mut().set_cipher(cipher);
+        C_INT_SUCCESS
+    }
 }
 
 entry! {
-    pub fn _SSL_CONF_CTX_new() -> *mut SSL_CONF_CTX {
-        to_boxed_mut_ptr(NotThreadSafe::new(conf::SslConfigCtx::new()))
+    pub fn _SSL_SESSION_get_protocol_version(sess: *const SSL_SESSION) -> c_int {
+        try_clone_arc!(sess).get().properties().version as c_int
     }
 }
 
 entry! {
-    pub fn _SSL_CONF_CTX_free(cctx: *mut SSL_CONF_CTX) {
-        free_box(cctx);
+    pub fn _SSL_SESSION_set_protocol_version(sess: *mut SSL_SESSION, version: c_int) -> c_int {
+        try_clone_arc!(sess).get_mut().properties_mut().version = version as u16;
+        C_INT_SUCCESS
     }
 }
 
 entry! {
-    pub fn _SSL_CONF_CTX_finish(cctx: *mut SSL_CONF_CTX) -> c_int {
-        match try_ref_from_ptr!(cctx).get_mut().finish() {
-            true => C_INT_SUCCESS,
-            false => 0,
-        }
+    pub fn _SSL_SESSION_get0_hostname(sess: *const SSL_SESSION) -> *const c_char {
+        let sess = try_clone_arc!(sess);
+        sess.get()
+            .properties()
+            .hostname
+            .as_ref()
+            .map(|hostname| hostname.as_ptr())
+            .unwrap_or_else(ptr::null)
     }
 }
 
 entry! {
-    pub fn _SSL_CONF_CTX_set_flags(cctx: *mut SSL_CONF_CTX, flags: c_uint) -> c_uint {
-        try_ref_from_ptr!(cctx).get_mut().set_flags(flags).into()
+    pub fn _SSL_SESSION_set1_hostname(sess: *mut SSL_SESSION, hostname: *const c_char) -> c_int {
+        let sess = try_clone_arc!(sess);
+        let hostname = match hostname.is_null() {
+            true => None,
+            false => Some(unsafe { CStr::from_ptr(hostname) }.to_owned()),
+        };
+        sess.get_mut().properties_mut().hostname = hostname;
+        C_INT_SUCCESS
     }
 }
 
 entry! {
-    pub fn _SSL_CONF_CTX_clear_flags(cctx: *mut SSL_CONF_CTX, flags: c_uint) -> c_uint {
-        try_ref_from_ptr!(cctx).get_mut().clear_flags(flags).into()
+    pub fn _SSL_SESSION_get0_alpn_selected(
+        sess: *const SSL_SESSION,
+        alpn: *mut *const c_uchar,
+        len: *mut usize,
+    ) {
+        if alpn.is_null() || len.is_null() {
+            return;
+        }
+
+        let sess = try_clone_arc!(sess);
+        match &sess.get().properties().alpn_selected {
+            Some(slice) => unsafe {
+                ptr::write(len, slice.len());
+                ptr::write(alpn, slice.as_ptr());
+            },
+            None => unsafe {
+                ptr::write(len, 0);
+                ptr::write(alpn, ptr::null());
+            },
+        }
     }
 }
 
 entry! {
-    pub fn _SSL_CONF_CTX_set1_prefix(cctx: *mut SSL_CONF_CTX, prefix: *mut c_char) -> c_int {
-        try_ref_from_ptr!(cctx)
-            .get_mut()
-            .set_prefix(try_str!(prefix));
+    pub fn _SSL_SESSION_set1_alpn_selected(
+        sess: *mut SSL_SESSION,
+        alpn: *const c_uchar,
+        len: usize,
+    ) -> c_int {
+        let sess = try_clone_arc!(sess);
+        let alpn = match alpn.is_null() || len == 0 {
+            true => None,
+            false => Some(try_slice!(alpn, len).to_vec()),
+        };
+        sess.get_mut().properties_mut().alpn_selected = alpn;
         C_INT_SUCCESS
     }
 }
 
 entry! {
-    pub fn _SSL_CONF_cmd(cctx: *mut SSL_CONF_CTX, cmd: *mut c_char, value: *mut c_char) -> c_int {
-        // Note: we use string_from_cstring here instead of try_str! because some commands
-        //       may allow NULL as a value.
-        let value = string_from_cstring(value);
-        try_ref_from_ptr!(cctx)
-            .get_mut()
-            .cmd(try_str!(cmd), value.as_deref())
+    pub fn _SSL_SESSION_has_ticket(sess: *const SSL_SESSION) -> c_int {
+        try_clone_arc!(sess).get().properties().has_ticket as c_int
     }
 }
 
 entry! {
-    pub fn _SSL_CONF_cmd_value_type(cctx: *mut SSL_CONF_CTX, cmd: *mut c_char) -> c_int {
-        try_ref_from_ptr!(cctx)
-            .get()
-            .cmd_value_type(try_str!(cmd))
-            .into()
+    pub fn _SSL_SESSION_get_ticket_lifetime_hint(sess: *const SSL_SESSION) -> c_ulong {
+        try_clone_arc!(sess).get().properties().ticket_lifetime_hint as c_ulong
     }
 }
 
 entry! {
-    pub fn _SSL_CONF_CTX_set_ssl(cctx: *mut SSL_CONF_CTX, ssl: *mut SSL) {
-        let cctx = try_ref_from_ptr!(cctx).get_mut();
-        match ssl.is_null() {
-            true => cctx.validation_only(),
-            false => cctx.apply_to_ssl(try_clone_arc!(ssl)),
-        }
+    pub fn _SSL_SESSION_is_resumable(sess: *const SSL_SESSION) -> c_int {
+        try_clone_arc!(sess).get().is_resumable() as c_int
     }
 }
 
 entry! {
-    pub fn _SSL_CONF_CTX_set_ssl_ctx(cctx: *mut SSL_CONF_CTX, ctx: *mut SSL_CTX) {
-        let cctx = try_ref_from_ptr!(cctx).get_mut();
-        match ctx.is_null() {
-            true => cctx.validation_only(),
-            false => cctx.apply_to_ctx(try_clone_arc!(ctx)),
-        }
+    pub fn _SSL_SESSION_get0_peer(sess: *mut SSL_SESSION) -> *mut X509 {
+        let sess = try_clone_arc!(sess);
+        sess.get()
+            .properties()
+            .peer
+            .as_ref()
+            .map(|x509| x509.borrow_ref())
+            .unwrap_or_else(ptr::null_mut)
     }
 }
 
-pub type SSL_CONF_CTX = conf::SslConfigCtx;
+entry! {
+    pub fn _SSL_SESSION_get_max_early_data(sess: *const SSL_SESSION) -> u32 {
+        try_clone_arc!(sess).get().properties().max_early_data
+    }
+}
 
-impl Castable for SSL_CONF_CTX {
-    type Ownership = OwnershipBox; // SSL_CONF_CTX does not do reference counting.
-    type RustType = NotThreadSafe<conf::SslConfigCtx>;
+entry! {
+    pub fn _SSL_SESSION_set_max_early_data(sess: *mut SSL_SESSION, max_early_data: u32) -> c_int {
+        try_clone_arc!(sess)
+            .get_mut()
+            .properties_mut()
+            .max_early_data = max_early_data;
+        C_INT_SUCCESS
+    }
 }
 
 entry! {
-    pub fn _X509_check_private_key(cert: *mut X509, pkey: *mut EVP_PKEY) -> c_int {
-        if cert.is_null() || pkey.is_null() {
-            return 0;
+    pub fn _SSL_SESSION_get_master_key(
+        sess: *const SSL_SESSION,
+        out: *mut c_uchar,
+        outlen: usize,
+    ) -> usize {
+        let sess = try_clone_arc!(sess);
+        let master_key = &sess.get().properties().master_key;
+        // "If outlen is 0, these functions return the maximum number of bytes they would copy"
+        if outlen == 0 {
+            return master_key.len();
         }
 
-        let chain = vec![CertificateDer::from(
-            OwnedX509::new_incref(cert).der_bytes(),
-        )];
-        let Ok(certified_key) = OpenSslCertifiedKey::new(chain, EvpPkey::new_incref(pkey)) else {
-            return 0;
-        };
+        let len = master_key.len().min(outlen);
+        if !out.is_null() {
+            unsafe { ptr::copy_nonoverlapping(master_key.as_ptr(), out, len) };
+        }
+        len
+    }
+}
 
-        if certified_key.keys_match() {
-            C_INT_SUCCESS
-        } else {
-            0
+entry! {
+    pub fn _SSL_SESSION_set1_master_key(
+        sess: *mut SSL_SESSION,
+        in_: *const c_uchar,
+        len: usize,
+    ) -> c_int {
+        let slice = try_slice!(in_, len);
+        if slice.len() > TLS13_MAX_RESUMPTION_PSK_LENGTH {
+            return 0;
         }
+        try_clone_arc!(sess).get_mut().properties_mut().master_key = slice.to_vec();
+        C_INT_SUCCESS
     }
 }
 
-/// Normal OpenSSL return value convention success indicator.
-///
-/// Compare [`crate::ffi::MysteriouslyOppositeReturnValue`].
-const C_INT_SUCCESS: c_int = 1;
+entry! {
+    pub fn _SSL_SESSION_get0_ticket_appdata(
+        sess: *mut SSL_SESSION,
+        data: *mut *mut c_void,
+        len: *mut usize,
+    ) -> c_int {
+        if data.is_null() || len.is_null() {
+            return Error::null_pointer().raise().into();
+        }
 
-pub(crate) const FILETYPE_PEM: c_int = 1;
-const FILETYPE_DER: c_int = 2;
+        let sess = try_clone_arc!(sess);
+        match &sess.get().properties().ticket_appdata {
+            Some(slice) => unsafe {
+                ptr::write(len, slice.len());
+                ptr::write(data, slice.as_ptr() as *mut c_void);
+            },
+            None => unsafe {
+                ptr::write(len, 0);
+                ptr::write(data, ptr::null_mut());
+            },
+        }
+        C_INT_SUCCESS
+    }
+}
 
-const SSL_MAX_SID_CTX_LENGTH: usize = 32;
+entry! {
+    pub fn _SSL_SESSION_set1_ticket_appdata(
+        sess: *mut SSL_SESSION,
+        data: *const c_void,
+        len: usize,
+    ) -> c_int {
+        let sess = try_clone_arc!(sess);
+        let data = match data.is_null() || len == 0 {
+            true => None,
+            false => Some(try_slice!(data as *const c_uchar, len).to_vec()),
+        };
+        sess.get_mut().properties_mut().ticket_appdata = data;
+        C_INT_SUCCESS
+    }
+}
 
-/// Define an enum that can round trip through a c_int, with no
-/// UB for unknown values.
-macro_rules! num_enum {
-    ($enum_vis:vis enum $enum_name:ident
-    { $( $enum_var:ident = $enum_val:expr ),* $(,)? }
-    ) => {
-        #[derive(Debug, PartialEq, Clone, Copy)]
-        $enum_vis enum $enum_name {
-            $( $enum_var),*
+entry! {
+    pub fn _d2i_SSL_SESSION(
+        a: *mut *mut SSL_SESSION,
+        pp: *mut *const c_uchar,
+        length: c_long,
+    ) -> *mut SSL_SESSION {
+        if !a.is_null() {
+            return Error::not_supported("d2i_SSL_SESSION with a != NULL")
+                .raise()
+                .into();
         }
 
-        impl From<$enum_name> for c_int {
-            fn from(item: $enum_name) -> Self {
-                match item {
-                    $( $enum_name::$enum_var => $enum_val),*
-                }
-            }
+        if pp.is_null() {
+            return Error::bad_data("d2i_SSL_SESSION with pp == NULL")
+                .raise()
+                .into();
         }
 
-        impl TryFrom<c_int> for $enum_name {
-            type Error = ();
-            fn try_from(i: c_int) -> Result<Self, ()> {
-                match i {
-                    $( $enum_val => Ok(Self::$enum_var), )*
-                    _ => Err(()),
-                }
+        let ptr = unsafe { ptr::read(pp) };
+        let slice = try_slice!(ptr, length);
+
+        let (sess, rest) = match SSL_SESSION::decode(slice) {
+            Some(r) => r,
+            None => {
+                return Error::bad_data("cannot decode SSL_SESSION").raise().into();
             }
-        }
+        };
+        let consumed_bytes = slice.len() - rest.len();
+
+        // move along *pp
+        unsafe { ptr::write(pp, ptr.add(consumed_bytes)) };
+        to_arc_mut_ptr(NotThreadSafe::new(sess))
     }
 }
 
-// See `ssl.h` for macros starting `SSL_CTRL_`, eg. `SSL_CTRL_SET_TLSEXT_HOSTNAME`
-num_enum! {
-    enum SslCtrl {
-        Mode = 33,
-        SetMsgCallbackArg = 16,
-        SetSessCacheSize = 42,
-        GetSessCacheSize = 43,
-        SetSessCacheMode = 44,
-        SetTlsExtServerNameCallback = 53,
-        SetTlsExtServerNameArg = 54,
-        SetTlsExtHostname = 55,
-        SetTlsExtTicketKeyCallback = 72,
-        SetChain = 88,
-        SetMinProtoVersion = 123,
-        SetMaxProtoVersion = 124,
-        GetMinProtoVersion = 130,
-        GetMaxProtoVersion = 131,
-        GetNegotiatedGroup = 134,
+entry! {
+    pub fn _i2d_SSL_SESSION(sess: *const SSL_SESSION, pp: *mut *mut c_uchar) -> c_int {
+        let sess = try_clone_arc!(sess);
+        let encoded = sess.get().encode();
+
+        if !pp.is_null() {
+            let ptr = unsafe { ptr::read(pp) };
+            if ptr.is_null() {
+                // "If *pp is NULL memory will be allocated for a buffer and the encoded
+                // data written to it. In this case *pp is not incremented and it points
+                // to the start of the data just written."
+                let allocd = unsafe { OPENSSL_malloc(encoded.len()) as *mut c_uchar };
+                if allocd.is_null() {
+                    return Error::bad_data("i2d_SSL_SESSION allocation failed")
+                        .raise()
+                        .into();
+                }
+                unsafe {
+                    ptr::copy_nonoverlapping(encoded.as_ptr(), allocd, encoded.len());
+                    ptr::write(pp, allocd);
+                }
+            } else {
+                unsafe {
+                    ptr::copy_nonoverlapping(encoded.as_ptr(), ptr, encoded.len());
+                    ptr::write(pp, ptr.add(encoded.len()));
+                }
+            }
+        }
+        encoded.len() as c_int
     }
 }
 
-// --- unimplemented stubs below here ---
+entry! {
+    pub fn _PEM_read_bio_SSL_SESSION(
+        bp: *mut BIO,
+        x: *mut *mut SSL_SESSION,
+        cb: pem_password_cb,
+        u: *mut c_void,
+    ) -> *mut SSL_SESSION {
+        if bp.is_null() {
+            return Error::null_pointer().raise().into();
+        }
 
-macro_rules! entry_stub {
-    (pub fn $name:ident($($args:tt)*);) => {
-        #[no_mangle]
-        pub extern "C" fn $name($($args)*) {
-            ffi_panic_boundary! {
-                Error::not_supported(stringify!($name)).raise().into()
+        let der = match bio::read_pem(bp, PEM_STRING_SSL_SESSION, cb, u) {
+            Some(der) => der,
+            None => return ptr::null_mut(),
+        };
+
+        let sess = match SSL_SESSION::decode(&der) {
+            Some((sess, _)) => to_arc_mut_ptr(NotThreadSafe::new(sess)),
+            None => {
+                return Error::bad_data("cannot decode SSL_SESSION").raise().into();
             }
-        }
-    };
-    (pub fn $name:ident($($args:tt)*) -> $ret:ty;) => {
-        #[no_mangle]
-        pub extern "C" fn $name($($args)*) -> $ret {
-            ffi_panic_boundary! {
-                Error::not_supported(stringify!($name)).raise().into()
+        };
+
+        // "If x is not NULL ... *x is freed and replaced with the result"
+        if !x.is_null() {
+            let old = unsafe { ptr::read(x) };
+            if !old.is_null() {
+                _SSL_SESSION_free(old);
             }
+            unsafe { ptr::write(x, sess) };
         }
-    };
+        sess
+    }
 }
 
-// things we support and should be able to implement to
-// some extent:
+entry! {
+    pub fn _PEM_read_SSL_SESSION(
+        fp: *mut FILE,
+        x: *mut *mut SSL_SESSION,
+        cb: pem_password_cb,
+        u: *mut c_void,
+    ) -> *mut SSL_SESSION {
+        if fp.is_null() {
+            return Error::null_pointer().raise().into();
+        }
 
-entry_stub! {
-    pub fn _SSL_get_ex_data_X509_STORE_CTX_idx() -> c_int;
+        match FileBio::new(fp) {
+            Some(bio) => _PEM_read_bio_SSL_SESSION(bio.as_ptr(), x, cb, u),
+            None => ptr::null_mut(),
+        }
+    }
 }
 
-entry_stub! {
-    pub fn _SSL_set_session(_ssl: *mut SSL, _session: *mut SSL_SESSION) -> c_int;
-}
+entry! {
+    pub fn _PEM_write_bio_SSL_SESSION(bp: *mut BIO, sess: *const SSL_SESSION) -> c_int {
+        if bp.is_null() {
+            return Error::null_pointer().raise().into();
+        }
 
-entry_stub! {
-    pub fn _SSL_CTX_remove_session(_ssl: *const SSL, _session: *mut SSL_SESSION) -> c_int;
+        let encoded = try_clone_arc!(sess).get().encode();
+        match bio::write_pem(bp, PEM_STRING_SSL_SESSION, &encoded) {
+            true => C_INT_SUCCESS,
+            false => 0,
+        }
+    }
 }
 
-entry_stub! {
-    pub fn _SSL_CTX_set_keylog_callback(_ctx: *mut SSL_CTX, _cb: SSL_CTX_keylog_cb_func);
+entry! {
+    pub fn _PEM_write_SSL_SESSION(fp: *mut FILE, sess: *const SSL_SESSION) -> c_int {
+        if fp.is_null() {
+            return Error::null_pointer().raise().into();
+        }
+
+        match FileBio::new(fp) {
+            Some(bio) => _PEM_write_bio_SSL_SESSION(bio.as_ptr(), sess),
+            None => 0,
+        }
+    }
 }
 
-pub type SSL_CTX_keylog_cb_func =
-    Option<unsafe extern "C" fn(ssl: *const SSL, line: *const c_char)>;
+entry! {
+    pub fn _SSL_SESSION_print(bp: *mut BIO, sess: *const SSL_SESSION) -> c_int {
+        if bp.is_null() {
+            return Error::null_pointer().raise().into();
+        }
 
-entry_stub! {
-    pub fn _SSL_CTX_add_client_CA(_ctx: *mut SSL_CTX, _x: *mut X509) -> c_int;
+        match try_clone_arc!(sess).get().print(bp) {
+            true => C_INT_SUCCESS,
+            false => 0,
+        }
+    }
 }
 
-entry_stub! {
-    pub fn _SSL_CTX_set_ciphersuites(_ctx: *mut SSL_CTX, _s: *const c_char) -> c_int;
-}
+entry! {
+    pub fn _SSL_SESSION_print_fp(fp: *mut FILE, sess: *const SSL_SESSION) -> c_int {
+        if fp.is_null() {
+            return Error::null_pointer().raise().into();
+        }
 
-entry_stub! {
-    pub fn _SSL_CTX_use_certificate_file(
-        _ctx: *mut SSL_CTX,
-        _file: *const c_char,
-        _type_: c_int,
-    ) -> c_int;
+        match FileBio::new(fp) {
+            Some(bio) => _SSL_SESSION_print(bio.as_ptr(), sess),
+            None => 0,
+        }
+    }
 }
 
-// The SSL_CTX X509_STORE isn't being meaningfully used yet.
-entry_stub! {
-    pub fn _SSL_CTX_set_default_verify_store(_ctx: *mut SSL_CTX) -> c_int;
-}
+entry! {
+    pub fn _SSL_SESSION_print_keylog(bp: *mut BIO, sess: *const SSL_SESSION) -> c_int {
+        if bp.is_null() {
+            return Error::null_pointer().raise().into();
+        }
+
+        match try_clone_arc!(sess).get().print_keylog(bp) {
+            true => C_INT_SUCCESS,
+            false => 0,
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_SESSION_free(sess: *mut SSL_SESSION) {
+        free_arc(sess);
+    }
+}
+
+impl Castable for SSL_SESSION {
+    type Ownership = OwnershipArc;
+    type RustType = NotThreadSafe<SSL_SESSION>;
+}
+
+entry! {
+    pub fn _SSL_CONF_CTX_new() -> *mut SSL_CONF_CTX {
+        to_boxed_mut_ptr(NotThreadSafe::new(conf::SslConfigCtx::new()))
+    }
+}
+
+entry! {
+    pub fn _SSL_CONF_CTX_free(cctx: *mut SSL_CONF_CTX) {
+        free_box(cctx);
+    }
+}
+
+entry! {
+    pub fn _SSL_CONF_CTX_finish(cctx: *mut SSL_CONF_CTX) -> c_int {
+        match try_ref_from_ptr!(cctx).get_mut().finish() {
+            true => C_INT_SUCCESS,
+            false => 0,
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_CONF_CTX_set_flags(cctx: *mut SSL_CONF_CTX, flags: c_uint) -> c_uint {
+        try_ref_from_ptr!(cctx).get_mut().set_flags(flags).into()
+    }
+}
+
+entry! {
+    pub fn _SSL_CONF_CTX_clear_flags(cctx: *mut SSL_CONF_CTX, flags: c_uint) -> c_uint {
+        try_ref_from_ptr!(cctx).get_mut().clear_flags(flags).into()
+    }
+}
+
+entry! {
+    pub fn _SSL_CONF_CTX_set1_prefix(cctx: *mut SSL_CONF_CTX, prefix: *mut c_char) -> c_int {
+        try_ref_from_ptr!(cctx)
+            .get_mut()
+            .set_prefix(try_str!(prefix));
+        C_INT_SUCCESS
+    }
+}
+
+entry! {
+    pub fn _SSL_CONF_cmd(cctx: *mut SSL_CONF_CTX, cmd: *mut c_char, value: *mut c_char) -> c_int {
+        // Note: we use string_from_cstring here instead of try_str! because some commands
+        //       may allow NULL as a value.
+        let value = string_from_cstring(value);
+        try_ref_from_ptr!(cctx)
+            .get_mut()
+            .cmd(try_str!(cmd), value.as_deref())
+    }
+}
+
+entry! {
+    pub fn _SSL_CONF_cmd_value_type(cctx: *mut SSL_CONF_CTX, cmd: *mut c_char) -> c_int {
+        try_ref_from_ptr!(cctx)
+            .get()
+            .cmd_value_type(try_str!(cmd))
+            .into()
+    }
+}
+
+entry! {
+    pub fn _SSL_CONF_CTX_set_ssl(cctx: *mut SSL_CONF_CTX, ssl: *mut SSL) {
+        let cctx = try_ref_from_ptr!(cctx).get_mut();
+        match ssl.is_null() {
+            true => cctx.validation_only(),
+            false => cctx.apply_to_ssl(try_clone_arc!(ssl)),
+        }
+    }
+}
+
+entry! {
+    pub fn _SSL_CONF_CTX_set_ssl_ctx(cctx: *mut SSL_CONF_CTX, ctx: *mut SSL_CTX) {
+        let cctx = try_ref_from_ptr!(cctx).get_mut();
+        match ctx.is_null() {
+            true => cctx.validation_only(),
+            false => cctx.apply_to_ctx(try_clone_arc!(ctx)),
+        }
+    }
+}
+
+pub type SSL_CONF_CTX = conf::SslConfigCtx;
+
+impl Castable for SSL_CONF_CTX {
+    type Ownership = OwnershipBox; // SSL_CONF_CTX does not do reference counting.
+    type RustType = NotThreadSafe<conf::SslConfigCtx>;
+}
+
+entry! {
+    pub fn _X509_check_private_key(cert: *mut X509, pkey: *mut EVP_PKEY) -> c_int {
+        if cert.is_null() || pkey.is_null() {
+            return 0;
+        }
+
+        let chain = vec![CertificateDer::from(
+            OwnedX509::new_incref(cert).der_bytes(),
+        )];
+        let Ok(certified_key) = OpenSslCertifiedKey::new(chain, EvpPkey::new_incref(pkey)) else {
+            return 0;
+        };
+
+        if certified_key.keys_match() {
+            C_INT_SUCCESS
+        } else {
+            0
+        }
+    }
+}
+
+/// Normal OpenSSL return value convention success indicator.
+///
+/// Compare [`crate::ffi::MysteriouslyOppositeReturnValue`].
+const C_INT_SUCCESS: c_int = 1;
+
+pub(crate) const FILETYPE_PEM: c_int = 1;
+const FILETYPE_DER: c_int = 2;
+
+const SSL_MAX_SID_CTX_LENGTH: usize = 32;
+
+const SSL_MAX_SSL_SESSION_ID_LENGTH: usize = 32;
+
+const TLS13_MAX_RESUMPTION_PSK_LENGTH: usize = 512;
+
+/// `SSL_SESSION_new` defaults to 5 minutes (and 4 seconds).
+const SSL_SESSION_DEFAULT_TIMEOUT: u64 = 304;
+
+const PEM_STRING_SSL_SESSION: &CStr = c"SSL SESSION PARAMETERS";
+
+/// Define an enum that can round trip through a c_int, with no
+/// UB for unknown values.
+macro_rules! num_enum {
+    ($enum_vis:vis enum $enum_name:ident
+    { $( $enum_var:ident = $enum_val:expr ),* $(,)? }
+    ) => {
+        #[derive(Debug, PartialEq, Clone, Copy)]
+        $enum_vis enum $enum_name {
+            $( $enum_var),*
+        }
+
+        impl From<$enum_name> for c_int {
+            fn from(item: $enum_name) -> Self {
+                match item {
+                    $( $enum_name::$enum_var => $enum_val),*
+                }
+            }
+        }
+
+        impl TryFrom<c_int> for $enum_name {
+            type Error = ();
+            fn try_from(i: c_int) -> Result<Self, ()> {
+                match i {
+                    $( $enum_val => Ok(Self::$enum_var), )*
+                    _ => Err(()),
+                }
+            }
+        }
+    }
+}
+
+// See `ssl.h` for macros starting `SSL_CTRL_`, eg. `SSL_CTRL_SET_TLSEXT_HOSTNAME`
+num_enum! {
+    enum SslCtrl {
+        Mode = 33,
+        SetMsgCallbackArg = 16,
+        ClearMode = 78,
+        SetSessCacheSize = 42,
+        GetSessCacheSize = 43,
+        SetSessCacheMode = 44,
+        SetTlsExtServerNameCallback = 53,
+        SetTlsExtServerNameArg = 54,
+        SetTlsExtHostname = 55,
+        SetTlsExtTicketKeyCallback = 72,
+        SetChain = 88,
+        SelectCurrentCert = 116,
+        SetCurrentCert = 117,
+        SetMinProtoVersion = 123,
+        SetMaxProtoVersion = 124,
+        GetMinProtoVersion = 130,
+        GetMaxProtoVersion = 131,
+        GetGroups = 90,
+        SetGroups = 91,
+        SetGroupsList = 92,
+        GetSharedGroup = 93,
+        SetSigalgsList = 98,
+        SetClientSigalgsList = 102,
+        GetNegotiatedGroup = 134,
+    }
+}
+
+/// Map the NID array given to `SSL_set1_groups` (etc) to `NamedGroup`s.
+fn groups_from_nids(nids: *const c_int, count: c_long) -> Result<Vec<NamedGroup>, Error> {
+    if nids.is_null() || count < 0 {
+        return Err(Error::null_pointer());
+    }
+
+    unsafe { core::slice::from_raw_parts(nids, count as usize) }
+        .iter()
+        .map(|nid| {
+            nid_to_named_group(*nid)
+                .ok_or_else(|| Error::bad_data(&format!("group nid {nid} cannot be set")))
+        })
+        .collect()
+}
+
+fn group_to_nid(group: NamedGroup) -> c_int {
+    named_group_to_nid(group).unwrap_or(TLSEXT_NID_UNKNOWN | u16::from(group) as c_int)
+}
+
+// --- unimplemented stubs below here ---
+
+macro_rules! entry_stub {
+    (pub fn $name:ident($($args:tt)*);) => {
+        #[no_mangle]
+        pub extern "C" fn $name($($args)*) {
+            ffi_panic_boundary! {
+                Error::not_supported(stringify!($name)).raise().into()
+            }
+        }
+    };
+    (pub fn $name:ident($($args:tt)*) -> $ret:ty;) => {
+        #[no_mangle]
+        pub extern "C" fn $name($($args)*) -> $ret {
+            ffi_panic_boundary! {
+                Error::not_supported(stringify!($name)).raise().into()
+            }
+        }
+    };
+}
+
+// things we support and should be able to implement to
+// some extent:
 
 entry_stub! {
-    pub fn _SSL_write_early_data(
-        _ssl: *mut SSL,
-        _buf: *const c_void,
-        _num: usize,
-        _written: *mut usize,
-    ) -> c_int;
+    pub fn _SSL_CTX_remove_session(_ssl: *const SSL, _session: *mut SSL_SESSION) -> c_int;
+}
+
+entry! {
+    pub fn _SSL_CTX_set_keylog_callback(ctx: *mut SSL_CTX, cb: SSL_CTX_keylog_cb_func) {
+        try_clone_arc!(ctx).get_mut().set_keylog_callback(cb);
+    }
+}
+
+entry! {
+    pub fn _SSL_CTX_get_keylog_callback(ctx: *const SSL_CTX) -> SSL_CTX_keylog_cb_func {
+        try_clone_arc!(ctx).get().get_keylog_callback()
+    }
+}
+
+pub type SSL_CTX_keylog_cb_func =
+    Option<unsafe extern "C" fn(ssl: *const SSL, line: *const c_char)>;
+
+entry_stub! {
+    pub fn _SSL_CTX_add_client_CA(_ctx: *mut SSL_CTX, _x: *mut X509) -> c_int;
+}
+
+// The SSL_CTX X509_STORE isn't being meaningfully used yet.
+entry_stub! {
+    pub fn _SSL_CTX_set_default_verify_store(_ctx: *mut SSL_CTX) -> c_int;
+}
+
+entry_stub! {
+    pub fn _SSL_CTX_get_client_CA_list(_ctx: *const SSL_CTX) -> *mut stack_st_X509_NAME;
 }
 
-entry_stub! {
-    pub fn _SSL_read_early_data(
-        _ssl: *mut SSL,
-        _buf: *mut c_void,
-        _num: usize,
-        _readbytes: *mut usize,
-    ) -> c_int;
-}
+entry_stub! {
+    pub fn _SSL_CTX_set_client_CA_list(_ctx: *mut SSL_CTX, _name_list: *mut stack_st_X509_NAME);
+}
+
+entry_stub! {
+    pub fn _SSL_load_client_CA_file(_file: *const c_char) -> *mut stack_st_X509_NAME;
+}
+
+entry! {
+    pub fn _SSL_CTX_set_msg_callback(ctx: *mut SSL_CTX, cb: SSL_CTX_msg_cb_func) {
+        try_clone_arc!(ctx).get_mut().set_msg_callback(cb);
+    }
+}
+
+entry! {
+    pub fn _SSL_set_msg_callback(ssl: *mut SSL, cb: SSL_CTX_msg_cb_func) {
+        try_clone_arc!(ssl).get_mut().set_msg_callback(cb);
+    }
+}
+
+pub type SSL_CTX_msg_cb_func = Option<
+    unsafe extern "C" fn(
+        write_p: c_int,
+        version: c_int,
+        content_type: c_int,
+        buf: *const c_void,
+        len: usize,
+        ssl: *mut SSL,
+        arg: *mut c_void,
+    ),
+>;
+
+entry! {
+    pub fn _SSL_CTX_set_info_callback(ctx: *mut SSL_CTX, cb: SSL_info_callback_func) {
+        try_clone_arc!(ctx).get_mut().set_info_callback(cb);
+    }
+}
+
+entry! {
+    pub fn _SSL_CTX_get_info_callback(ctx: *const SSL_CTX) -> SSL_info_callback_func {
+        try_clone_arc!(ctx).get().get_info_callback()
+    }
+}
+
+entry! {
+    pub fn _SSL_set_info_callback(ssl: *mut SSL, cb: SSL_info_callback_func) {
+        try_clone_arc!(ssl).get_mut().set_info_callback(cb);
+    }
+}
+
+entry! {
+    pub fn _SSL_get_info_callback(ssl: *const SSL) -> SSL_info_callback_func {
+        try_clone_arc!(ssl).get().get_info_callback()
+    }
+}
+
+pub type SSL_info_callback_func =
+    Option<unsafe extern "C" fn(ssl: *const SSL, type_: c_int, val: c_int)>;
+
+// no NPN (obsolete precursor to ALPN)
+
+entry_stub! {
+    pub fn _SSL_CTX_set_next_proto_select_cb(
+        _ctx: *mut SSL_CTX,
+        _cb: SSL_CTX_npn_select_cb_func,
+        _arg: *mut c_void,
+    );
+}
+
+pub type SSL_CTX_npn_select_cb_func = Option<
+    unsafe extern "C" fn(
+        s: *mut SSL,
+        out: *mut *mut c_uchar,
+        outlen: *mut c_uchar,
+        in_: *const c_uchar,
+        inlen: c_uint,
+        arg: *mut c_void,
+    ) -> c_int,
+>;
+
+entry_stub! {
+    pub fn _SSL_get0_next_proto_negotiated(
+        _ssl: *const SSL,
+        _data: *mut *const c_uchar,
+        _len: *mut c_uint,
+    );
+}
+
+entry_stub! {
+    pub fn _SSL_CTX_set_next_protos_advertised_cb(
+        _ctx: *mut SSL_CTX,
+        _cb: SSL_CTX_npn_advertised_cb_func,
+        _arg: *mut c_void,
+    );
+}
+
+pub type SSL_CTX_npn_advertised_cb_func = Option<
+    unsafe extern "C" fn(
+        ssl: *mut SSL,
+        out: *mut *const c_uchar,
+        outlen: *mut c_uint,
+        arg: *mut c_void,
+    ) -> c_int,
+>;
+
+// no password-protected key loading
+
+entry_stub! {
+    pub fn _SSL_CTX_set_default_passwd_cb(_ctx: *mut SSL_CTX, _cb: pem_password_cb);
+}
+
+pub type pem_password_cb = Option<
+    unsafe extern "C" fn(
+        buf: *mut c_char,
+        size: c_int,
+        rwflag: c_int,
+        userdata: *mut c_void,
+    ) -> c_int,
+>;
+
+entry_stub! {
+    pub fn _SSL_CTX_set_default_passwd_cb_userdata(_ctx: *mut SSL_CTX, _u: *mut c_void);
+}
+
+// no SRP
+
+entry_stub! {
+    pub fn _SSL_CTX_set_srp_password(_ctx: *mut SSL_CTX, _password: *mut c_char) -> c_int;
+}
+
+entry_stub! {
+    pub fn _SSL_CTX_set_srp_username(_ctx: *mut SSL_CTX, _name: *mut c_char) -> c_int;
+}
+
+// no post-handshake auth
+
+entry_stub! {
+    pub fn _SSL_CTX_set_post_handshake_auth(_ctx: *mut SSL_CTX, _val: c_int);
+}
+
+entry_stub! {
+    pub fn _SSL_set_post_handshake_auth(_s: *mut SSL, _val: c_int);
+}
+
+// No kTLS/sendfile support
+
+entry_stub! {
+    pub fn _SSL_sendfile(
+        _ssl: *mut SSL,
+        _fd: c_int,
+        _offset: c_long,
+        _size: usize,
+        _flags: c_int,
+    ) -> c_long;
+}
+
+// ---------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{
+        SSL_CB_EXIT, SSL_CB_HANDSHAKE_DONE, SSL_CB_HANDSHAKE_START, SSL_CB_LOOP, SSL_CB_READ_ALERT,
+        SSL_CB_WRITE_ALERT, SSL_ST_ACCEPT, SSL_ST_CONNECT,
+    };
+    use openssl_sys::{
+        NID_X9_62_id_ecPublicKey, NID_ecdsa_with_SHA384, NID_rsassaPss, NID_sha384, EVP_PKEY_EC,
+        EVP_PKEY_RSA, SSL_CTRL_MODE, SSL_CTRL_SET_MAX_PROTO_VERSION, SSL_CTRL_SET_SESS_CACHE_MODE,
+        SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER, SSL_MODE_ENABLE_PARTIAL_WRITE,
+        SSL_READ_EARLY_DATA_ERROR, SSL_SESS_CACHE_CLIENT, SSL_SESS_CACHE_NO_INTERNAL,
+        SSL_SESS_CACHE_NO_INTERNAL_STORE, SSL_SESS_CACHE_OFF, SSL_SESS_CACHE_SERVER,
+        TLS1_2_VERSION, TLS1_3_VERSION, X509_V_ERR_APPLICATION_VERIFICATION,
+        X509_V_ERR_CERT_CHAIN_TOO_LONG, X509_V_ERR_SELF_SIGNED_CERT_IN_CHAIN,
+        X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY, X509_V_OK,
+    };
+    use std::cell::RefCell;
+    use std::ffi::CString;
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    /// A `SSL_CTX` serving `test-ca/{key_type}/server.cert`.
+    fn server_ctx(key_type: &str) -> *mut SSL_CTX {
+        let ctx = _SSL_CTX_new(_TLS_server_method());
+        use_server_cert(ctx, key_type);
+        ctx
+    }
+
+    /// Add the ECDSA and then the RSA server certificates and keys to `ctx`,
+    /// returning each `(cert, key)` as it becomes current.
+    fn cert_and_key_pairs(ctx: *mut SSL_CTX) -> [(*mut X509, *mut EVP_PKEY); 2] {
+        ["ecdsa-p256", "rsa"].map(|key_type| {
+            use_server_cert(ctx, key_type);
+            (
+                _SSL_CTX_get0_certificate(ctx),
+                _SSL_CTX_get0_privatekey(ctx),
+            )
+        })
+    }
+
+    /// Add `test-ca/{key_type}/server.cert` and its key to `ctx`.
+    fn use_server_cert(ctx: *mut SSL_CTX, key_type: &str) {
+        let cert = CString::new(format!("test-ca/{key_type}/server.cert")).unwrap();
+        let key = CString::new(format!("test-ca/{key_type}/server.key")).unwrap();
+        assert_eq!(
+            _SSL_CTX_use_certificate_chain_file(ctx, cert.as_ptr()),
+            C_INT_SUCCESS
+        );
+        assert_eq!(
+            _SSL_CTX_use_PrivateKey_file(ctx, key.as_ptr(), FILETYPE_PEM),
+            C_INT_SUCCESS
+        );
+    }
+
+    /// A `SSL_CTX` trusting `test-ca/{key_type}/ca.cert`.
+    fn client_ctx(key_type: &str) -> *mut SSL_CTX {
+        let ctx = _SSL_CTX_new(_TLS_client_method());
+        let ca = CString::new(format!("test-ca/{key_type}/ca.cert")).unwrap();
+        assert_eq!(_SSL_CTX_load_verify_file(ctx, ca.as_ptr()), C_INT_SUCCESS);
+        ctx
+    }
+
+    /// Make a client and server `SSL` joined by a BIO pair with `buffer` bytes
+    /// of buffering in each direction (zero for the default).
+    fn connect_pair(
+        client_ctx: *mut SSL_CTX,
+        server_ctx: *mut SSL_CTX,
+        buffer: usize,
+    ) -> (*mut SSL, *mut SSL) {
+        let (client, server) = (_SSL_new(client_ctx), _SSL_new(server_ctx));
+        let (mut client_bio, mut server_bio) = (ptr::null_mut(), ptr::null_mut());
+        assert_eq!(
+            unsafe { BIO_new_bio_pair(&mut client_bio, buffer, &mut server_bio, buffer) },
+            1
+        );
+        _SSL_set_bio(client, client_bio, client_bio);
+        _SSL_set_bio(server, server_bio, server_bio);
+        _SSL_set_connect_state(client);
+        _SSL_set_accept_state(server);
+        (client, server)
+    }
+
+    /// Drive both sides of a handshake to completion, returning false if
+    /// either fails.
+    fn handshake(client: *mut SSL, server: *mut SSL) -> bool {
+        let (mut client_done, mut server_done) = (false, false);
+        for _ in 0..50 {
+            for (ssl, done) in [(client, &mut client_done), (server, &mut server_done)] {
+                if *done {
+                    continue;
+                }
+                match _SSL_do_handshake(ssl) {
+                    1 => {
+                        assert_eq!(_SSL_is_init_finished(ssl), 1);
+                        *done = true;
+                    }
+                    ret if matches!(
+                        _SSL_get_error(ssl, ret),
+                        SSL_ERROR_WANT_READ | SSL_ERROR_WANT_WRITE
+                    ) => {}
+                    _ => return false,
+                }
+            }
+            if client_done && server_done {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Free the `SSL`s and `SSL_CTX`s of a test connection.
+    fn free_pair(client: *mut SSL, server: *mut SSL, ctxs: &[*mut SSL_CTX]) {
+        _SSL_free(client);
+        _SSL_free(server);
+        for ctx in ctxs {
+            _SSL_CTX_free(*ctx);
+        }
+    }
+
+    /// Collect the output of an `i2d_*` function.
+    fn der_bytes(i2d: impl Fn(*mut *mut u8) -> c_int) -> Vec<u8> {
+        let mut der = vec![0u8; i2d(ptr::null_mut()) as usize];
+        let mut out = der.as_mut_ptr();
+        assert_eq!(i2d(&mut out) as usize, der.len());
+        der
+    }
+
+    const SSL_ERROR_WANT_READ: c_int = 2;
+    const SSL_ERROR_WANT_WRITE: c_int = 3;
+
+    extern "C" {
+        fn BIO_new_bio_pair(
+            bio1: *mut *mut BIO,
+            writebuf1: usize,
+            bio2: *mut *mut BIO,
+            writebuf2: usize,
+        ) -> c_int;
+        fn BIO_new(method: *const crate::bio::BIO_METHOD) -> *mut BIO;
+        fn BIO_s_mem() -> *const crate::bio::BIO_METHOD;
+        fn BIO_read(bio: *mut BIO, data: *mut c_void, len: c_int) -> c_int;
+        fn BIO_free(bio: *mut BIO) -> c_int;
+    }
+
+    /// Take everything written so far to the memory BIO `bio`.
+    fn read_mem_bio(bio: *mut BIO) -> String {
+        let mut buf = [0u8; 4096];
+        let len = unsafe { BIO_read(bio, buf.as_mut_ptr() as *mut c_void, buf.len() as c_int) };
+        String::from_utf8(buf[..len.max(0) as usize].to_vec()).unwrap()
+    }
+
+    thread_local! {
+        /// `(preverify_ok, error, depth, has_ssl)` for each verify callback call.
+        static VERIFY_CALLS: RefCell<Vec<(c_int, c_int, c_int, bool)>> = const { RefCell::new(vec![]) };
+    }
+
+    thread_local! {
+        /// `(ssl, line)` for each keylog callback call.
+        static KEYLOG_LINES: RefCell<Vec<(*const SSL, String)>> = const { RefCell::new(vec![]) };
+    }
+
+    extern "C" fn record_keylog(ssl: *const SSL, line: *const c_char) {
+        let line = unsafe { CStr::from_ptr(line) }.to_str().unwrap().to_owned();
+        KEYLOG_LINES.with_borrow_mut(|lines| lines.push((ssl, line)));
+    }
+
+    thread_local! {
+        /// `(ssl, from_ctx, where, ret)` for each info callback call.
+        static INFO_CALLS: RefCell<Vec<(*const SSL, bool, c_int, c_int)>> = const { RefCell::new(vec![]) };
+    }
+
+    extern "C" fn record_ctx_info(ssl: *const SSL, where_: c_int, ret: c_int) {
+        INFO_CALLS.with_borrow_mut(|calls| calls.push((ssl, true, where_, ret)));
+    }
+
+    extern "C" fn record_ssl_info(ssl: *const SSL, where_: c_int, ret: c_int) {
+        INFO_CALLS.with_borrow_mut(|calls| calls.push((ssl, false, where_, ret)));
+    }
+
+    /// `(write_p, version, content_type, buf)` of a message callback call.
+    type MsgCall = (c_int, c_int, c_int, Vec<u8>);
+
+    thread_local! {
+        static MSG_CALLS: RefCell<Vec<MsgCall>> = const { RefCell::new(vec![]) };
+    }
+
+    extern "C" fn record_msg(
+        write_p: c_int,
+        version: c_int,
+        content_type: c_int,
+        buf: *const c_void,
+        len: usize,
+        _ssl: *mut SSL,
+        _arg: *mut c_void,
+    ) {
+        let buf = unsafe { core::slice::from_raw_parts(buf as *const u8, len) }.to_vec();
+        MSG_CALLS.with_borrow_mut(|calls| calls.push((write_p, version, content_type, buf)));
+    }
+
+    thread_local! {
+        /// The `SSL` of each new session callback call.
+        static NEW_SESSIONS: RefCell<Vec<*mut SSL>> = const { RefCell::new(vec![]) };
+    }
+
+    extern "C" fn record_new_session(ssl: *mut SSL, _sess: *mut SSL_SESSION) -> c_int {
+        NEW_SESSIONS.with_borrow_mut(|sessions| sessions.push(ssl));
+        0
+    }
+
+    thread_local! {
+        /// The `i2d_SSL_SESSION` encoding of each session given to `store_session`.
+        static STORED_SESSIONS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(vec![]) };
+    }
+
+    extern "C" fn store_session(_ssl: *mut SSL, sess: *mut SSL_SESSION) -> c_int {
+        let mut der = vec![0u8; _i2d_SSL_SESSION(sess, ptr::null_mut()) as usize];
+        _i2d_SSL_SESSION(sess, &mut der.as_mut_ptr());
+        STORED_SESSIONS.with_borrow_mut(|stored| stored.push(der));
+        0
+    }
+
+    unsafe extern "C" fn load_session(
+        _ssl: *mut SSL,
+        data: *const c_uchar,
+        len: c_int,
+        copy: *mut c_int,
+    ) -> *mut SSL_SESSION {
+        let id = unsafe { core::slice::from_raw_parts(data, len as usize) };
+        unsafe { *copy = 0 };
+        STORED_SESSIONS.with_borrow(|stored| {
+            for der in stored {
+                let sess =
+                    _d2i_SSL_SESSION(ptr::null_mut(), &mut der.as_ptr(), der.len() as c_long);
+                let mut sess_id_len = 0;
+                let sess_id = _SSL_SESSION_get_id(sess, &mut sess_id_len);
+                if unsafe { core::slice::from_raw_parts(sess_id, sess_id_len as usize) } == id {
+                    return sess;
+                }
+                _SSL_SESSION_free(sess);
+            }
+            ptr::null_mut()
+        })
+    }
+
+    extern "C" fn switch_ctx(ssl: *mut SSL, _ad: *mut c_int, ctx: *mut c_void) -> c_int {
+        assert_eq!(
+            _SSL_set_SSL_CTX(ssl, ctx as *mut SSL_CTX),
+            ctx as *mut SSL_CTX
+        );
+        0
+    }
+
+    extern "C" fn refuse_early_data(_ssl: *mut SSL, _arg: *mut c_void) -> c_int {
+        0
+    }
+
+    extern "C" fn record_verify(ok: c_int, ctx: *mut X509_STORE_CTX) -> c_int {
+        let call = unsafe {
+            (
+                ok,
+                openssl_sys::X509_STORE_CTX_get_error(ctx),
+                openssl_sys::X509_STORE_CTX_get_error_depth(ctx),
+                !openssl_sys::X509_STORE_CTX_get_ex_data(
+                    ctx,
+                    _SSL_get_ex_data_X509_STORE_CTX_idx(),
+                )
+                .is_null(),
+            )
+        };
+        VERIFY_CALLS.with_borrow_mut(|calls| calls.push(call));
+        ok
+    }
+
+    extern "C" fn accept_verify(ok: c_int, ctx: *mut X509_STORE_CTX) -> c_int {
+        record_verify(ok, ctx);
+        1
+    }
+
+    extern "C" fn reject_verify(ok: c_int, ctx: *mut X509_STORE_CTX) -> c_int {
+        record_verify(ok, ctx);
+        0
+    }
+
+    /// A typical `SSL_CTX_set_cert_verify_callback` callback, which defers to
+    /// `X509_verify_cert` after counting its calls in `*arg`.
+    extern "C" fn defer_cert_verify(ctx: *mut X509_STORE_CTX, arg: *mut c_void) -> c_int {
+        unsafe {
+            *(arg as *mut c_int) += 1;
+            openssl_sys::X509_verify_cert(ctx)
+        }
+    }
+
+    /// A `SSL_CTX_set_cert_verify_callback` callback that rejects every chain,
+    /// without saying why.
+    extern "C" fn reject_cert_verify(_ctx: *mut X509_STORE_CTX, _arg: *mut c_void) -> c_int {
+        0
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        _SSL_CTX_set_verify(client_ctx, 1, None);
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert!(handshake(client, server));
+        assert_eq!(_SSL_get_verify_result(client), 0);
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
+
+    #[test]
+    fn test_client_session_resumption() {
+        let client_ctx = client_ctx("rsa");
+        let (server_ctx, other_server_ctx) = (server_ctx("rsa"), server_ctx("ecdsa-p256"));
+        _SSL_CTX_ctrl(
+            client_ctx,
+            SSL_CTRL_SET_SESS_CACHE_MODE,
+            SSL_SESS_CACHE_CLIENT,
+            ptr::null_mut(),
+        );
+        _SSL_CTX_sess_set_new_cb(client_ctx, Some(record_new_session));
+
+        // connect (offering `session`) and then read the server's tickets
+        let connect = |server_ctx, session: *mut SSL_SESSION| {
+            let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+            if !session.is_null() {
+                assert_eq!(_SSL_set_session(client, session), 1);
+                _SSL_SESSION_free(session);
+            }
+            assert!(handshake(client, server));
+            let mut buf = [0u8; 1];
+            let ret = _SSL_read(client, buf.as_mut_ptr() as *mut c_void, 1);
+            assert_eq!(_SSL_get_error(client, ret), SSL_ERROR_WANT_READ);
+            (client, server)
+        };
+        let peer_signature_type = |ssl| {
+            let mut nid = 0;
+            _SSL_get_peer_signature_type_nid(ssl, &mut nid);
+            nid
+        };
+
+        NEW_SESSIONS.take();
+        let (first, first_server) = connect(server_ctx, ptr::null_mut());
+        assert_eq!(_SSL_session_reused(first), 0);
+        assert_eq!(NEW_SESSIONS.take(), vec![first, first]);
+
+        let (resumed, resumed_server) = connect(server_ctx, _SSL_get1_session(first));
+        assert_eq!(_SSL_session_reused(resumed), 1);
+        let sessions = NEW_SESSIONS.take();
+        assert!(!sessions.is_empty());
+        assert!(sessions.iter().all(|ssl| *ssl == resumed));
+        // like OpenSSL, the verify result is the session's.
+        assert_eq!(_SSL_get_verify_result(resumed), X509_V_OK as c_long);
+        assert_eq!(peer_signature_type(resumed), 0);
+
+        // this server cannot resume the session, nor is its certificate trusted:
+        // its verification must not be seen by the others, though they share a
+        // verifier to allow resumption.
+        let (declined, declined_server) = connect(other_server_ctx, _SSL_get1_session(resumed));
+        assert_eq!(_SSL_session_reused(declined), 0);
+        assert_eq!(
+            _SSL_get_verify_result(declined),
+            X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY as c_long
+        );
+        assert_eq!(peer_signature_type(declined), NID_X9_62_id_ecPublicKey);
+
+        assert_eq!(_SSL_get_verify_result(first), X509_V_OK as c_long);
+        assert_eq!(peer_signature_type(first), NID_rsassaPss);
+        assert_eq!(_SSL_get_verify_result(resumed), X509_V_OK as c_long);
+        assert_eq!(peer_signature_type(resumed), 0);
+
+        free_pair(first, first_server, &[]);
+        free_pair(resumed, resumed_server, &[]);
+        free_pair(
+            declined,
+            declined_server,
+            &[client_ctx, server_ctx, other_server_ctx],
+        );
+    }
+
+    #[test]
+    fn test_client_automatic_resumption() {
+        for (mode, reused) in [
+            (SSL_SESS_CACHE_CLIENT, 1),
+            (SSL_SESS_CACHE_CLIENT | SSL_SESS_CACHE_NO_INTERNAL_STORE, 0),
+        ] {
+            let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+            _SSL_CTX_ctrl(
+                client_ctx,
+                SSL_CTRL_SET_SESS_CACHE_MODE,
+                mode,
+                ptr::null_mut(),
+            );
+
+            // without `SSL_set_session`, the second connection resumes with a
+            // ticket the first received.
+            let mut sessions_reused = vec![];
+            for _ in 0..2 {
+                let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+                assert!(handshake(client, server));
+                let mut buf = [0u8; 1];
+                let ret = _SSL_read(client, buf.as_mut_ptr() as *mut c_void, 1);
+                assert_eq!(_SSL_get_error(client, ret), SSL_ERROR_WANT_READ);
+                sessions_reused.push(_SSL_session_reused(client));
+                assert_eq!(_SSL_get_verify_result(client), X509_V_OK as c_long);
+                free_pair(client, server, &[]);
+            }
+            assert_eq!(sessions_reused, vec![0, reused]);
+            _SSL_CTX_free(client_ctx);
+            _SSL_CTX_free(server_ctx);
+        }
+    }
+
+    #[test]
+    fn test_server_session_external_cache() {
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        _SSL_CTX_set_options(server_ctx, crate::SSL_OP_NO_TICKET);
+        _SSL_CTX_ctrl(
+            server_ctx,
+            SSL_CTRL_SET_SESS_CACHE_MODE,
+            SSL_SESS_CACHE_SERVER | SSL_SESS_CACHE_NO_INTERNAL,
+            ptr::null_mut(),
+        );
+        _SSL_CTX_sess_set_new_cb(server_ctx, Some(store_session));
+        _SSL_CTX_sess_set_get_cb(server_ctx, Some(load_session));
+
+        STORED_SESSIONS.take();
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert!(handshake(client, server));
+        let mut buf = [0u8; 1];
+        let ret = _SSL_read(client, buf.as_mut_ptr() as *mut c_void, 1);
+        assert_eq!(_SSL_get_error(client, ret), SSL_ERROR_WANT_READ);
+        let session = _SSL_get1_session(client);
+        free_pair(client, server, &[]);
+
+        // the application sees the connection's properties in each session
+        // rustls stores, and can give their encodings back for resumption
+        let stored = STORED_SESSIONS.with_borrow(|stored| stored.clone());
+        assert!(!stored.is_empty());
+        for der in &stored {
+            let sess = _d2i_SSL_SESSION(ptr::null_mut(), &mut der.as_ptr(), der.len() as c_long);
+            assert_eq!(_SSL_SESSION_get_protocol_version(sess), TLS1_3_VERSION);
+            assert!(!_SSL_SESSION_get0_cipher(sess).is_null());
+            _SSL_SESSION_free(sess);
+        }
+
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert_eq!(_SSL_set_session(client, session), 1);
+        _SSL_SESSION_free(session);
+        assert!(handshake(client, server));
+        assert_eq!(_SSL_session_reused(client), 1);
+        assert_eq!(_SSL_session_reused(server), 1);
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
+
+    #[test]
+    fn test_server_cert_selection() {
+        let server_ctx = server_ctx("rsa");
+        use_server_cert(server_ctx, "ecdsa-p256");
+        let ecdsa = _SSL_CTX_get0_certificate(server_ctx);
+        let set_sigalgs = c_int::from(SslCtrl::SetSigalgsList);
+
+        // (client trusted CA, client sigalgs, client TLS1.2 cipher list)
+        // -> (version, cipher, server key is ECDSA, peer signature type)
+        let cases = [
+            (
+                "ecdsa-p256",
+                Some(c"ECDSA+SHA256"),
+                None,
+                (
+                    c"TLSv1.3",
+                    c"TLS_AES_256_GCM_SHA384",
+                    true,
+                    NID_X9_62_id_ecPublicKey,
+                ),
+            ),
+            (
+                "rsa",
+                Some(c"RSA-PSS+SHA256:RSA+SHA256"),
+                None,
+                (c"TLSv1.3", c"TLS_AES_256_GCM_SHA384", false, NID_rsassaPss),
+            ),
+            (
+                "rsa",
+                None,
+                Some(c"ECDHE-RSA-AES128-GCM-SHA256"),
+                (
+                    c"TLSv1.2",
+                    c"ECDHE-RSA-AES128-GCM-SHA256",
+                    false,
+                    NID_rsassaPss,
+                ),
+            ),
+            (
+                "ecdsa-p256",
+                None,
+                Some(c"ECDHE-ECDSA-AES128-GCM-SHA256"),
+                (
+                    c"TLSv1.2",
+                    c"ECDHE-ECDSA-AES128-GCM-SHA256",
+                    true,
+                    NID_X9_62_id_ecPublicKey,
+                ),
+            ),
+        ];
+        for (ca, sigalgs, ciphers, expected) in cases {
+            let client_ctx = client_ctx(ca);
+            if let Some(sigalgs) = sigalgs {
+                let list = sigalgs.as_ptr() as *mut c_void;
+                assert_eq!(_SSL_CTX_ctrl(client_ctx, set_sigalgs, 0, list), 1);
+            }
+            if let Some(ciphers) = ciphers {
+                _SSL_CTX_ctrl(
+                    client_ctx,
+                    SSL_CTRL_SET_MAX_PROTO_VERSION,
+                    TLS1_2_VERSION as c_long,
+                    ptr::null_mut(),
+                );
+                assert_eq!(_SSL_CTX_set_cipher_list(client_ctx, ciphers.as_ptr()), 1);
+            }
+
+            let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+            assert!(handshake(client, server));
+            let mut nid = 0;
+            assert_eq!(_SSL_get_peer_signature_type_nid(client, &mut nid), 1);
+            let actual = unsafe {
+                (
+                    CStr::from_ptr(_SSL_get_version(client)),
+                    CStr::from_ptr(_SSL_CIPHER_get_name(_SSL_get_current_cipher(client))),
+                    ptr::eq(_SSL_get_certificate(server), ecdsa),
+                    nid,
+                )
+            };
+            assert_eq!(actual, expected);
+            assert_eq!(_SSL_get_verify_result(client), X509_V_OK as c_long);
+            free_pair(client, server, &[client_ctx]);
+        }
+
+        _SSL_CTX_free(server_ctx);
+    }
+
+    #[test]
+    fn test_msg_callback() {
+        const HEADER: c_int = 0x100;
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        _SSL_CTX_set_msg_callback(client_ctx, Some(record_msg));
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+
+        MSG_CALLS.take();
+        assert!(handshake(client, server));
+        let calls = MSG_CALLS
+            .take()
+            .into_iter()
+            .map(|(write_p, version, content_type, buf)| (write_p, version, content_type, buf[0]))
+            .collect::<Vec<_>>();
+        // the encrypted flights are reported as headers alone.
+        assert_eq!(
+            calls,
+            vec![
+                (1, 0x301, HEADER, 22),
+                (1, 0x304, 22, 1),
+                (0, 0x303, HEADER, 22),
+                (0, 0x304, 22, 2),
+                (0, 0x303, HEADER, 20),
+                (0, 0x304, 20, 1),
+                (1, 0x303, HEADER, 20),
+                (1, 0x304, 20, 1),
+                (0, 0x303, HEADER, 23),
+                (1, 0x303, HEADER, 23),
+            ]
+        );
+
+        // leave the server part way through writing a record (it overflows the
+        // BIO pair) before it has a callback
+        let data = vec![0u8; 20000];
+        let mut buf = vec![0u8; data.len()];
+        let written = _SSL_write(server, data.as_ptr() as *const c_void, data.len() as c_int);
+        assert_eq!(_SSL_get_error(server, written), SSL_ERROR_WANT_WRITE);
+
+        MSG_CALLS.take();
+        _SSL_set_msg_callback(server, Some(record_msg));
+        let mut read = 0;
+        while read < data.len() {
+            let ret = _SSL_read(
+                client,
+                buf[read..].as_mut_ptr() as *mut c_void,
+                (data.len() - read) as c_int,
+            );
+            if ret <= 0 {
+                assert_eq!(_SSL_get_error(client, ret), SSL_ERROR_WANT_READ);
+                assert_eq!(
+                    _SSL_write(server, data.as_ptr() as *const c_void, data.len() as c_int),
+                    data.len() as c_int
+                );
+                continue;
+            }
+            read += ret as usize;
+        }
+
+        let calls = MSG_CALLS.take();
+        // (the client's reads are reported too)
+        let writes = calls
+            .into_iter()
+            .filter(|(write_p, ..)| *write_p == 1)
+            .collect::<Vec<_>>();
+        assert_eq!(writes.len(), 1);
+        for (_, version, content_type, buf) in writes {
+            assert_eq!((version, content_type), (0x303, HEADER));
+            assert_eq!(buf[..3], [23, 3, 3]);
+            let len = u16::from_be_bytes([buf[3], buf[4]]);
+            // the rest of the data, its content type and an AEAD tag
+            assert_eq!(len, 20000 - 16384 + 1 + 16);
+        }
+
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
+
+    #[test]
+    fn test_info_callback() {
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        _SSL_CTX_set_info_callback(client_ctx, Some(record_ctx_info));
+        _SSL_CTX_set_info_callback(server_ctx, Some(record_ctx_info));
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        // the server's own callback takes precedence over its SSL_CTX's
+        _SSL_set_info_callback(server, Some(record_ssl_info));
+
+        INFO_CALLS.take();
+        assert!(handshake(client, server));
+        assert_eq!(_SSL_shutdown(client), 0);
+        assert_eq!(_SSL_shutdown(server), 0);
+        assert_eq!(_SSL_shutdown(client), 1);
+        let calls = INFO_CALLS.take();
+
+        // OpenSSL reports more `SSL_CB_LOOP` steps, so compare runs of them as one.
+        let side = |ssl: *mut SSL, from_ctx: bool| {
+            let mut side = calls
+                .iter()
+                .filter(|(from, _, _, _)| ptr::eq(*from, ssl))
+                .map(|(_, ctx, where_, ret)| {
+                    assert_eq!(*ctx, from_ctx);
+                    (*where_, *ret)
+                })
+                .collect::<Vec<_>>();
+            side.dedup();
+            side
+        };
+        let close_notify = 256;
+
+        assert_eq!(
+            side(client, true),
+            vec![
+                (SSL_CB_HANDSHAKE_START, 1),
+                (SSL_ST_CONNECT | SSL_CB_LOOP, 1),
+                (SSL_ST_CONNECT | SSL_CB_EXIT, -1),
+                (SSL_ST_CONNECT | SSL_CB_LOOP, 1),
+                (SSL_CB_HANDSHAKE_DONE, 1),
+                (SSL_ST_CONNECT | SSL_CB_EXIT, 1),
+                (SSL_CB_WRITE_ALERT, close_notify),
+                (SSL_CB_READ_ALERT, close_notify),
+            ]
+        );
+        assert_eq!(
+            side(server, false),
+            vec![
+                (SSL_CB_HANDSHAKE_START, 1),
+                (SSL_ST_ACCEPT | SSL_CB_LOOP, 1),
+                (SSL_ST_ACCEPT | SSL_CB_EXIT, -1),
+                (SSL_ST_ACCEPT | SSL_CB_LOOP, 1),
+                (SSL_CB_HANDSHAKE_DONE, 1),
+                (SSL_ST_ACCEPT | SSL_CB_EXIT, 1),
+                (SSL_CB_WRITE_ALERT, close_notify),
+            ]
+        );
+
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
+
+    #[test]
+    fn test_keylog_callback() {
+        for (max_version, labels) in [
+            (
+                0,
+                &[
+                    "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+                    "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+                    "CLIENT_TRAFFIC_SECRET_0",
+                    "SERVER_TRAFFIC_SECRET_0",
+                    "EXPORTER_SECRET",
+                ][..],
+            ),
+            (TLS1_2_VERSION, &["CLIENT_RANDOM"][..]),
+        ] {
+            let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+            assert!(_SSL_CTX_get_keylog_callback(client_ctx).is_none());
+            for ctx in [client_ctx, server_ctx] {
+                _SSL_CTX_set_keylog_callback(ctx, Some(record_keylog));
+                assert_eq!(
+                    _SSL_CTX_get_keylog_callback(ctx).map(|cb| cb as *const ()),
+                    Some(record_keylog as *const ())
+                );
+            }
+            _SSL_CTX_ctrl(
+                client_ctx,
+                SSL_CTRL_SET_MAX_PROTO_VERSION,
+                max_version as c_long,
+                ptr::null_mut(),
+            );
+
+            KEYLOG_LINES.take();
+            let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+            assert!(handshake(client, server));
+            let lines = KEYLOG_LINES.take();
+
+            // each side logs the same NSS key log lines
+            let side = |ssl: *mut SSL| {
+                let mut side = lines
+                    .iter()
+                    .filter(|(from, _)| ptr::eq(*from, ssl))
+                    .map(|(_, line)| line.as_str())
+                    .collect::<Vec<_>>();
+                side.sort();
+                side
+            };
+            let client_lines = side(client);
+            assert_eq!(client_lines, side(server));
+            assert_eq!(client_lines.len() * 2, lines.len());
+
+            let mut logged_labels = vec![];
+            let mut client_randoms = vec![];
+            for line in client_lines {
+                let fields = line.split(' ').collect::<Vec<_>>();
+                let [label, client_random, secret] = fields[..] else {
+                    panic!("bad keylog line {line:?}");
+                };
+                assert_eq!(client_random.len(), 64);
+                assert!(secret.len() >= 64);
+                assert!(client_random
+                    .chars()
+                    .chain(secret.chars())
+                    .all(|c| matches!(c, '0'..='9' | 'a'..='f')));
+                logged_labels.push(label);
+                client_randoms.push(client_random);
+            }
+            client_randoms.dedup();
+            assert_eq!(client_randoms.len(), 1);
+            let mut expected_labels = labels.to_vec();
+            expected_labels.sort();
+            logged_labels.sort();
+            assert_eq!(logged_labels, expected_labels);
+
+            free_pair(client, server, &[client_ctx, server_ctx]);
+        }
+    }
+
+    #[test]
+    fn test_signature_algorithms_after_handshake() {
+        for (key_type, sig_type) in [
+            ("ecdsa-p256", NID_X9_62_id_ecPublicKey),
+            ("rsa", NID_rsassaPss),
+        ] {
+            let (client_ctx, server_ctx) = (client_ctx(key_type), server_ctx(key_type));
+            let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+            assert!(handshake(client, server));
+
+            let mut nid = 0;
+            assert_eq!(_SSL_get_signature_type_nid(server, &mut nid), 1);
+            assert_eq!(nid, sig_type);
+            nid = 0;
+            assert_eq!(_SSL_get_peer_signature_type_nid(client, &mut nid), 1);
+            assert_eq!(nid, sig_type);
+            // no client authentication took place
+            assert_eq!(_SSL_get_signature_type_nid(client, &mut nid), 0);
+            assert_eq!(_SSL_get_peer_signature_type_nid(server, &mut nid), 0);
+
+            // the server saw the client's offer; the client got no CertificateRequest
+            let count = _SSL_get_sigalgs(
+                server,
+                -1,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            assert!(count > 0);
+            assert_eq!(
+                _SSL_get_shared_sigalgs(
+                    server,
+                    -1,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                ),
+                0
+            );
+            assert_eq!(
+                _SSL_get_sigalgs(
+                    client,
+                    -1,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                ),
+                0
+            );
+
+            // rustls offers ECDSA_NISTP384_SHA384 first
+            let (mut sign, mut hash, mut sign_hash) = (0, 0, 0);
+            let (mut rsig, mut rhash) = (0u8, 0u8);
+            for get in [_SSL_get_sigalgs, _SSL_get_shared_sigalgs] {
+                assert_eq!(
+                    get(
+                        server,
+                        0,
+                        &mut sign,
+                        &mut hash,
+                        &mut sign_hash,
+                        &mut rsig,
+                        &mut rhash
+                    ),
+                    count
+                );
+                assert_eq!(
+                    (sign, hash, sign_hash, rhash, rsig),
+                    (
+                        NID_X9_62_id_ecPublicKey,
+                        NID_sha384,
+                        NID_ecdsa_with_SHA384,
+                        0x05,
+                        0x03
+                    )
+                );
+                assert_eq!(
+                    get(
+                        server,
+                        count,
+                        &mut sign,
+                        &mut hash,
+                        &mut sign_hash,
+                        &mut rsig,
+                        &mut rhash
+                    ),
+                    0
+                );
+            }
+
+            free_pair(client, server, &[client_ctx, server_ctx]);
+        }
+    }
+
+    #[test]
+    fn test_cert_verify_callback() {
+        for (ca, accepted, verify_result) in [
+            ("rsa", true, X509_V_OK),
+            // OpenSSL does the verification here, so the error is its own.
+            ("ecdsa-p256", false, X509_V_ERR_SELF_SIGNED_CERT_IN_CHAIN),
+        ] {
+            let (client_ctx, server_ctx) = (client_ctx(ca), server_ctx("rsa"));
+            let mut calls: c_int = 0;
+            _SSL_CTX_set_verify(client_ctx, 1, None);
+            _SSL_CTX_set_cert_verify_callback(
+                client_ctx,
+                Some(defer_cert_verify),
+                &mut calls as *mut c_int as *mut c_void,
+            );
+
+            let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+            assert_eq!(handshake(client, server), accepted);
+            assert_eq!(calls, 1);
+            assert_eq!(_SSL_get_verify_result(client), verify_result as c_long);
+            free_pair(client, server, &[client_ctx, server_ctx]);
+        }
+    }
+
+    #[test]
+    fn test_cert_verify_callback_rejects_without_error() {
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        _SSL_CTX_set_verify(client_ctx, 1, None);
+        _SSL_CTX_set_cert_verify_callback(client_ctx, Some(reject_cert_verify), ptr::null_mut());
+
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert!(!handshake(client, server));
+        assert_ne!(_SSL_get_verify_result(client), X509_V_OK as c_long);
+        assert_eq!(
+            _SSL_get_verify_result(client),
+            X509_V_ERR_APPLICATION_VERIFICATION as c_long
+        );
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
+
+    #[test]
+    fn test_verify_callback_overrides_failure() {
+        // the server's certificate is not issued by the client's CA
+        let (client_ctx, server_ctx) = (client_ctx("ecdsa-p256"), server_ctx("rsa"));
+        _SSL_CTX_set_verify(client_ctx, 1, Some(accept_verify));
+        VERIFY_CALLS.with_borrow_mut(|calls| calls.clear());
+
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert!(handshake(client, server));
+        // nb. OpenSSL says `X509_V_ERR_SELF_SIGNED_CERT_IN_CHAIN` here, as the
+        // server sends its root: webpki does not distinguish that case.
+        assert_eq!(
+            _SSL_get_verify_result(client),
+            X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY as c_long
+        );
+        let issuer_error = X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY;
+        assert_eq!(
+            VERIFY_CALLS.take(),
+            vec![
+                (0, issuer_error, 2, true),
+                (1, issuer_error, 2, true),
+                (1, issuer_error, 1, true),
+                (1, issuer_error, 0, true),
+            ]
+        );
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
+
+    #[test]
+    fn test_verify_callback_rejects_valid_chain() {
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        _SSL_CTX_set_verify(client_ctx, 1, Some(reject_verify));
+        VERIFY_CALLS.with_borrow_mut(|calls| calls.clear());
+
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert!(!handshake(client, server));
+        assert_eq!(
+            _SSL_get_verify_result(client),
+            X509_V_ERR_UNSPECIFIED as c_long
+        );
+        // rejected at the first certificate checked: the top of the chain
+        assert_eq!(VERIFY_CALLS.take(), vec![(1, X509_V_OK, 2, true)]);
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
+
+    #[test]
+    fn test_verify_callback_error_depth_follows_chain() {
+        // the server sends its end-entity certificate and an unrelated CA
+        // certificate, but not the intermediate that issued it.
+        let chain = std::env::temp_dir().join(format!(
+            "rustls-libssl-{}-unrelated-chain.pem",
+            std::process::id()
+        ));
+        let mut pem = fs::read("test-ca/rsa/end.cert").unwrap();
+        pem.extend(fs::read("test-ca/ecdsa-p256/ca.cert").unwrap());
+        fs::write(&chain, pem).unwrap();
+        let chain_name = CString::new(chain.to_str().unwrap()).unwrap();
+
+        let server_ctx = _SSL_CTX_new(_TLS_method());
+        assert_eq!(
+            _SSL_CTX_use_certificate_chain_file(server_ctx, chain_name.as_ptr()),
+            C_INT_SUCCESS
+        );
+        assert_eq!(
+            _SSL_CTX_use_PrivateKey_file(server_ctx, c"test-ca/rsa/end.key".as_ptr(), FILETYPE_PEM),
+            C_INT_SUCCESS
+        );
+        fs::remove_file(&chain).unwrap();
+
+        let client_ctx = client_ctx("rsa");
+        _SSL_CTX_set_verify(client_ctx, 1, Some(record_verify));
+        VERIFY_CALLS.with_borrow_mut(|calls| calls.clear());
+
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert!(!handshake(client, server));
+        let issuer_error = X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY;
+        assert_eq!(_SSL_get_verify_result(client), issuer_error as c_long);
+        // the end-entity's issuer is missing, not the unrelated certificate's
+        assert_eq!(VERIFY_CALLS.take(), vec![(0, issuer_error, 0, true)]);
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
+
+    #[test]
+    fn test_verify_depth() {
+        // the server sends its end-entity certificate, one intermediate, and the root.
+        for (depth, accepted, verify_result) in [
+            (-1, true, X509_V_OK),
+            (0, false, X509_V_ERR_CERT_CHAIN_TOO_LONG),
+            (1, true, X509_V_OK),
+        ] {
+            let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+            _SSL_CTX_set_verify(client_ctx, 1, Some(record_verify));
+            _SSL_CTX_set_verify_depth(client_ctx, depth);
+            assert_eq!(_SSL_CTX_get_verify_depth(client_ctx), depth);
+            VERIFY_CALLS.with_borrow_mut(|calls| calls.clear());
+
+            let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+            assert_eq!(handshake(client, server), accepted);
+            assert_eq!(_SSL_get_verify_result(client), verify_result as c_long);
+            if !accepted {
+                // the intermediate is the first certificate too deep
+                assert_eq!(VERIFY_CALLS.take(), vec![(0, verify_result, 1, true)]);
+            }
+            free_pair(client, server, &[client_ctx, server_ctx]);
+        }
+    }
+
+    #[test]
+    fn test_SSL_CTX_new_null() {
+        assert!(_SSL_CTX_new(ptr::null()).is_null());
+    }
+
+    #[test]
+    fn test_SSL_new_null() {
+        assert!(_SSL_new(ptr::null_mut()).is_null());
+    }
+
+    #[test]
+    fn test_SSL_up_ref_null() {
+        assert_eq!(_SSL_up_ref(ptr::null_mut()), 0);
+    }
+
+    #[test]
+    fn test_SSL_free() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        assert!(!ctx.is_null());
+        let ssl = _SSL_new(ctx);
+        assert!(!ssl.is_null());
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
+    }
+
+    #[test]
+    fn test_SSL_free_after_up_ref() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        assert!(!ctx.is_null());
+        let ssl = _SSL_new(ctx);
+        assert!(!ssl.is_null());
+        assert_eq!(_SSL_up_ref(ssl), 1);
+        _SSL_free(ssl); // ref 2
+        _SSL_free(ssl); // ref 1
+        _SSL_CTX_free(ctx);
+    }
+
+    #[test]
+    fn test_SSL_CTX_set_alpn_protos_works() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        assert_eq!(
+            _SSL_CTX_set_alpn_protos(ctx, b"\x05hello" as *const u8, 6) as i32,
+            0i32
+        );
+        _SSL_CTX_free(ctx);
+    }
+
+    #[test]
+    fn test_SSL_CTX_set_alpn_protos_null_ctx() {
+        assert_eq!(
+            _SSL_CTX_set_alpn_protos(ptr::null_mut(), b"\x05hello" as *const u8, 6) as i32,
+            1i32
+        );
+    }
+
+    #[test]
+    fn test_SSL_CTX_set_alpn_protos_null_proto() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        assert_eq!(_SSL_CTX_set_alpn_protos(ctx, ptr::null(), 6) as i32, 1i32);
+        _SSL_CTX_free(ctx);
+    }
+
+    #[test]
+    fn test_SSL_CTX_set_alpn_protos_invalid_proto() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        assert_eq!(
+            _SSL_CTX_set_alpn_protos(ctx, b"\x05hell" as *const u8, 5) as i32,
+            1i32
+        );
+        _SSL_CTX_free(ctx);
+    }
+
+    #[test]
+    fn test_SSL_set_alpn_protos_works() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        let ssl = _SSL_new(ctx);
+        assert_eq!(
+            _SSL_set_alpn_protos(ssl, b"\x05hello" as *const u8, 6) as i32,
+            0i32
+        );
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
+    }
+
+    #[test]
+    fn test_SSL_set_alpn_protos_null_ssl() {
+        assert_eq!(
+            _SSL_set_alpn_protos(ptr::null_mut(), b"\x05hello" as *const u8, 6) as i32,
+            1i32
+        );
+    }
+
+    #[test]
+    fn test_SSL_set_alpn_protos_null_proto() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        let ssl = _SSL_new(ctx);
+        assert_eq!(_SSL_set_alpn_protos(ssl, ptr::null(), 6) as i32, 1i32);
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
+    }
+
+    #[test]
+    fn test_SSL_set_alpn_protos_invalid_proto() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        let ssl = _SSL_new(ctx);
+        assert_eq!(
+            _SSL_set_alpn_protos(ssl, b"\x05hell" as *const u8, 5) as i32,
+            1i32
+        );
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
+    }
+
+    #[test]
+    fn test_SSL_select_next_proto_match() {
+        let mut output = ptr::null_mut();
+        let mut output_len = 0u8;
+        let client = b"\x05hello\x05world";
+        let server = b"\x05uhoh!\x05world";
+        assert_eq!(
+            _SSL_select_next_proto(
+                &mut output as *mut *mut u8,
+                &mut output_len as *mut u8,
+                server.as_ptr(),
+                server.len() as c_uint,
+                client.as_ptr(),
+                client.len() as c_uint
+            ),
+            1i32
+        );
+        assert_eq!(b"world", &server[7..]);
+        assert_eq!(output as *const u8, server[7..].as_ptr());
+        assert_eq!(output_len, 5);
+    }
+
+    #[test]
+    fn test_SSL_select_next_proto_no_overlap() {
+        let mut output = ptr::null_mut();
+        let mut output_len = 0u8;
+        let client = b"\x05hello\x05world";
+        let server = b"\x05uhoh!\x05what!";
+        assert_eq!(
+            _SSL_select_next_proto(
+                &mut output as *mut *mut u8,
+                &mut output_len as *mut u8,
+                server.as_ptr(),
+                server.len() as c_uint,
+                client.as_ptr(),
+                client.len() as c_uint
+            ),
+            2i32
+        );
+        assert_eq!(b"hello", &client[1..6]);
+        assert_eq!(output as *const u8, client[1..].as_ptr());
+        assert_eq!(output_len, 5);
+    }
+
+    #[test]
+    fn test_SSL_select_next_proto_illegal_client() {
+        let mut output = ptr::null_mut();
+        let mut output_len = 0u8;
+        let client = b"\x09hello";
+        let server = b"\x05uhoh!\x05world";
+        assert_eq!(
+            _SSL_select_next_proto(
+                &mut output as *mut *mut u8,
+                &mut output_len as *mut u8,
+                server.as_ptr(),
+                server.len() as c_uint,
+                client.as_ptr(),
+                client.len() as c_uint
+            ),
+            2i32
+        );
+        assert_eq!(output as *const u8, ptr::null_mut());
+    }
+
+    #[test]
+    fn test_SSL_select_next_proto_null() {
+        let mut output = ptr::null_mut();
+        let mut output_len = 0u8;
+        let client = b"\x05hello\x05world";
+        let server = b"\x05uhoh!\x05world";
+
+        assert_eq!(
+            _SSL_select_next_proto(
+                ptr::null_mut(),
+                &mut output_len as *mut u8,
+                server.as_ptr(),
+                server.len() as c_uint,
+                client.as_ptr(),
+                client.len() as c_uint
+            ),
+            0
+        );
+
+        assert_eq!(
+            _SSL_select_next_proto(
+                &mut output as *mut *mut u8,
+                ptr::null_mut(),
+                server.as_ptr(),
+                server.len() as c_uint,
+                client.as_ptr(),
+                client.len() as c_uint
+            ),
+            0
+        );
+
+        assert_eq!(
+            _SSL_select_next_proto(
+                &mut output as *mut *mut u8,
+                &mut output_len as *mut u8,
+                ptr::null(),
+                server.len() as c_uint,
+                client.as_ptr(),
+                client.len() as c_uint
+            ),
+            0
+        );
+
+        assert_eq!(
+            _SSL_select_next_proto(
+                &mut output as *mut *mut u8,
+                &mut output_len as *mut u8,
+                server.as_ptr(),
+                server.len() as c_uint,
+                ptr::null(),
+                client.len() as c_uint
+            ),
+            0
+        );
+    }
+
+    #[test]
+    fn test_SSL_SESSION_roundtrip() {
+        let sess = crate::SslSession::new(
+            vec![1; 32],
+            vec![2; 32],
+            vec![3; 128],
+            crate::cache::TimeBase(123),
+            300,
+        );
+        let sess_ptr = to_arc_mut_ptr(NotThreadSafe::new(sess));
+
+        let mut buffer = [0u8; 1024];
+        let mut ptr = buffer.as_mut_ptr();
+        let len = _i2d_SSL_SESSION(sess_ptr, &mut ptr);
+
+        println!("encoding: {:?}", &buffer[..len as usize]);
+
+        let mut ptr = buffer.as_ptr();
+        let new_sess = _d2i_SSL_SESSION(ptr::null_mut(), &mut ptr, buffer.len() as c_long);
+        assert!(!new_sess.is_null());
+        assert_eq!(len as usize, (ptr as usize) - (buffer.as_ptr() as usize));
+
+        _SSL_SESSION_free(new_sess);
+        _SSL_SESSION_free(sess_ptr);
+    }
 
-entry_stub! {
-    pub fn _SSL_CTX_get_client_CA_list(_ctx: *const SSL_CTX) -> *mut stack_st_X509_NAME;
-}
+    #[test]
+    fn test_PEM_SSL_SESSION_roundtrip() {
+        let sess = _SSL_SESSION_new();
+        assert_eq!(_SSL_SESSION_set_protocol_version(sess, 0x0304), 1);
+        assert_eq!(_SSL_SESSION_set1_hostname(sess, c"localhost".as_ptr()), 1);
+        assert_eq!(_SSL_SESSION_set1_master_key(sess, [2; 48].as_ptr(), 48), 1);
+
+        let bio = unsafe { BIO_new(BIO_s_mem()) };
+        assert_eq!(_PEM_write_bio_SSL_SESSION(bio, sess), 1);
+        assert_eq!(_PEM_write_bio_SSL_SESSION(bio, sess), 1);
+
+        // "*x is freed and replaced with the result"
+        let mut x = _SSL_SESSION_new();
+        let decoded = _PEM_read_bio_SSL_SESSION(bio, &mut x, None, ptr::null_mut());
+        assert!(!decoded.is_null());
+        assert_eq!(x, decoded);
+        assert_eq!(_SSL_SESSION_get_protocol_version(decoded), 0x0304);
+        assert_eq!(
+            unsafe { CStr::from_ptr(_SSL_SESSION_get0_hostname(decoded)) },
+            c"localhost"
+        );
+        assert_eq!(_SSL_SESSION_get_master_key(decoded, ptr::null_mut(), 0), 48);
 
-entry_stub! {
-    pub fn _SSL_CTX_set_client_CA_list(_ctx: *mut SSL_CTX, _name_list: *mut stack_st_X509_NAME);
-}
+        // the second copy, without `x`
+        let second = _PEM_read_bio_SSL_SESSION(bio, ptr::null_mut(), None, ptr::null_mut());
+        assert!(!second.is_null());
+        assert_ne!(second, decoded);
+        assert!(_PEM_read_bio_SSL_SESSION(bio, ptr::null_mut(), None, ptr::null_mut()).is_null());
 
-entry_stub! {
-    pub fn _SSL_load_client_CA_file(_file: *const c_char) -> *mut stack_st_X509_NAME;
-}
+        assert!(
+            _PEM_read_SSL_SESSION(ptr::null_mut(), ptr::null_mut(), None, ptr::null_mut())
+                .is_null()
+        );
+        assert_eq!(_PEM_write_SSL_SESSION(ptr::null_mut(), sess), 0);
+        assert_eq!(_SSL_SESSION_print_fp(ptr::null_mut(), sess), 0);
 
-// no individual message logging
+        unsafe {
+            openssl_sys::ERR_clear_error();
+            BIO_free(bio);
+        }
+        _SSL_SESSION_free(second);
+        _SSL_SESSION_free(decoded);
+        _SSL_SESSION_free(sess);
+    }
 
-entry_stub! {
-    pub fn _SSL_CTX_set_msg_callback(_ctx: *mut SSL_CTX, _cb: SSL_CTX_msg_cb_func);
-}
+    #[test]
+    fn test_SSL_SESSION_print() {
+        // these match OpenSSL 3's output for the same sessions
+        const TLS12: &str = "SSL-Session:
+    Protocol  : TLSv1.2
+    Cipher    : ECDHE-RSA-AES128-GCM-SHA256
+    Session-ID: 000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F
+    Session-ID-ctx: 637478
+    Master-Key: A0A1A2A3A4A5A6A7A8A9AAABACADAEAFA0A1A2A3A4A5A6A7A8A9AAABACADAEAFA0A1A2A3A4A5A6A7A8A9AAABACADAEAF
+    PSK identity: None
+    PSK identity hint: None
+    SRP username: None
+    Start Time: 1700000000
+    Timeout   : 7200 (sec)
+    Verify return code: 1 (unspecified certificate verification error)
+    Extended master secret: no
+";
+        const TLS13: &str = "SSL-Session:
+    Protocol  : TLSv1.3
+    Cipher    : TLS_AES_128_GCM_SHA256
+    Session-ID: 000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F
+    Session-ID-ctx: 637478
+    Resumption PSK: A0A1A2A3A4A5A6A7A8A9AAABACADAEAFA0A1A2A3A4A5A6A7A8A9AAABACADAEAFA0A1A2A3A4A5A6A7A8A9AAABACADAEAF
+    PSK identity: None
+    PSK identity hint: None
+    SRP username: None
+    Start Time: 1700000000
+    Timeout   : 7200 (sec)
+    Verify return code: 1 (unspecified certificate verification error)
+    Extended master secret: no
+    Max Early Data: 16384
+";
+        const KEYLOG: &str = "RSA Session-ID:000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F \
+                              Master-Key:A0A1A2A3A4A5A6A7A8A9AAABACADAEAFA0A1A2A3A4A5A6A7A8A9AAABACADAEAFA0A1A2A3A4A5A6A7A8A9AAABACADAEAF\n";
+
+        let id = (0..32).collect::<Vec<u8>>();
+        let master_key = (0..48).map(|i| 0xa0 + (i % 16)).collect::<Vec<u8>>();
+        let bio = unsafe { BIO_new(BIO_s_mem()) };
+
+        for (version, cipher, expected) in
+            [(0x0303, [0xc0, 0x2f], TLS12), (0x0304, [0x13, 0x01], TLS13)]
+        {
+            let sess = _SSL_SESSION_new();
+            let cipher = _SSL_CIPHER_find(ptr::null(), cipher.as_ptr());
+            assert_eq!(_SSL_SESSION_set_cipher(sess, cipher), 1);
+            assert_eq!(_SSL_SESSION_set_protocol_version(sess, version), 1);
+            assert_eq!(_SSL_SESSION_set1_id(sess, id.as_ptr(), 32), 1);
+            assert_eq!(_SSL_SESSION_set1_id_context(sess, b"ctx".as_ptr(), 3), 1);
+            assert_eq!(
+                _SSL_SESSION_set1_master_key(sess, master_key.as_ptr(), 48),
+                1
+            );
+            _SSL_SESSION_set_time(sess, 1700000000);
+            _SSL_SESSION_set_timeout(sess, 7200);
+            if version == 0x0304 {
+                assert_eq!(_SSL_SESSION_set_max_early_data(sess, 16384), 1);
+            }
 
-pub type SSL_CTX_msg_cb_func = Option<
-    unsafe extern "C" fn(
-        write_p: c_int,
-        version: c_int,
-        content_type: c_int,
-        buf: *const c_void,
-        len: usize,
-        ssl: *mut SSL,
-        arg: *mut c_void,
-    ),
->;
+            assert_eq!(_SSL_SESSION_print(bio, sess), 1);
+            assert_eq!(read_mem_bio(bio), expected);
+            assert_eq!(_SSL_SESSION_print_keylog(bio, sess), 1);
+            assert_eq!(read_mem_bio(bio), KEYLOG);
+            _SSL_SESSION_free(sess);
+        }
 
-// no state machine observation
+        // the keylog needs both a session id and master key
+        let sess = _SSL_SESSION_new();
+        assert_eq!(_SSL_SESSION_print_keylog(bio, sess), 0);
+        assert_eq!(_SSL_SESSION_set1_id(sess, id.as_ptr(), 32), 1);
+        assert_eq!(_SSL_SESSION_print_keylog(bio, sess), 0);
+        assert_eq!(read_mem_bio(bio), "");
 
-entry_stub! {
-    pub fn _SSL_CTX_set_info_callback(
-        _ctx: *mut SSL_CTX,
-        _cb: Option<unsafe extern "C" fn(ssl: *const SSL, type_: c_int, val: c_int)>,
-    );
-}
+        unsafe {
+            openssl_sys::ERR_clear_error();
+            BIO_free(bio);
+        }
+        _SSL_SESSION_free(sess);
+    }
 
-// no NPN (obsolete precursor to ALPN)
+    #[test]
+    fn test_SSL_SESSION_properties() {
+        let sess = _SSL_SESSION_new();
+        assert_eq!(_SSL_SESSION_get_timeout(sess), 304);
+        assert_eq!(_SSL_SESSION_is_resumable(sess), 0);
+        assert!(_SSL_SESSION_get0_cipher(sess).is_null());
+        assert!(_SSL_SESSION_get0_hostname(sess).is_null());
+
+        let cipher = _SSL_CIPHER_find(ptr::null(), [0x13, 0x02].as_ptr());
+        assert_eq!(_SSL_SESSION_set_cipher(sess, cipher), 1);
+        assert_eq!(_SSL_SESSION_set_protocol_version(sess, 0x0304), 1);
+        assert_eq!(_SSL_SESSION_set1_id(sess, [1; 33].as_ptr(), 33), 0);
+        assert_eq!(_SSL_SESSION_set1_id(sess, [1; 32].as_ptr(), 32), 1);
+        assert_eq!(_SSL_SESSION_set1_hostname(sess, c"localhost".as_ptr()), 1);
+        assert_eq!(_SSL_SESSION_set1_alpn_selected(sess, b"h2".as_ptr(), 2), 1);
+        assert_eq!(_SSL_SESSION_set1_master_key(sess, [2; 48].as_ptr(), 48), 1);
+        assert_eq!(_SSL_SESSION_set_max_early_data(sess, 1024), 1);
+
+        // the properties survive both dup and an encoding roundtrip
+        let dup = _SSL_SESSION_dup(sess);
+        let mut encoding = ptr::null_mut();
+        let len = _i2d_SSL_SESSION(dup, &mut encoding);
+        let mut ptr = encoding as *const c_uchar;
+        let decoded = _d2i_SSL_SESSION(ptr::null_mut(), &mut ptr, len as c_long);
+        assert!(!decoded.is_null());
+
+        assert_eq!(_SSL_SESSION_get0_cipher(decoded), cipher);
+        assert_eq!(_SSL_SESSION_get_protocol_version(decoded), 0x0304);
+        assert_eq!(
+            unsafe { CStr::from_ptr(_SSL_SESSION_get0_hostname(decoded)) },
+            c"localhost"
+        );
+        let (mut alpn, mut alpn_len) = (ptr::null(), 0);
+        _SSL_SESSION_get0_alpn_selected(decoded, &mut alpn, &mut alpn_len);
+        assert_eq!(
+            unsafe { core::slice::from_raw_parts(alpn, alpn_len) },
+            b"h2"
+        );
+        assert_eq!(_SSL_SESSION_get_master_key(decoded, ptr::null_mut(), 0), 48);
+        let mut master_key = [0u8; 16];
+        assert_eq!(
+            _SSL_SESSION_get_master_key(decoded, master_key.as_mut_ptr(), 16),
+            16
+        );
+        assert_eq!(master_key, [2; 16]);
+        assert_eq!(_SSL_SESSION_get_max_early_data(decoded), 1024);
 
-entry_stub! {
-    pub fn _SSL_CTX_set_next_proto_select_cb(
-        _ctx: *mut SSL_CTX,
-        _cb: SSL_CTX_npn_select_cb_func,
-        _arg: *mut c_void,
-    );
-}
+        unsafe { openssl_sys::OPENSSL_free(encoding as *mut c_void) };
+        _SSL_SESSION_free(decoded);
+        _SSL_SESSION_free(dup);
+        _SSL_SESSION_free(sess);
+    }
 
-pub type SSL_CTX_npn_select_cb_func = Option<
-    unsafe extern "C" fn(
-        s: *mut SSL,
-        out: *mut *mut c_uchar,
-        outlen: *mut c_uchar,
-        in_: *const c_uchar,
-        inlen: c_uint,
-        arg: *mut c_void,
-    ) -> c_int,
->;
+    #[test]
+    fn test_SSL_early_data_settings() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        assert_eq!(_SSL_CTX_get_recv_max_early_data(ctx), 16384);
+        assert_eq!(_SSL_CTX_set_max_early_data(ctx, 1024), 1);
+        assert_eq!(_SSL_CTX_set_recv_max_early_data(ctx, 2048), 1);
 
-entry_stub! {
-    pub fn _SSL_get0_next_proto_negotiated(
-        _ssl: *const SSL,
-        _data: *mut *const c_uchar,
-        _len: *mut c_uint,
-    );
-}
+        let ssl = _SSL_new(ctx);
+        assert_eq!(_SSL_get_max_early_data(ssl), 1024);
+        assert_eq!(_SSL_get_recv_max_early_data(ssl), 2048);
+        assert_eq!(_SSL_get_early_data_status(ssl), SSL_EARLY_DATA_NOT_SENT);
 
-entry_stub! {
-    pub fn _SSL_CTX_set_next_protos_advertised_cb(
-        _ctx: *mut SSL_CTX,
-        _cb: SSL_CTX_npn_advertised_cb_func,
-        _arg: *mut c_void,
-    );
-}
+        // a client needs a session that permits early data
+        let mut written = 0;
+        _SSL_set_connect_state(ssl);
+        assert_eq!(
+            _SSL_write_early_data(ssl, b"hello".as_ptr() as *const c_void, 5, &mut written),
+            0
+        );
+        let mut readbytes = 0;
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            _SSL_read_early_data(
+                ssl,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+                &mut readbytes
+            ),
+            SSL_READ_EARLY_DATA_ERROR
+        );
 
-pub type SSL_CTX_npn_advertised_cb_func = Option<
-    unsafe extern "C" fn(
-        ssl: *mut SSL,
-        out: *mut *const c_uchar,
-        outlen: *mut c_uint,
-        arg: *mut c_void,
-    ) -> c_int,
->;
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
+    }
 
-// no password-protected key loading
+    #[test]
+    fn test_early_data() {
+        let client_ctx = client_ctx("rsa");
+        let server_ctx = server_ctx("rsa");
+        _SSL_CTX_ctrl(
+            client_ctx,
+            SSL_CTRL_SET_SESS_CACHE_MODE,
+            SSL_SESS_CACHE_CLIENT,
+            ptr::null_mut(),
+        );
+        assert_eq!(_SSL_CTX_set_max_early_data(server_ctx, 1024), 1);
+
+        // drive the server with `SSL_read_early_data` until it finishes
+        let read_early = |client, server| {
+            let (mut got, mut buf) = (vec![], [0u8; 64]);
+            for _ in 0..20 {
+                let mut read = 0;
+                match _SSL_read_early_data(server, buf.as_mut_ptr() as *mut c_void, 64, &mut read) {
+                    SSL_READ_EARLY_DATA_SUCCESS => got.extend_from_slice(&buf[..read]),
+                    SSL_READ_EARLY_DATA_FINISH => return got,
+                    ret => {
+                        assert_eq!(_SSL_get_error(server, ret), SSL_ERROR_WANT_READ);
+                        let ret = _SSL_do_handshake(client);
+                        assert!(ret == 1 || _SSL_get_error(client, ret) == SSL_ERROR_WANT_READ);
+                    }
+                }
+            }
+            panic!("early data never finished");
+        };
+        // finish the handshake, and return the client's session once it has
+        // read the server's tickets
+        let exchange = |client, server| {
+            assert!(handshake(client, server));
+            let mut buf = [0u8; 1];
+            assert_eq!(_SSL_write(server, b"x".as_ptr() as *const c_void, 1), 1);
+            assert_eq!(_SSL_read(client, buf.as_mut_ptr() as *mut c_void, 1), 1);
+            _SSL_get1_session(client)
+        };
+        let write_early = |client, data: &[u8]| {
+            let mut written = 0;
+            let ret = _SSL_write_early_data(
+                client,
+                data.as_ptr() as *const c_void,
+                data.len(),
+                &mut written,
+            );
+            (ret, written)
+        };
 
-entry_stub! {
-    pub fn _SSL_CTX_set_default_passwd_cb(_ctx: *mut SSL_CTX, _cb: pem_password_cb);
-}
+        // a full handshake issues a session permitting early data.  the
+        // allow-early-data callback is only asked when early data is offered.
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        _SSL_set_allow_early_data_cb(server, Some(refuse_early_data), ptr::null_mut());
+        assert_eq!(read_early(client, server), b"");
+        let session = exchange(client, server);
+        assert_eq!(_SSL_SESSION_get_max_early_data(session), 1024);
+        assert_eq!(_SSL_get_early_data_status(client), SSL_EARLY_DATA_NOT_SENT);
+        assert_eq!(_SSL_get_early_data_status(server), SSL_EARLY_DATA_NOT_SENT);
+        free_pair(client, server, &[]);
+
+        // accepted
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert_eq!(_SSL_set_session(client, session), 1);
+        _SSL_SESSION_free(session);
+        assert_eq!(write_early(client, b"hello "), (1, 6));
+        assert_eq!(write_early(client, b"early"), (1, 5));
+        assert_eq!(read_early(client, server), b"hello early");
+        let session = exchange(client, server);
+        assert_eq!(_SSL_session_reused(client), 1);
+        assert_eq!(_SSL_get_early_data_status(client), SSL_EARLY_DATA_ACCEPTED);
+        assert_eq!(_SSL_get_early_data_status(server), SSL_EARLY_DATA_ACCEPTED);
+        free_pair(client, server, &[]);
+
+        // rejected by the allow-early-data callback, still resuming
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        _SSL_set_allow_early_data_cb(server, Some(refuse_early_data), ptr::null_mut());
+        assert_eq!(_SSL_set_session(client, session), 1);
+        _SSL_SESSION_free(session);
+        assert_eq!(write_early(client, b"hello"), (1, 5));
+        assert_eq!(read_early(client, server), b"");
+        _SSL_SESSION_free(exchange(client, server));
+        assert_eq!(_SSL_session_reused(client), 1);
+        assert_eq!(_SSL_get_early_data_status(client), SSL_EARLY_DATA_REJECTED);
+        // (rustls does not tell the server it rejected early data)
+        assert_eq!(_SSL_get_early_data_status(server), SSL_EARLY_DATA_NOT_SENT);
+        free_pair(client, server, &[]);
+
+        // servers not reading early data issue stateless tickets, which resume
+        // without the server's session cache but do not permit early data
+        _SSL_CTX_ctrl(
+            server_ctx,
+            SSL_CTRL_SET_SESS_CACHE_MODE,
+            SSL_SESS_CACHE_OFF,
+            ptr::null_mut(),
+        );
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        let session = exchange(client, server);
+        assert_eq!(_SSL_SESSION_get_max_early_data(session), 0);
+        free_pair(client, server, &[]);
+
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert_eq!(_SSL_set_session(client, session), 1);
+        _SSL_SESSION_free(session);
+        assert_eq!(write_early(client, b"hello").0, 0);
+        _SSL_SESSION_free(exchange(client, server));
+        assert_eq!(_SSL_session_reused(client), 1);
+        assert_eq!(_SSL_get_early_data_status(client), SSL_EARLY_DATA_NOT_SENT);
+        assert_eq!(_SSL_get_early_data_status(server), SSL_EARLY_DATA_NOT_SENT);
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
 
-pub type pem_password_cb = Option<
-    unsafe extern "C" fn(
-        buf: *mut c_char,
-        size: c_int,
-        rwflag: c_int,
-        userdata: *mut c_void,
-    ) -> c_int,
->;
+    #[test]
+    fn test_SSL_export_keying_material_before_handshake() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        let ssl = _SSL_new(ctx);
+        let mut out = [0u8; 16];
+        let label = c"EXPORTER-test";
+        assert_eq!(
+            _SSL_export_keying_material(
+                ssl,
+                out.as_mut_ptr(),
+                out.len(),
+                label.as_ptr(),
+                label.count_bytes(),
+                ptr::null(),
+                0,
+                0
+            ),
+            -1
+        );
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
+    }
 
-entry_stub! {
-    pub fn _SSL_CTX_set_default_passwd_cb_userdata(_ctx: *mut SSL_CTX, _u: *mut c_void);
-}
+    #[test]
+    fn test_SSL_key_update_before_handshake() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        let ssl = _SSL_new(ctx);
+        assert_eq!(_SSL_get_key_update_type(ssl), SSL_KEY_UPDATE_NONE);
+        assert_eq!(_SSL_key_update(ssl, 2), 0);
+        assert_eq!(_SSL_key_update(ssl, SSL_KEY_UPDATE_REQUESTED), 0);
+        assert_eq!(_SSL_get_key_update_type(ssl), SSL_KEY_UPDATE_NONE);
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
+    }
 
-// no SRP
+    #[test]
+    fn test_handshake_small_buffer() {
+        for max_version in [TLS1_2_VERSION, TLS1_3_VERSION] {
+            let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+            _SSL_CTX_ctrl(
+                client_ctx,
+                SSL_CTRL_SET_MAX_PROTO_VERSION,
+                max_version as c_long,
+                ptr::null_mut(),
+            );
+            // neither side's flights fit in the BIO pair
+            let (client, server) = connect_pair(client_ctx, server_ctx, 512);
+            assert!(handshake(client, server));
+            assert_eq!(_SSL_write(client, b"x".as_ptr() as *const c_void, 1), 1);
+            let mut buf = [0u8; 1];
+            assert_eq!(_SSL_read(server, buf.as_mut_ptr() as *mut c_void, 1), 1);
+            free_pair(client, server, &[client_ctx, server_ctx]);
+        }
+    }
 
-entry_stub! {
-    pub fn _SSL_CTX_set_srp_password(_ctx: *mut SSL_CTX, _password: *mut c_char) -> c_int;
-}
+    #[test]
+    fn test_accept_eof_in_client_hello() {
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        let ret = _SSL_do_handshake(client);
+        assert_eq!(_SSL_get_error(client, ret), SSL_ERROR_WANT_READ);
+        let mut hello = [0u8; 4096];
+        let len = unsafe {
+            BIO_read(
+                _SSL_get_rbio(server),
+                hello.as_mut_ptr() as *mut c_void,
+                hello.len() as c_int,
+            )
+        };
+        assert!(len > 0);
+        free_pair(client, server, &[]);
+
+        // the client closes its socket with half the client hello sent
+        let (mut client_sock, server_sock) = UnixStream::pair().unwrap();
+        client_sock.write_all(&hello[..len as usize / 2]).unwrap();
+        drop(client_sock);
+        let server = _SSL_new(server_ctx);
+        assert_eq!(_SSL_set_fd(server, server_sock.as_raw_fd()), 1);
+        _SSL_set_accept_state(server);
+        let ret = _SSL_do_handshake(server);
+        assert!(ret <= 0);
+        assert_eq!(_SSL_get_error(server, ret), openssl_sys::SSL_ERROR_SSL);
+        assert_ne!(unsafe { openssl_sys::ERR_get_error() }, 0);
+        unsafe { openssl_sys::ERR_clear_error() };
+
+        _SSL_free(server);
+        _SSL_CTX_free(client_ctx);
+        _SSL_CTX_free(server_ctx);
+    }
 
-entry_stub! {
-    pub fn _SSL_CTX_set_srp_username(_ctx: *mut SSL_CTX, _name: *mut c_char) -> c_int;
-}
+    #[test]
+    fn test_SSL_write_small_buffer() {
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        let (client, server) = connect_pair(client_ctx, server_ctx, 512);
+        assert!(handshake(client, server));
+
+        let data = vec![b'a'; 4000];
+        let moved = data.clone();
+        let mut written = 0;
+        // read all the server can, returning the amount
+        let drain = || {
+            let (mut total, mut buf, mut read) = (0, [0u8; 1024], 0);
+            while _SSL_read_ex(server, buf.as_mut_ptr() as *mut c_void, 1024, &mut read) == 1 {
+                total += read;
+            }
+            total
+        };
+        let write_ex = |buf: &[u8], written: &mut usize| {
+            _SSL_write_ex(client, buf.as_ptr() as *const c_void, buf.len(), written)
+        };
+        let write =
+            |buf: &[u8]| _SSL_write(client, buf.as_ptr() as *const c_void, buf.len() as c_int);
+
+        // only the whole buffer is success, and its retry must be the same buffer
+        assert_eq!(write_ex(&data, &mut written), 0);
+        assert_eq!(_SSL_get_error(client, 0), SSL_ERROR_WANT_WRITE);
+        assert_eq!(write(&moved), -1);
+        assert_ne!(unsafe { openssl_sys::ERR_get_error() }, 0);
+        let mut total = 0;
+        while {
+            total += drain();
+            write_ex(&data, &mut written) == 0
+        } {
+            assert_eq!(_SSL_get_error(client, 0), SSL_ERROR_WANT_WRITE);
+        }
+        assert_eq!((written, total + drain()), (4000, 4000));
+
+        // unless the buffer may move
+        _SSL_ctrl(
+            client,
+            SSL_CTRL_MODE,
+            SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER,
+            ptr::null_mut(),
+        );
+        assert_eq!(write(&data), -1);
+        assert_eq!(_SSL_get_error(client, -1), SSL_ERROR_WANT_WRITE);
+        let mut total = 0;
+        while {
+            total += drain();
+            write(&moved) == -1
+        } {
+            assert_eq!(_SSL_get_error(client, -1), SSL_ERROR_WANT_WRITE);
+        }
+        assert_eq!(total + drain(), 4000);
+
+        // partial writes succeed with what was accepted.  a write that fails
+        // accepted nothing, so its retry need not be the same buffer.
+        _SSL_ctrl(
+            client,
+            SSL_CTRL_MODE,
+            SSL_MODE_ENABLE_PARTIAL_WRITE,
+            ptr::null_mut(),
+        );
+        _SSL_ctrl(
+            client,
+            c_int::from(SslCtrl::ClearMode),
+            SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER,
+            ptr::null_mut(),
+        );
+        let data = vec![b'b'; 100_000];
+        let buffers = [&data, &data.clone()];
+        let (mut sent, mut successes, mut failures, mut total) = (0, 0, 0, 0);
+        while sent < data.len() {
+            total += drain();
+            match write_ex(&buffers[failures % 2][sent..], &mut written) {
+                1 => {
+                    assert!(written > 0 && sent + written <= data.len());
+                    sent += written;
+                    successes += 1;
+                }
+                _ => {
+                    assert_eq!(_SSL_get_error(client, 0), SSL_ERROR_WANT_WRITE);
+                    assert_eq!(unsafe { openssl_sys::ERR_get_error() }, 0);
+                    failures += 1;
+                }
+            }
+        }
+        assert!(successes > 1 && failures > 0);
 
-// no post-handshake auth
+        // reading sends the rest, though there is nothing to read
+        let (mut buf, mut read) = ([0u8; 4], 7);
+        while total < data.len() {
+            assert_eq!(
+                _SSL_read_ex(client, buf.as_mut_ptr() as *mut c_void, 4, &mut read),
+                0
+            );
+            total += drain();
+        }
+        assert_eq!(total, data.len());
+        assert_eq!(
+            _SSL_read_ex(client, buf.as_mut_ptr() as *mut c_void, 4, &mut read),
+            0
+        );
+        assert_eq!((_SSL_get_error(client, 0), read), (SSL_ERROR_WANT_READ, 7));
 
-entry_stub! {
-    pub fn _SSL_CTX_set_post_handshake_auth(_ctx: *mut SSL_CTX, _val: c_int);
-}
+        free_pair(client, server, &[client_ctx, server_ctx]);
+    }
 
-entry_stub! {
-    pub fn _SSL_set_post_handshake_auth(_s: *mut SSL, _val: c_int);
-}
+    #[test]
+    fn test_SSL_peek() {
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert!(handshake(client, server));
+
+        let mut buf = [0u8; 64];
+        let mut peek = |len: c_int| {
+            let ret = _SSL_peek(client, buf.as_mut_ptr() as *mut c_void, len);
+            assert!(ret >= 0);
+            buf[..ret as usize].to_vec()
+        };
+        assert_eq!(
+            _SSL_write(server, b"hello world".as_ptr() as *const c_void, 11),
+            11
+        );
+        assert_eq!(_SSL_pending(client), 0);
 
-// No kTLS/sendfile support
+        // peeked data stays pending, and a larger peek returns what is buffered
+        assert_eq!(peek(5), b"hello");
+        assert_eq!((_SSL_pending(client), _SSL_has_pending(client)), (11, 1));
+        assert_eq!(peek(64), b"hello world");
+        assert_eq!(_SSL_pending(client), 11);
 
-entry_stub! {
-    pub fn _SSL_sendfile(
-        _ssl: *mut SSL,
-        _fd: c_int,
-        _offset: c_long,
-        _size: usize,
-        _flags: c_int,
-    ) -> c_long;
-}
+        // reads return peeked data in order
+        let mut read_buf = [0u8; 64];
+        assert_eq!(
+            _SSL_read(client, read_buf.as_mut_ptr() as *mut c_void, 3),
+            3
+        );
+        assert_eq!(&read_buf[..3], b"hel");
+        assert_eq!(_SSL_pending(client), 8);
+        assert_eq!(peek(64), b"lo world");
+        assert_eq!(
+            _SSL_read(client, read_buf.as_mut_ptr() as *mut c_void, 64),
+            8
+        );
+        assert_eq!(&read_buf[..8], b"lo world");
+        assert_eq!((_SSL_pending(client), _SSL_has_pending(client)), (0, 0));
 
-// ---------------------
+        let ret = _SSL_peek(client, read_buf.as_mut_ptr() as *mut c_void, 64);
+        assert_eq!(_SSL_get_error(client, ret), SSL_ERROR_WANT_READ);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert_eq!(_SSL_write(server, b"again".as_ptr() as *const c_void, 5), 5);
+        let mut read = 0;
+        assert_eq!(
+            _SSL_peek_ex(client, read_buf.as_mut_ptr() as *mut c_void, 2, &mut read),
+            1
+        );
+        assert_eq!((&read_buf[..read], _SSL_pending(client)), (&b"ag"[..], 5));
+        assert_eq!(
+            _SSL_read_ex(client, read_buf.as_mut_ptr() as *mut c_void, 64, &mut read),
+            1
+        );
+        assert_eq!(&read_buf[..read], b"again");
 
-    #[test]
-    fn test_SSL_CTX_new_null() {
-        assert!(_SSL_CTX_new(ptr::null()).is_null());
+        free_pair(client, server, &[client_ctx, server_ctx]);
     }
 
     #[test]
-    fn test_SSL_new_null() {
-        assert!(_SSL_new(ptr::null_mut()).is_null());
+    fn test_SSL_key_update() {
+        let (client_ctx, server_ctx) = (client_ctx("rsa"), server_ctx("rsa"));
+        let (client, server) = connect_pair(client_ctx, server_ctx, 0);
+        assert!(handshake(client, server));
+
+        assert_eq!(_SSL_key_update(client, SSL_KEY_UPDATE_NOT_REQUESTED), 0);
+        assert_eq!(_SSL_get_key_update_type(client), SSL_KEY_UPDATE_NONE);
+        assert_eq!(_SSL_key_update(client, SSL_KEY_UPDATE_REQUESTED), 1);
+        assert_eq!(_SSL_get_key_update_type(client), SSL_KEY_UPDATE_REQUESTED);
+        _SSL_set_msg_callback(client, Some(record_msg));
+        MSG_CALLS.take();
+        assert_eq!(_SSL_write(client, b"x".as_ptr() as *const c_void, 1), 1);
+        assert_eq!(_SSL_get_key_update_type(client), SSL_KEY_UPDATE_NONE);
+
+        // the `KeyUpdate` message is sent before the data, each with its
+        // content type and an AEAD tag
+        let lengths = MSG_CALLS
+            .take()
+            .into_iter()
+            .map(|(_, _, _, buf)| u16::from_be_bytes([buf[3], buf[4]]))
+            .collect::<Vec<_>>();
+        assert_eq!(lengths, vec![5 + 1 + 16, 1 + 1 + 16]);
+
+        // the server reads the data under the new keys, and updates its own
+        let mut buf = [0u8; 1];
+        assert_eq!(_SSL_read(server, buf.as_mut_ptr() as *mut c_void, 1), 1);
+        assert_eq!(_SSL_write(server, b"y".as_ptr() as *const c_void, 1), 1);
+        assert_eq!(_SSL_read(client, buf.as_mut_ptr() as *mut c_void, 1), 1);
+        assert_eq!(&buf, b"y");
+
+        free_pair(client, server, &[client_ctx, server_ctx]);
     }
 
     #[test]
-    fn test_SSL_up_ref_null() {
-        assert_eq!(_SSL_up_ref(ptr::null_mut()), 0);
+    fn test_SSL_client_hello_before_handshake() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        let ssl = _SSL_new(ctx);
+        let mut data = ptr::null();
+        let mut len = 0;
+        assert_eq!(_SSL_client_hello_isv2(ssl), 0);
+        assert_eq!(_SSL_client_hello_get0_legacy_version(ssl), 0);
+        assert_eq!(_SSL_client_hello_get0_random(ssl, &mut data), 0);
+        assert_eq!(_SSL_client_hello_get0_ciphers(ssl, &mut data), 0);
+        assert!(data.is_null());
+        assert_eq!(_SSL_client_hello_get0_ext(ssl, 0, &mut data, &mut len), 0);
+
+        let mut exts = ptr::null_mut();
+        assert_eq!(
+            _SSL_client_hello_get1_extensions_present(ssl, &mut exts, &mut len),
+            0
+        );
+        assert_eq!(_SSL_want(ssl), SSL_NOTHING);
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
     }
 
     #[test]
-    fn test_SSL_free() {
+    fn test_SSL_CTX_current_cert() {
         let ctx = _SSL_CTX_new(_TLS_method());
-        assert!(!ctx.is_null());
+        let set_current = c_int::from(SslCtrl::SetCurrentCert);
+        let select_current = c_int::from(SslCtrl::SelectCurrentCert);
+        assert_eq!(_SSL_CTX_ctrl(ctx, set_current, 1, ptr::null_mut()), 0);
+        cert_and_key_pairs(ctx);
+
+        // the last one added is current, but iteration is in OpenSSL's order
+        let rsa = _SSL_CTX_get0_certificate(ctx);
+        assert_eq!(_SSL_CTX_ctrl(ctx, set_current, 2, ptr::null_mut()), 1);
+        let ecdsa = _SSL_CTX_get0_certificate(ctx);
+        assert_ne!(rsa, ecdsa);
+        assert_eq!(_SSL_CTX_ctrl(ctx, set_current, 2, ptr::null_mut()), 0);
+        assert_eq!(_SSL_CTX_ctrl(ctx, set_current, 1, ptr::null_mut()), 1);
+        assert_eq!(_SSL_CTX_get0_certificate(ctx), rsa);
+
+        assert_eq!(
+            _SSL_CTX_ctrl(ctx, select_current, 0, ecdsa as *mut c_void),
+            1
+        );
+        assert_eq!(_SSL_CTX_get0_certificate(ctx), ecdsa);
+        assert_eq!(_SSL_CTX_ctrl(ctx, select_current, 0, ptr::null_mut()), 0);
+
+        // new `SSL`s inherit the current cert
         let ssl = _SSL_new(ctx);
-        assert!(!ssl.is_null());
+        assert_eq!(_SSL_get_certificate(ssl), ecdsa);
         _SSL_free(ssl);
         _SSL_CTX_free(ctx);
     }
 
     #[test]
-    fn test_SSL_free_after_up_ref() {
+    fn test_SSL_CTX_use_cert_and_key() {
+        let source = _SSL_CTX_new(_TLS_method());
+        let [(ecdsa_cert, ecdsa_key), (rsa_cert, rsa_key)] = cert_and_key_pairs(source);
+
         let ctx = _SSL_CTX_new(_TLS_method());
-        assert!(!ctx.is_null());
-        let ssl = _SSL_new(ctx);
-        assert!(!ssl.is_null());
-        assert_eq!(_SSL_up_ref(ssl), 1);
-        _SSL_free(ssl); // ref 2
-        _SSL_free(ssl); // ref 1
+        let none = ptr::null_mut();
+        assert_eq!(
+            _SSL_CTX_use_cert_and_key(ctx, rsa_cert, ecdsa_key, none, 1),
+            0
+        );
+        assert_eq!(
+            _SSL_CTX_use_cert_and_key(ctx, rsa_cert, rsa_key, none, 0),
+            C_INT_SUCCESS
+        );
+
+        // only replaces a key of the same type if asked to
+        assert_eq!(
+            _SSL_CTX_use_cert_and_key(ctx, rsa_cert, rsa_key, none, 0),
+            0
+        );
+        assert_eq!(
+            _SSL_CTX_use_cert_and_key(ctx, rsa_cert, rsa_key, none, 1),
+            C_INT_SUCCESS
+        );
+        assert_eq!(
+            _SSL_CTX_use_cert_and_key(ctx, ecdsa_cert, ecdsa_key, none, 0),
+            C_INT_SUCCESS
+        );
+        assert!(!_SSL_CTX_get0_certificate(ctx).is_null());
         _SSL_CTX_free(ctx);
-    }
 
-    #[test]
-    fn test_SSL_CTX_set_alpn_protos_works() {
+        // without a key, the certificate awaits one
         let ctx = _SSL_CTX_new(_TLS_method());
         assert_eq!(
-            _SSL_CTX_set_alpn_protos(ctx, b"\x05hello" as *const u8, 6) as i32,
-            0i32
+            _SSL_CTX_use_cert_and_key(ctx, ecdsa_cert, ptr::null_mut(), none, 0),
+            C_INT_SUCCESS
         );
+        assert!(_SSL_CTX_get0_certificate(ctx).is_null());
+        assert_eq!(_SSL_CTX_use_PrivateKey(ctx, ecdsa_key), C_INT_SUCCESS);
+        assert!(!_SSL_CTX_get0_certificate(ctx).is_null());
+
         _SSL_CTX_free(ctx);
+        _SSL_CTX_free(source);
     }
 
     #[test]
-    fn test_SSL_CTX_set_alpn_protos_null_ctx() {
+    fn test_use_certificate_and_PrivateKey_ASN1() {
+        let source = _SSL_CTX_new(_TLS_method());
+        // nb. `i2d_PrivateKey` gives PKCS#1 and SEC1 encodings, not PKCS#8
+        let [(ecdsa_cert, ecdsa_key), (rsa_cert, rsa_key)] =
+            cert_and_key_pairs(source).map(|(cert, key)| unsafe {
+                (
+                    der_bytes(|out| openssl_sys::i2d_X509(cert, out)),
+                    der_bytes(|out| openssl_sys::i2d_PrivateKey(key, out)),
+                )
+            });
+
+        let ctx = _SSL_CTX_new(_TLS_server_method());
+        assert_eq!(_SSL_CTX_use_certificate_ASN1(ctx, 3, b"bad".as_ptr()), 0);
         assert_eq!(
-            _SSL_CTX_set_alpn_protos(ptr::null_mut(), b"\x05hello" as *const u8, 6) as i32,
-            1i32
+            _SSL_CTX_use_certificate_ASN1(ctx, rsa_cert.len() as c_int, rsa_cert.as_ptr()),
+            C_INT_SUCCESS
+        );
+        assert_eq!(
+            _SSL_CTX_use_PrivateKey_ASN1(
+                EVP_PKEY_EC,
+                ctx,
+                rsa_key.as_ptr(),
+                rsa_key.len() as c_long
+            ),
+            0
+        );
+        assert_eq!(
+            _SSL_CTX_use_PrivateKey_ASN1(
+                EVP_PKEY_RSA,
+                ctx,
+                rsa_key.as_ptr(),
+                rsa_key.len() as c_long
+            ),
+            C_INT_SUCCESS
+        );
+        let rsa_client_ctx = client_ctx("rsa");
+        let (client, server) = connect_pair(rsa_client_ctx, ctx, 0);
+        assert!(handshake(client, server));
+        free_pair(client, server, &[rsa_client_ctx]);
+
+        // the `SSL` versions, leaving the `SSL_CTX` alone
+        let ecdsa_client_ctx = client_ctx("ecdsa-p256");
+        let (client, server) = connect_pair(ecdsa_client_ctx, ctx, 0);
+        assert_eq!(_SSL_use_certificate_ASN1(server, b"bad".as_ptr(), 3), 0);
+        assert_eq!(
+            _SSL_use_certificate_ASN1(server, ecdsa_cert.as_ptr(), ecdsa_cert.len() as c_int),
+            C_INT_SUCCESS
+        );
+        assert_eq!(
+            _SSL_use_PrivateKey_ASN1(
+                EVP_PKEY_RSA,
+                server,
+                ecdsa_key.as_ptr(),
+                ecdsa_key.len() as c_long
+            ),
+            0
+        );
+        assert_eq!(
+            _SSL_use_PrivateKey_ASN1(
+                EVP_PKEY_EC,
+                server,
+                ecdsa_key.as_ptr(),
+                ecdsa_key.len() as c_long
+            ),
+            C_INT_SUCCESS
         );
+        assert_ne!(_SSL_get_certificate(server), _SSL_CTX_get0_certificate(ctx));
+        assert!(handshake(client, server));
+        free_pair(client, server, &[ecdsa_client_ctx, ctx, source]);
     }
 
-    #[test]
-    fn test_SSL_CTX_set_alpn_protos_null_proto() {
-        let ctx = _SSL_CTX_new(_TLS_method());
-        assert_eq!(_SSL_CTX_set_alpn_protos(ctx, ptr::null(), 6) as i32, 1i32);
-        _SSL_CTX_free(ctx);
+    /// Neither `SSL_FILETYPE_PEM` nor `SSL_FILETYPE_ASN1`.
+    const FILETYPE_INVALID: c_int = 3;
+
+    /// Write the DER form of the PEM certificate `pem`, returning the new file's path.
+    fn cert_der_file(pem: &str, name: &str) -> std::path::PathBuf {
+        let mut reader = io::BufReader::new(fs::File::open(pem).unwrap());
+        let cert = rustls_pemfile::certs(&mut reader).next().unwrap().unwrap();
+        let path =
+            std::env::temp_dir().join(format!("rustls-libssl-{}-{name}", std::process::id()));
+        fs::write(&path, cert.as_ref()).unwrap();
+        path
     }
 
     #[test]
-    fn test_SSL_CTX_set_alpn_protos_invalid_proto() {
+    fn test_SSL_CTX_use_certificate_file() {
         let ctx = _SSL_CTX_new(_TLS_method());
+        let cert = c"test-ca/rsa/server.cert";
+        let der = cert_der_file("test-ca/rsa/server.cert", "ctx-server.der");
+        let der_name = CString::new(der.to_str().unwrap()).unwrap();
+
         assert_eq!(
-            _SSL_CTX_set_alpn_protos(ctx, b"\x05hell" as *const u8, 5) as i32,
-            1i32
+            _SSL_CTX_use_certificate_file(ctx, cert.as_ptr(), FILETYPE_INVALID),
+            0
         );
-        _SSL_CTX_free(ctx);
-    }
-
-    #[test]
-    fn test_SSL_set_alpn_protos_works() {
-        let ctx = _SSL_CTX_new(_TLS_method());
-        let ssl = _SSL_new(ctx);
         assert_eq!(
-            _SSL_set_alpn_protos(ssl, b"\x05hello" as *const u8, 6) as i32,
-            0i32
+            _SSL_CTX_use_certificate_file(ctx, c"test-ca/missing".as_ptr(), FILETYPE_PEM),
+            0
         );
-        _SSL_free(ssl);
-        _SSL_CTX_free(ctx);
-    }
+        assert_eq!(
+            _SSL_CTX_use_certificate_file(ctx, cert.as_ptr(), FILETYPE_DER),
+            0
+        );
+        assert!(_SSL_CTX_get0_certificate(ctx).is_null());
 
-    #[test]
-    fn test_SSL_set_alpn_protos_null_ssl() {
         assert_eq!(
-            _SSL_set_alpn_protos(ptr::null_mut(), b"\x05hello" as *const u8, 6) as i32,
-            1i32
+            _SSL_CTX_use_certificate_file(ctx, der_name.as_ptr(), FILETYPE_DER),
+            C_INT_SUCCESS
+        );
+        assert_eq!(
+            _SSL_CTX_use_PrivateKey_file(ctx, c"test-ca/rsa/server.key".as_ptr(), FILETYPE_PEM),
+            C_INT_SUCCESS
+        );
+        let from_der = _SSL_CTX_get0_certificate(ctx);
+        assert!(!from_der.is_null());
+        assert_eq!(
+            _SSL_CTX_use_certificate_file(ctx, cert.as_ptr(), FILETYPE_PEM),
+            C_INT_SUCCESS
+        );
+        assert_eq!(
+            unsafe { openssl_sys::X509_cmp(from_der, _SSL_CTX_get0_certificate(ctx)) },
+            0
         );
-    }
 
-    #[test]
-    fn test_SSL_set_alpn_protos_null_proto() {
-        let ctx = _SSL_CTX_new(_TLS_method());
-        let ssl = _SSL_new(ctx);
-        assert_eq!(_SSL_set_alpn_protos(ssl, ptr::null(), 6) as i32, 1i32);
-        _SSL_free(ssl);
+        fs::remove_file(der).unwrap();
         _SSL_CTX_free(ctx);
     }
 
     #[test]
-    fn test_SSL_set_alpn_protos_invalid_proto() {
+    fn test_SSL_use_certificate_file() {
         let ctx = _SSL_CTX_new(_TLS_method());
         let ssl = _SSL_new(ctx);
+        let cert = c"test-ca/rsa/end.cert";
         assert_eq!(
-            _SSL_set_alpn_protos(ssl, b"\x05hell" as *const u8, 5) as i32,
-            1i32
+            _SSL_use_certificate_file(ssl, cert.as_ptr(), FILETYPE_INVALID),
+            0
+        );
+        assert_eq!(
+            _SSL_use_certificate_file(ssl, c"test-ca/missing".as_ptr(), FILETYPE_PEM),
+            0
+        );
+        assert_eq!(
+            _SSL_use_certificate_file(ssl, c"test-ca/rsa/end.key".as_ptr(), FILETYPE_PEM),
+            0
+        );
+        assert_eq!(
+            _SSL_use_certificate_file(ssl, cert.as_ptr(), FILETYPE_DER),
+            0
         );
-        _SSL_free(ssl);
-        _SSL_CTX_free(ctx);
-    }
 
-    #[test]
-    fn test_SSL_select_next_proto_match() {
-        let mut output = ptr::null_mut();
-        let mut output_len = 0u8;
-        let client = b"\x05hello\x05world";
-        let server = b"\x05uhoh!\x05world";
         assert_eq!(
-            _SSL_select_next_proto(
-                &mut output as *mut *mut u8,
-                &mut output_len as *mut u8,
-                server.as_ptr(),
-                server.len() as c_uint,
-                client.as_ptr(),
-                client.len() as c_uint
-            ),
-            1i32
+            _SSL_use_certificate_file(ssl, cert.as_ptr(), FILETYPE_PEM),
+            C_INT_SUCCESS
         );
-        assert_eq!(b"world", &server[7..]);
-        assert_eq!(output as *const u8, server[7..].as_ptr());
-        assert_eq!(output_len, 5);
-    }
+        assert_eq!(
+            _SSL_use_PrivateKey_file(ssl, c"test-ca/rsa/end.key".as_ptr(), FILETYPE_PEM),
+            C_INT_SUCCESS
+        );
+        assert!(!_SSL_get_certificate(ssl).is_null());
+        assert!(_SSL_CTX_get0_certificate(ctx).is_null());
 
-    #[test]
-    fn test_SSL_select_next_proto_no_overlap() {
-        let mut output = ptr::null_mut();
-        let mut output_len = 0u8;
-        let client = b"\x05hello\x05world";
-        let server = b"\x05uhoh!\x05what!";
+        let der = cert_der_file("test-ca/rsa/end.cert", "ssl-end.der");
+        let der_name = CString::new(der.to_str().unwrap()).unwrap();
         assert_eq!(
-            _SSL_select_next_proto(
-                &mut output as *mut *mut u8,
-                &mut output_len as *mut u8,
-                server.as_ptr(),
-                server.len() as c_uint,
-                client.as_ptr(),
-                client.len() as c_uint
-            ),
-            2i32
+            _SSL_use_certificate_file(ssl, der_name.as_ptr(), FILETYPE_DER),
+            C_INT_SUCCESS
         );
-        assert_eq!(b"hello", &client[1..6]);
-        assert_eq!(output as *const u8, client[1..].as_ptr());
-        assert_eq!(output_len, 5);
-    }
+        assert_eq!(
+            _SSL_use_PrivateKey_file(ssl, c"test-ca/rsa/end.key".as_ptr(), FILETYPE_PEM),
+            C_INT_SUCCESS
+        );
+        fs::remove_file(der).unwrap();
 
-    #[test]
-    fn test_SSL_select_next_proto_illegal_client() {
-        let mut output = ptr::null_mut();
-        let mut output_len = 0u8;
-        let client = b"\x09hello";
-        let server = b"\x05uhoh!\x05world";
         assert_eq!(
-            _SSL_select_next_proto(
-                &mut output as *mut *mut u8,
-                &mut output_len as *mut u8,
-                server.as_ptr(),
-                server.len() as c_uint,
-                client.as_ptr(),
-                client.len() as c_uint
-            ),
-            2i32
+            _SSL_use_certificate_chain_file(ssl, c"test-ca/ecdsa-p256/server.cert".as_ptr()),
+            C_INT_SUCCESS
         );
-        assert_eq!(output as *const u8, ptr::null_mut());
+        assert_eq!(
+            _SSL_use_PrivateKey_file(ssl, c"test-ca/ecdsa-p256/server.key".as_ptr(), FILETYPE_PEM),
+            C_INT_SUCCESS
+        );
+
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
     }
 
     #[test]
-    fn test_SSL_select_next_proto_null() {
-        let mut output = ptr::null_mut();
-        let mut output_len = 0u8;
-        let client = b"\x05hello\x05world";
-        let server = b"\x05uhoh!\x05world";
+    fn test_SSL_set_SSL_CTX() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        let vhost = _SSL_CTX_new(_TLS_method());
+        _SSL_CTX_set_verify(vhost, 3, None);
+        let vhost_options = _SSL_CTX_set_options(vhost, 0x4000);
+        assert_eq!(_SSL_CTX_set_num_tickets(vhost, 5), 1);
+        assert_eq!(_SSL_CTX_set_max_early_data(vhost, 1024), 1);
+
+        let ssl = _SSL_new(ctx);
+        let options = _SSL_set_options(ssl, 0x1);
+        assert_eq!(_SSL_set_SSL_CTX(ssl, ctx), ctx);
+        assert_eq!(_SSL_get_options(ssl), options);
+        assert_eq!(_SSL_get_verify_mode(ssl), 0);
+
+        assert_eq!(_SSL_set_SSL_CTX(ssl, vhost), vhost);
+        assert_eq!(_SSL_get_SSL_CTX(ssl), vhost);
+        assert_eq!(_SSL_get_options(ssl), vhost_options);
+        assert_eq!(_SSL_get_verify_mode(ssl), 3);
+        assert_eq!(_SSL_get_num_tickets(ssl), 5);
+        assert_eq!(_SSL_get_max_early_data(ssl), 1024);
+        _SSL_free(ssl);
+        _SSL_CTX_free(vhost);
+        _SSL_CTX_free(ctx);
 
+        // now switch from the servername callback, as for virtual hosting.
+        // the default ctx cannot sign with ECDSA, so the handshake needs the vhost's
+        // certificate and sigalgs.
+        let set_sigalgs = c_int::from(SslCtrl::SetSigalgsList);
+        let default = server_ctx("rsa");
         assert_eq!(
-            _SSL_select_next_proto(
-                ptr::null_mut(),
-                &mut output_len as *mut u8,
-                server.as_ptr(),
-                server.len() as c_uint,
-                client.as_ptr(),
-                client.len() as c_uint
+            _SSL_CTX_ctrl(
+                default,
+                set_sigalgs,
+                0,
+                c"RSA-PSS+SHA256".as_ptr() as *mut c_void
             ),
-            0
+            1
         );
-
         assert_eq!(
-            _SSL_select_next_proto(
-                &mut output as *mut *mut u8,
-                ptr::null_mut(),
-                server.as_ptr(),
-                server.len() as c_uint,
-                client.as_ptr(),
-                client.len() as c_uint
-            ),
+            _SSL_CTX_set_alpn_protos(default, b"\x02h2".as_ptr(), 3) as i32,
             0
         );
-
+        let vhost = server_ctx("ecdsa-p256");
         assert_eq!(
-            _SSL_select_next_proto(
-                &mut output as *mut *mut u8,
-                &mut output_len as *mut u8,
-                ptr::null(),
-                server.len() as c_uint,
-                client.as_ptr(),
-                client.len() as c_uint
+            _SSL_CTX_ctrl(
+                vhost,
+                set_sigalgs,
+                0,
+                c"ECDSA+SHA256:RSA-PSS+SHA256".as_ptr() as *mut c_void
             ),
+            1
+        );
+        assert_eq!(
+            _SSL_CTX_set_alpn_protos(vhost, b"\x08http/1.1".as_ptr(), 9) as i32,
             0
         );
+        _SSL_CTX_set_verify(vhost, 3, None);
+        assert_eq!(
+            _SSL_CTX_load_verify_file(vhost, c"test-ca/rsa/ca.cert".as_ptr()),
+            C_INT_SUCCESS
+        );
+        _SSL_CTX_sess_set_new_cb(vhost, Some(record_new_session));
+        let callback = unsafe {
+            mem::transmute::<
+                extern "C" fn(*mut SSL, *mut c_int, *mut c_void) -> c_int,
+                unsafe extern "C" fn(),
+            >(switch_ctx)
+        };
+        _SSL_CTX_callback_ctrl(
+            default,
+            c_int::from(SslCtrl::SetTlsExtServerNameCallback),
+            Some(callback),
+        );
+        _SSL_CTX_ctrl(
+            default,
+            c_int::from(SslCtrl::SetTlsExtServerNameArg),
+            0,
+            vhost as *mut c_void,
+        );
 
+        let client_ctx = client_ctx("ecdsa-p256");
         assert_eq!(
-            _SSL_select_next_proto(
-                &mut output as *mut *mut u8,
-                &mut output_len as *mut u8,
-                server.as_ptr(),
-                server.len() as c_uint,
-                ptr::null(),
-                client.len() as c_uint
+            _SSL_CTX_use_certificate_chain_file(client_ctx, c"test-ca/rsa/client.cert".as_ptr()),
+            C_INT_SUCCESS
+        );
+        assert_eq!(
+            _SSL_CTX_use_PrivateKey_file(
+                client_ctx,
+                c"test-ca/rsa/client.key".as_ptr(),
+                FILETYPE_PEM
             ),
+            C_INT_SUCCESS
+        );
+        assert_eq!(
+            _SSL_CTX_set_alpn_protos(client_ctx, b"\x02h2\x08http/1.1".as_ptr(), 12) as i32,
             0
         );
-    }
 
-    #[test]
-    fn test_SSL_SESSION_roundtrip() {
-        let sess = crate::SslSession::new(
-            vec![1; 32],
-            vec![2; 32],
-            vec![3; 128],
-            crate::cache::TimeBase(123),
-            300,
-        );
-        let sess_ptr = to_arc_mut_ptr(NotThreadSafe::new(sess));
+        // the session (from the vhost's ticketer, or else its cache) resumes
+        // on the vhost itself
+        for no_ticket in [false, true] {
+            if no_ticket {
+                _SSL_CTX_set_options(vhost, crate::SSL_OP_NO_TICKET);
+            }
 
-        let mut buffer = [0u8; 1024];
-        let mut ptr = buffer.as_mut_ptr();
-        let len = _i2d_SSL_SESSION(sess_ptr, &mut ptr);
+            let (client, server) = connect_pair(client_ctx, default, 0);
+            NEW_SESSIONS.take();
+            assert!(handshake(client, server));
+            let mut buf = [0u8; 1];
+            assert_eq!(_SSL_write(server, b"x".as_ptr() as *const c_void, 1), 1);
+            assert_eq!(_SSL_read(client, buf.as_mut_ptr() as *mut c_void, 1), 1);
+            assert_eq!(_SSL_get_SSL_CTX(server), vhost);
+            assert_eq!(NEW_SESSIONS.take().contains(&server), no_ticket);
+
+            let (mut alpn, mut alpn_len) = (ptr::null(), 0);
+            _SSL_get0_alpn_selected(client, &mut alpn, &mut alpn_len);
+            assert_eq!(
+                unsafe { core::slice::from_raw_parts(alpn, alpn_len as usize) },
+                b"http/1.1"
+            );
+            assert!(!_SSL_get0_peer_certificate(server).is_null());
+            assert_eq!(_SSL_get_verify_result(server), X509_V_OK as c_long);
+            let session = _SSL_get1_session(client);
+            free_pair(client, server, &[]);
+
+            let (resumed, vhost_server) = connect_pair(client_ctx, vhost, 0);
+            assert_eq!(_SSL_set_session(resumed, session), 1);
+            _SSL_SESSION_free(session);
+            assert!(handshake(resumed, vhost_server));
+            assert_eq!(_SSL_session_reused(resumed), 1);
+            free_pair(resumed, vhost_server, &[]);
+        }
 
-        println!("encoding: {:?}", &buffer[..len as usize]);
+        // a vhost whose verification roots cannot be loaded is still switched
+        // to, but fails the handshake
+        let broken = server_ctx("ecdsa-p256");
+        crate::ffi::clone_arc(broken)
+            .unwrap()
+            .get_mut()
+            .default_cert_file = Some("test-ca/missing.pem".into());
+        _SSL_CTX_ctrl(
+            default,
+            c_int::from(SslCtrl::SetTlsExtServerNameArg),
+            0,
+            broken as *mut c_void,
+        );
+        let (client, server) = connect_pair(client_ctx, default, 0);
+        assert!(!handshake(client, server));
+        assert_eq!(_SSL_get_SSL_CTX(server), broken);
+        unsafe { openssl_sys::ERR_clear_error() };
+        free_pair(client, server, &[broken]);
 
-        let mut ptr = buffer.as_ptr();
-        let new_sess = _d2i_SSL_SESSION(ptr::null_mut(), &mut ptr, buffer.len() as c_long);
-        assert!(!new_sess.is_null());
-        assert_eq!(len as usize, (ptr as usize) - (buffer.as_ptr() as usize));
+        _SSL_CTX_free(client_ctx);
+        _SSL_CTX_free(vhost);
+        _SSL_CTX_free(default);
+    }
 
-        _SSL_SESSION_free(new_sess);
-        _SSL_SESSION_free(sess_ptr);
+    #[test]
+    fn test_SSL_mode() {
+        let ctx = _SSL_CTX_new(_TLS_method());
+        let mode = c_int::from(SslCtrl::Mode);
+        let clear_mode = c_int::from(SslCtrl::ClearMode);
+        // `SSL_MODE_AUTO_RETRY` is on by default
+        assert_eq!(_SSL_CTX_ctrl(ctx, mode, 0, ptr::null_mut()), 0x4);
+        assert_eq!(_SSL_CTX_ctrl(ctx, mode, 0x1, ptr::null_mut()), 0x5);
+        assert_eq!(_SSL_CTX_ctrl(ctx, clear_mode, 0x4, ptr::null_mut()), 0x1);
+
+        let ssl = _SSL_new(ctx);
+        assert_eq!(_SSL_ctrl(ssl, mode, 0x2, ptr::null_mut()), 0x3);
+        assert_eq!(_SSL_ctrl(ssl, clear_mode, 0x3, ptr::null_mut()), 0);
+        assert_eq!(_SSL_CTX_ctrl(ctx, mode, 0, ptr::null_mut()), 0x1);
+        _SSL_free(ssl);
+        _SSL_CTX_free(ctx);
     }
 }
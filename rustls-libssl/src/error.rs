@@ -26,6 +26,8 @@ enum Reason {
     OperationFailed,
     Unsupported,
     WouldBlock,
+    NoCipherMatch,
+    InvalidCommand,
     Alert(AlertDescription),
 }
 
@@ -40,12 +42,14 @@ impl From<Reason> for c_int {
             Unsupported => ERR_RFLAG_COMMON | 268,
             WouldBlock => 0,
             // `sslerr.h`
+            NoCipherMatch => 185,
+            InvalidCommand => 280,
             Alert(alert) => 1000 + u8::from(alert) as c_int,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Error {
     lib: Lib,
     reason: Reason,
@@ -85,6 +89,22 @@ impl Error {
         }
     }
 
+    pub fn no_cipher_match() -> Self {
+        Self {
+            lib: Lib::Ssl,
+            reason: Reason::NoCipherMatch,
+            string: None,
+        }
+    }
+
+    pub fn invalid_command(hint: &str) -> Self {
+        Self {
+            lib: Lib::Ssl,
+            reason: Reason::InvalidCommand,
+            string: Some(hint.to_string()),
+        }
+    }
+
     pub fn from_rustls(err: rustls::Error) -> Self {
         match err {
             rustls::Error::AlertReceived(alert) => Self {
@@ -144,6 +164,10 @@ impl Error {
         self
     }
 
+    pub fn is_would_block(&self) -> bool {
+        self.quiet()
+    }
+
     /// `WouldBlock` errors never make it on the error stack.
     ///
     /// They are usual in the use of non-blocking BIOs.
@@ -228,6 +252,18 @@ impl From<Error> for crate::entry::SSL_verify_cb {
     }
 }
 
+impl From<Error> for crate::entry::SSL_CTX_keylog_cb_func {
+    fn from(_: Error) -> crate::entry::SSL_CTX_keylog_cb_func {
+        None
+    }
+}
+
+impl From<Error> for crate::entry::SSL_info_callback_func {
+    fn from(_: Error) -> crate::entry::SSL_info_callback_func {
+        None
+    }
+}
+
 #[macro_export]
 macro_rules! ffi_panic_boundary {
     ( $($tt:tt)* ) => {
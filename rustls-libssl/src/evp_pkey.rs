@@ -5,9 +5,9 @@ use std::slice;
 use openssl_sys::{
     d2i_AutoPrivateKey, i2d_PUBKEY, EVP_DigestSign, EVP_DigestSignInit, EVP_MD_CTX_free,
     EVP_MD_CTX_new, EVP_PKEY_CTX_set_rsa_padding, EVP_PKEY_CTX_set_rsa_pss_saltlen,
-    EVP_PKEY_CTX_set_signature_md, EVP_PKEY_free, EVP_PKEY_up_ref, EVP_sha256, EVP_sha384,
-    EVP_sha512, OPENSSL_free, EVP_MD, EVP_MD_CTX, EVP_PKEY, EVP_PKEY_CTX, RSA_PKCS1_PADDING,
-    RSA_PKCS1_PSS_PADDING,
+    EVP_PKEY_CTX_set_signature_md, EVP_PKEY_free, EVP_PKEY_id, EVP_PKEY_up_ref, EVP_sha256,
+    EVP_sha384, EVP_sha512, OPENSSL_free, EVP_MD, EVP_MD_CTX, EVP_PKEY, EVP_PKEY_CTX,
+    RSA_PKCS1_PADDING, RSA_PKCS1_PSS_PADDING,
 };
 use rustls::pki_types::PrivateKeyDer;
 
@@ -87,6 +87,11 @@ impl EvpPkey {
         self.pkey as *mut EVP_PKEY
     }
 
+    /// The `EVP_PKEY_*` type of this key.
+    pub fn id(&self) -> c_int {
+        unsafe { EVP_PKEY_id(self.pkey) }
+    }
+
     fn is_rsa_type(&self) -> bool {
         self.is_a(c"RSA") || self.is_a(c"RSA-PSS")
     }
@@ -1,5 +1,6 @@
-use core::ffi::{c_int, c_void};
+use core::ffi::{c_int, c_long, c_void};
 use core::ptr;
+use std::sync::OnceLock;
 
 use crate::entry::{SSL, SSL_CTX};
 use crate::error::Error;
@@ -78,6 +79,25 @@ impl Default for ExData {
     }
 }
 
+/// Returns the index used to store the `SSL*` in `X509_STORE_CTX`s given
+/// to verify callbacks.
+///
+/// See `SSL_get_ex_data_X509_STORE_CTX_idx`.  Returns -1 on error.
+pub fn ssl_x509_store_ctx_idx() -> c_int {
+    static IDX: OnceLock<c_int> = OnceLock::new();
+
+    *IDX.get_or_init(|| unsafe {
+        CRYPTO_get_ex_new_index(
+            CRYPTO_EX_INDEX_X509_STORE_CTX,
+            0,
+            c"SSL for verify callback".as_ptr() as *mut c_void,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    })
+}
+
 /// This has the same layout prefix as `struct crypto_ex_data_st` aka
 /// `CRYPTO_EX_DATA` -- just two pointers.  We don't need to know
 /// the types of these; the API lets us treat them opaquely.
@@ -101,10 +121,19 @@ impl Default for CRYPTO_EX_DATA {
 // See `crypto.h`
 const CRYPTO_EX_INDEX_SSL: c_int = 0;
 const CRYPTO_EX_INDEX_SSL_CTX: c_int = 1;
+const CRYPTO_EX_INDEX_X509_STORE_CTX: c_int = 5;
 
 extern "C" {
     fn CRYPTO_new_ex_data(class_index: c_int, obj: *mut c_void, ed: *mut CRYPTO_EX_DATA) -> c_int;
     fn CRYPTO_set_ex_data(ed: *mut CRYPTO_EX_DATA, index: c_int, data: *mut c_void) -> c_int;
     fn CRYPTO_get_ex_data(ed: *const CRYPTO_EX_DATA, index: c_int) -> *mut c_void;
     fn CRYPTO_free_ex_data(class_index: c_int, obj: *mut c_void, ed: *mut CRYPTO_EX_DATA);
+    fn CRYPTO_get_ex_new_index(
+        class_index: c_int,
+        argl: c_long,
+        argp: *mut c_void,
+        new_func: *mut c_void,
+        dup_func: *mut c_void,
+        free_func: *mut c_void,
+    ) -> c_int;
 }
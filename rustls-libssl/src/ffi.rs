@@ -425,6 +425,18 @@ macro_rules! try_slice {
 
 pub(crate) use try_slice;
 
+macro_rules! try_mut_slice {
+    ( $ptr:expr, $count:expr ) => {
+        if $ptr.is_null() {
+            return $crate::error::Error::null_pointer().raise().into();
+        } else {
+            unsafe { ::core::slice::from_raw_parts_mut($ptr, $count as usize) }
+        }
+    };
+}
+
+pub(crate) use try_mut_slice;
+
 pub(crate) fn string_from_cstring(s: *const c_char) -> Option<String> {
     if s.is_null() {
         return None;
@@ -1,31 +1,41 @@
 use core::ffi::{c_char, c_int, c_uint, c_void, CStr};
+use core::fmt::Write as _;
 use core::{borrow, cmp, fmt, mem, ptr};
 use std::ffi::CString;
 use std::fs;
-use std::io::{ErrorKind, Read, Write};
+use std::io::{self, ErrorKind, Read, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use openssl_probe::ProbeResult;
 use openssl_sys::{
-    EVP_PKEY, SSL_ERROR_NONE, SSL_ERROR_SSL, SSL_ERROR_WANT_READ, SSL_ERROR_WANT_WRITE, X509,
-    X509_STORE, X509_V_ERR_UNSPECIFIED,
+    stack_st_SSL_CIPHER, EVP_PKEY, SSL_ERROR_NONE, SSL_ERROR_SSL, SSL_ERROR_WANT_CLIENT_HELLO_CB,
+    SSL_ERROR_WANT_READ, SSL_ERROR_WANT_WRITE, TLS1_3_VERSION, X509, X509_STORE,
+    X509_V_ERR_UNSPECIFIED,
 };
 use rustls::client::Resumption;
-use rustls::crypto::{aws_lc_rs as provider, SupportedKxGroup};
+use rustls::crypto::{aws_lc_rs as provider, CryptoProvider, SupportedKxGroup};
 use rustls::pki_types::{CertificateDer, ServerName};
 use rustls::server::{Accepted, Acceptor, ProducesTickets};
 use rustls::{
-    CipherSuite, ClientConfig, ClientConnection, Connection, HandshakeKind, ProtocolVersion,
-    RootCertStore, ServerConfig, SignatureScheme, SupportedProtocolVersion,
+    AlertDescription, CipherSuite, ClientConfig, ClientConnection, Connection, HandshakeKind,
+    NamedGroup, ProtocolVersion, RootCertStore, ServerConfig, SignatureScheme,
+    SupportedProtocolVersion,
 };
 
+use constants::{
+    alert_to_info_value, sent_alert_for_error, SSL3_AL_FATAL, SSL3_AL_WARNING, SSL_CB_EXIT,
+    SSL_CB_HANDSHAKE_DONE, SSL_CB_HANDSHAKE_START, SSL_CB_LOOP, SSL_CB_READ_ALERT,
+    SSL_CB_WRITE_ALERT, SSL_ST_ACCEPT, SSL_ST_CONNECT,
+};
 use not_thread_safe::NotThreadSafe;
 use x509::OwnedX509Store;
 
 mod bio;
 mod cache;
 mod callbacks;
+mod cipher_list;
+mod client_hello;
 #[macro_use]
 mod constants;
 #[allow(
@@ -48,6 +58,7 @@ mod conf;
 #[allow(non_camel_case_types, dead_code)]
 mod miri;
 mod not_thread_safe;
+mod session;
 mod sign;
 mod verifier;
 mod x509;
@@ -98,6 +109,8 @@ pub struct SslCipher {
     pub version: &'static CStr,
     pub description: &'static CStr,
     rustls: &'static rustls::SupportedCipherSuite,
+    /// Attributes for matching cipher list aliases; see `cipher_list`.
+    attrs: u32,
 }
 
 impl SslCipher {
@@ -137,8 +150,30 @@ impl SslCipher {
     }
 }
 
+/// Supported TLS1.2 ciphers, in OpenSSL's default preference order.
+static TLS12_CIPHERS: &[&SslCipher] = &[
+    &TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    &TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    &TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+    &TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+    &TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+    &TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+];
+
+/// Supported TLS1.3 ciphersuites, in OpenSSL's default preference order.
+static TLS13_CIPHERS: &[&SslCipher] = &[
+    &TLS13_AES_256_GCM_SHA384,
+    &TLS13_CHACHA20_POLY1305_SHA256,
+    &TLS13_AES_128_GCM_SHA256,
+];
+
 static TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256: SslCipher = SslCipher {
     rustls: &provider::cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+    attrs: cipher_list::TLS12
+        | cipher_list::KX_ECDHE
+        | cipher_list::AUTH_ECDSA
+        | cipher_list::ENC_AES128GCM
+        | cipher_list::FIPS,
     bits: 128,
     openssl_name: c"ECDHE-ECDSA-AES128-GCM-SHA256",
     standard_name: c"TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
@@ -148,6 +183,11 @@ static TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256: SslCipher = SslCipher {
 
 static TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384: SslCipher = SslCipher {
     rustls: &provider::cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    attrs: cipher_list::TLS12
+        | cipher_list::KX_ECDHE
+        | cipher_list::AUTH_ECDSA
+        | cipher_list::ENC_AES256GCM
+        | cipher_list::FIPS,
     bits: 256,
     openssl_name: c"ECDHE-ECDSA-AES256-GCM-SHA384",
     standard_name: c"TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
@@ -157,6 +197,10 @@ static TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384: SslCipher = SslCipher {
 
 static TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256: SslCipher = SslCipher {
     rustls: &provider::cipher_suite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+    attrs: cipher_list::TLS12
+        | cipher_list::KX_ECDHE
+        | cipher_list::AUTH_ECDSA
+        | cipher_list::ENC_CHACHA20POLY1305,
     bits: 256,
     openssl_name: c"ECDHE-ECDSA-CHACHA20-POLY1305",
     standard_name: c"TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
@@ -166,6 +210,11 @@ static TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256: SslCipher = SslCipher {
 
 static TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: SslCipher = SslCipher {
     rustls: &provider::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    attrs: cipher_list::TLS12
+        | cipher_list::KX_ECDHE
+        | cipher_list::AUTH_RSA
+        | cipher_list::ENC_AES128GCM
+        | cipher_list::FIPS,
     bits: 128,
     openssl_name: c"ECDHE-RSA-AES128-GCM-SHA256",
     standard_name: c"TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
@@ -175,6 +224,11 @@ static TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: SslCipher = SslCipher {
 
 static TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384: SslCipher = SslCipher {
     rustls: &provider::cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    attrs: cipher_list::TLS12
+        | cipher_list::KX_ECDHE
+        | cipher_list::AUTH_RSA
+        | cipher_list::ENC_AES256GCM
+        | cipher_list::FIPS,
     bits: 256,
     openssl_name: c"ECDHE-RSA-AES256-GCM-SHA384",
     standard_name: c"TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
@@ -184,6 +238,10 @@ static TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384: SslCipher = SslCipher {
 
 static TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256: SslCipher = SslCipher {
     rustls: &provider::cipher_suite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+    attrs: cipher_list::TLS12
+        | cipher_list::KX_ECDHE
+        | cipher_list::AUTH_RSA
+        | cipher_list::ENC_CHACHA20POLY1305,
     bits: 256,
     openssl_name: c"ECDHE-RSA-CHACHA20-POLY1305",
     standard_name: c"TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
@@ -193,6 +251,7 @@ static TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256: SslCipher = SslCipher {
 
 static TLS13_AES_128_GCM_SHA256: SslCipher = SslCipher {
     rustls: &provider::cipher_suite::TLS13_AES_128_GCM_SHA256,
+    attrs: 0,
     bits: 128,
     openssl_name: c"TLS_AES_128_GCM_SHA256",
     standard_name: c"TLS_AES_128_GCM_SHA256",
@@ -202,6 +261,7 @@ static TLS13_AES_128_GCM_SHA256: SslCipher = SslCipher {
 
 static TLS13_AES_256_GCM_SHA384: SslCipher = SslCipher {
     rustls: &provider::cipher_suite::TLS13_AES_256_GCM_SHA384,
+    attrs: 0,
     bits: 256,
     openssl_name: c"TLS_AES_256_GCM_SHA384",
     standard_name: c"TLS_AES_256_GCM_SHA384",
@@ -211,6 +271,7 @@ static TLS13_AES_256_GCM_SHA384: SslCipher = SslCipher {
 
 static TLS13_CHACHA20_POLY1305_SHA256: SslCipher = SslCipher {
     rustls: &provider::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+    attrs: 0,
     bits: 256,
     openssl_name: c"TLS_CHACHA20_POLY1305_SHA256",
     standard_name: c"TLS_CHACHA20_POLY1305_SHA256",
@@ -218,7 +279,7 @@ static TLS13_CHACHA20_POLY1305_SHA256: SslCipher = SslCipher {
     description: c"TLS_CHACHA20_POLY1305_SHA256   TLSv1.3 Kx=any      Au=any   Enc=CHACHA20/POLY1305(256) Mac=AEAD\n",
 };
 
-/// Backs a server-side SSL_SESSION object
+/// Backs a SSL_SESSION object
 ///
 /// Note that this has equality and ordering entirely based on the `id` field.
 pub struct SslSession {
@@ -227,6 +288,12 @@ pub struct SslSession {
     context: Vec<u8>,
     creation_time: cache::TimeBase,
     time_out: u64,
+    properties: session::SessionProperties,
+
+    /// Resumption state, for sessions made by a client.
+    ///
+    /// This is not included in the encoding.
+    client: Option<cache::ClientSessionData>,
 }
 
 impl SslSession {
@@ -245,17 +312,212 @@ impl SslSession {
     ) -> Self {
         Self {
             id: SslSessionLookup(id),
+            properties: session::SessionProperties::default(),
             value,
             context,
             creation_time,
             time_out,
+            client: None,
+        }
+    }
+
+    pub fn new_client(
+        id: Vec<u8>,
+        context: Vec<u8>,
+        creation_time: cache::TimeBase,
+        time_out: u64,
+        properties: session::SessionProperties,
+        client: cache::ClientSessionData,
+    ) -> Self {
+        Self {
+            properties,
+            client: Some(client),
+            ..Self::new(id, vec![], context, creation_time, time_out)
+        }
+    }
+
+    /// A copy of this session, for `SSL_SESSION_dup`.
+    pub fn dup(&self) -> Self {
+        Self {
+            id: SslSessionLookup(self.id.0.clone()),
+            value: self.value.clone(),
+            context: self.context.clone(),
+            creation_time: self.creation_time,
+            time_out: self.time_out,
+            properties: self.properties.clone(),
+            client: self.client.as_ref().map(|client| client.dup()),
         }
     }
 
-    /// Encode this session to an opaque binary format.
+    /// Encode this session in OpenSSL's DER format.
     ///
-    /// This could be DER (OpenSSL does) but currently is ad-hoc.
+    /// divergence: the resumption state of client sessions is not encoded.
     pub fn encode(&self) -> Vec<u8> {
+        self.to_asn1().encode()
+    }
+
+    /// Describe this session in OpenSSL's terms.
+    ///
+    /// The value rustls gave us is opaque, and is carried in the ticket field.
+    fn to_asn1(&self) -> session::SessionAsn1 {
+        let mut asn1 = session::SessionAsn1 {
+            session_id: self.id.0.clone(),
+            time: self.creation_time.0,
+            timeout: self.time_out,
+            session_id_context: self.context.clone(),
+            ..session::SessionAsn1::default()
+        };
+
+        if !self.value.is_empty() {
+            asn1.ticket = Some(self.value.clone());
+        }
+
+        self.properties.describe(&mut asn1);
+        asn1
+    }
+
+    /// Write a description of this session to `bio`, in the layout of
+    /// OpenSSL's `SSL_SESSION_print`.
+    pub fn print(&self, bio: *mut bio::BIO) -> bool {
+        let asn1 = self.to_asn1();
+        let tls13 = asn1.ssl_version == u16::from(ProtocolVersion::TLSv1_3);
+
+        let mut head = String::from("SSL-Session:\n");
+        let _ = writeln!(
+            head,
+            "    Protocol  : {}",
+            protocol_version_name(asn1.ssl_version)
+        );
+        let _ = match SslCipher::find_by_id(CipherSuite::from(asn1.cipher)) {
+            Some(cipher) => writeln!(
+                head,
+                "    Cipher    : {}",
+                cipher.openssl_name.to_string_lossy()
+            ),
+            None => writeln!(head, "    Cipher    : {:04X}", asn1.cipher),
+        };
+        let _ = writeln!(head, "    Session-ID: {}", hex_upper(&asn1.session_id));
+        let _ = writeln!(
+            head,
+            "    Session-ID-ctx: {}",
+            hex_upper(&asn1.session_id_context)
+        );
+        let _ = write!(
+            head,
+            "    {}: {}",
+            match tls13 {
+                true => "Resumption PSK",
+                false => "Master-Key",
+            },
+            hex_upper(&asn1.master_key)
+        );
+        // we support neither PSK nor SRP, but OpenSSL's layout includes them
+        head.push_str(
+            "\n    PSK identity: None\n    PSK identity hint: None\n    SRP username: None",
+        );
+        if asn1.ticket_lifetime_hint != 0 {
+            let _ = write!(
+                head,
+                "\n    TLS session ticket lifetime hint: {} (seconds)",
+                asn1.ticket_lifetime_hint
+            );
+        }
+
+        let mut tail = String::new();
+        if asn1.time != 0 {
+            let _ = write!(tail, "\n    Start Time: {}", asn1.time);
+        }
+        if asn1.timeout != 0 {
+            let _ = write!(tail, "\n    Timeout   : {} (sec)", asn1.timeout);
+        }
+        let verify_error = unsafe {
+            CStr::from_ptr(openssl_sys::X509_verify_cert_error_string(
+                asn1.verify_result as _,
+            ))
+        };
+        let _ = writeln!(
+            tail,
+            "\n    Verify return code: {} ({})",
+            asn1.verify_result,
+            verify_error.to_string_lossy()
+        );
+        let _ = writeln!(
+            tail,
+            "    Extended master secret: {}",
+            match asn1.flags & session::SSL_SESS_FLAG_EXTMS {
+                0 => "no",
+                _ => "yes",
+            }
+        );
+        if tls13 {
+            let _ = writeln!(tail, "    Max Early Data: {}", asn1.max_early_data);
+        }
+
+        bio::write_all(bio, head.as_bytes())
+            && match &asn1.ticket {
+                Some(ticket) => {
+                    bio::write_all(bio, b"\n    TLS session ticket:\n")
+                        && bio::dump_indent(bio, ticket, 4)
+                }
+                None => true,
+            }
+            && bio::write_all(bio, tail.as_bytes())
+    }
+
+    /// Write this session's id and master key to `bio`, in the NSS key log
+    /// format used by `SSL_SESSION_print_keylog`.
+    ///
+    /// Fails if either is empty.
+    pub fn print_keylog(&self, bio: *mut bio::BIO) -> bool {
+        let master_key = &self.properties.master_key;
+        if self.id.0.is_empty() || master_key.is_empty() {
+            return false;
+        }
+
+        // the "RSA" prefix is required by the format, regardless of the key exchange.
+        let line = format!(
+            "RSA Session-ID:{} Master-Key:{}\n",
+            hex_upper(&self.id.0),
+            hex_upper(master_key)
+        );
+        bio::write_all(bio, line.as_bytes())
+    }
+
+    /// Decodes from the front of `slice`.  Returns the remainder.
+    ///
+    /// This accepts OpenSSL's DER format, or our previous ad-hoc format.
+    ///
+    /// divergence: sessions made by OpenSSL can be read and inspected, but
+    /// rustls cannot resume them.
+    pub fn decode(slice: &[u8]) -> Option<(Self, &[u8])> {
+        if slice.starts_with(SslSession::MAGIC) {
+            return Self::decode_legacy(slice);
+        }
+
+        let (asn1, rest) = session::SessionAsn1::decode(slice)?;
+        let value = match asn1.master_key.is_empty() {
+            true => asn1.ticket.clone().unwrap_or_default(),
+            false => vec![],
+        };
+        let properties = session::SessionProperties::from_asn1(&asn1);
+        Some((
+            Self {
+                properties,
+                ..Self::new(
+                    asn1.session_id,
+                    value,
+                    asn1.session_id_context,
+                    cache::TimeBase(asn1.time),
+                    asn1.timeout,
+                )
+            },
+            rest,
+        ))
+    }
+
+    /// Encode this session in our previous ad-hoc format.
+    #[cfg(test)]
+    fn encode_legacy(&self) -> Vec<u8> {
         let id_len = self.id.0.len().to_le_bytes();
         let value_len = self.value.len().to_le_bytes();
         let context_len = self.context.len().to_le_bytes();
@@ -285,8 +547,8 @@ impl SslSession {
         ret
     }
 
-    /// Decodes from the front of `slice`.  Returns the remainder.
-    pub fn decode(slice: &[u8]) -> Option<(Self, &[u8])> {
+    /// Decodes our previous ad-hoc format from the front of `slice`.
+    fn decode_legacy(slice: &[u8]) -> Option<(Self, &[u8])> {
         fn split_at(slice: &[u8], mid: usize) -> Option<(&[u8], &[u8])> {
             if mid <= slice.len() {
                 Some(slice.split_at(mid))
@@ -321,13 +583,13 @@ impl SslSession {
         let (creation_time, slice) = split_at(slice, u64_len)?;
         let (time_out, slice) = split_at(slice, u64_len)?;
         Some((
-            Self {
-                id: SslSessionLookup(id.to_vec()),
-                value: value.to_vec(),
-                context: context.to_vec(),
-                creation_time: cache::TimeBase(slice_to_u64(creation_time)),
-                time_out: slice_to_u64(time_out),
-            },
+            Self::new(
+                id.to_vec(),
+                value.to_vec(),
+                context.to_vec(),
+                cache::TimeBase(slice_to_u64(creation_time)),
+                slice_to_u64(time_out),
+            ),
             slice,
         ))
     }
@@ -336,6 +598,14 @@ impl SslSession {
         &self.id.0
     }
 
+    pub fn set_id(&mut self, id: &[u8]) {
+        self.id = SslSessionLookup::for_id(id);
+    }
+
+    pub fn get_context(&self) -> &[u8] {
+        &self.context
+    }
+
     pub fn get_creation_time(&self) -> u64 {
         self.creation_time.0
     }
@@ -359,11 +629,61 @@ impl SslSession {
     pub fn expired(&self, at_time: cache::TimeBase) -> bool {
         cache::ExpiryTime::calculate(self.creation_time, self.time_out).in_past(at_time)
     }
+
+    pub fn client_origin(&self) -> Option<&cache::ClientSessionOrigin> {
+        self.client.as_ref().map(|client| client.origin())
+    }
+
+    pub fn properties(&self) -> &session::SessionProperties {
+        &self.properties
+    }
+
+    pub fn properties_mut(&mut self) -> &mut session::SessionProperties {
+        &mut self.properties
+    }
+
+    pub fn get_cipher(&self) -> Option<&'static SslCipher> {
+        SslCipher::find_by_id(CipherSuite::from(self.properties.cipher))
+    }
+
+    pub fn set_cipher(&mut self, cipher: Option<&SslCipher>) {
+        self.properties.cipher = cipher.map(|c| c.protocol_id()).unwrap_or_default();
+    }
+
+    /// Whether this session can be offered for resumption.
+    pub fn is_resumable(&self) -> bool {
+        match &self.client {
+            Some(client) => client.is_resumable(),
+            None => !self.value.is_empty(),
+        }
+    }
+
+    /// Record the properties of `conn` that rustls does not expose in a
+    /// session value.
+    fn complete_from(&mut self, conn: &Connection, verify_result: i64) {
+        if let Some(version) = conn.protocol_version() {
+            self.properties.version = u16::from(version);
+        }
+        if let Connection::Server(server) = conn {
+            self.properties.hostname = server
+                .server_name()
+                .and_then(|name| CString::new(name).ok());
+        }
+        if let Some(suite) = conn.negotiated_cipher_suite() {
+            self.properties.cipher = u16::from(suite.suite());
+        }
+        self.properties.alpn_selected = conn.alpn_protocol().map(|alpn| alpn.to_vec());
+        self.properties.peer = conn
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|cert| x509::OwnedX509::parse_der(cert.as_ref()));
+        self.properties.verify_result = verify_result;
+    }
 }
 
 impl PartialOrd<SslSession> for SslSession {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        Some(self.id.cmp(&other.id))
+        Some(self.cmp(other))
     }
 }
 
@@ -414,7 +734,10 @@ pub struct SslContext {
     ticketer: Option<Arc<dyn ProducesTickets>>,
     num_tickets: usize,
     raw_options: u64,
+    raw_mode: u32,
     verify_mode: VerifyMode,
+    verify_callback: entry::SSL_verify_cb,
+    cert_verify_callback: callbacks::CertVerifyCallbackConfig,
     verify_depth: c_int,
     verify_roots: RootCertStore,
     verify_x509_store: x509::OwnedX509Store,
@@ -423,9 +746,21 @@ pub struct SslContext {
     default_cert_dir: Option<PathBuf>,
     alpn_callback: callbacks::AlpnCallbackConfig,
     cert_callback: callbacks::CertCallbackConfig,
+    client_hello_callback: callbacks::ClientHelloCallbackConfig,
     servername_callback: callbacks::ServerNameCallbackConfig,
+    allow_early_data_callback: callbacks::AllowEarlyDataCallbackConfig,
     auth_keys: sign::CertifiedKeySet,
     max_early_data: u32,
+    recv_max_early_data: u32,
+    tls12_ciphers: Vec<&'static SslCipher>,
+    tls13_ciphers: Vec<&'static SslCipher>,
+    cipher_stack: cipher_list::OwnedCipherStack,
+    kx_groups: Option<Vec<&'static dyn SupportedKxGroup>>,
+    sigalgs: Option<Vec<SignatureScheme>>,
+    client_sigalgs: Option<Vec<SignatureScheme>>,
+    keylog_callback: entry::SSL_CTX_keylog_cb_func,
+    info_callback: entry::SSL_info_callback_func,
+    msg_callback: callbacks::MsgCallbackConfig,
 }
 
 impl SslContext {
@@ -445,7 +780,10 @@ impl SslContext {
             ticketer,
             num_tickets: 2, // match OpenSSL default: see `man SSL_CTX_set_num_tickets`
             raw_options: 0,
+            raw_mode: SSL_MODE_AUTO_RETRY,
             verify_mode: VerifyMode::default(),
+            verify_callback: None,
+            cert_verify_callback: callbacks::CertVerifyCallbackConfig::default(),
             verify_depth: -1,
             verify_roots: RootCertStore::empty(),
             verify_x509_store: OwnedX509Store::default(),
@@ -454,9 +792,21 @@ impl SslContext {
             default_cert_dir: None,
             alpn_callback: callbacks::AlpnCallbackConfig::default(),
             cert_callback: callbacks::CertCallbackConfig::default(),
+            client_hello_callback: callbacks::ClientHelloCallbackConfig::default(),
             servername_callback: callbacks::ServerNameCallbackConfig::default(),
+            allow_early_data_callback: callbacks::AllowEarlyDataCallbackConfig::default(),
             auth_keys: sign::CertifiedKeySet::default(),
             max_early_data: 0,
+            recv_max_early_data: SSL3_RT_MAX_PLAIN_LENGTH,
+            tls12_ciphers: TLS12_CIPHERS.to_vec(),
+            tls13_ciphers: TLS13_CIPHERS.to_vec(),
+            cipher_stack: cipher_list::OwnedCipherStack::new(TLS13_CIPHERS, TLS12_CIPHERS),
+            kx_groups: None,
+            sigalgs: None,
+            client_sigalgs: None,
+            keylog_callback: None,
+            info_callback: None,
+            msg_callback: callbacks::MsgCallbackConfig::default(),
         }
     }
 
@@ -502,6 +852,16 @@ impl SslContext {
         self.raw_options
     }
 
+    fn set_mode(&mut self, set: u32) -> u32 {
+        self.raw_mode |= set;
+        self.raw_mode
+    }
+
+    fn clear_mode(&mut self, clear: u32) -> u32 {
+        self.raw_mode &= !clear;
+        self.raw_mode
+    }
+
     fn set_min_protocol_version(&mut self, ver: u16) {
         self.versions.min = match ver {
             0 => None,
@@ -580,26 +940,83 @@ impl SslContext {
         self.max_early_data
     }
 
+    fn set_recv_max_early_data(&mut self, max: u32) {
+        self.recv_max_early_data = max;
+    }
+
+    fn get_recv_max_early_data(&self) -> u32 {
+        self.recv_max_early_data
+    }
+
+    fn set_allow_early_data_cb(
+        &mut self,
+        cb: entry::SSL_allow_early_data_cb_fn,
+        context: *mut c_void,
+    ) {
+        self.allow_early_data_callback = callbacks::AllowEarlyDataCallbackConfig { cb, context };
+    }
+
     fn set_verify(&mut self, mode: VerifyMode) {
         self.verify_mode = mode;
     }
 
+    fn set_verify_callback(&mut self, callback: entry::SSL_verify_cb) {
+        self.verify_callback = callback;
+    }
+
+    fn set_cipher_list(&mut self, rule_str: &str) -> Result<(), error::Error> {
+        self.tls12_ciphers = cipher_list::parse(rule_str, TLS12_CIPHERS)?;
+        self.cipher_stack =
+            cipher_list::OwnedCipherStack::new(&self.tls13_ciphers, &self.tls12_ciphers);
+        Ok(())
+    }
+
+    fn set_ciphersuites(&mut self, names: &str) -> Result<(), error::Error> {
+        self.tls13_ciphers = cipher_list::parse_ciphersuites(names, TLS13_CIPHERS)?;
+        self.cipher_stack =
+            cipher_list::OwnedCipherStack::new(&self.tls13_ciphers, &self.tls12_ciphers);
+        Ok(())
+    }
+
+    fn get_ciphers(&self) -> *mut stack_st_SSL_CIPHER {
+        self.cipher_stack.pointer()
+    }
+
+    fn set_groups(&mut self, groups: &[NamedGroup]) -> Result<(), error::Error> {
+        self.kx_groups = Some(select_kx_groups(groups)?);
+        Ok(())
+    }
+
+    fn set_sigalgs(&mut self, sigalgs: Vec<SignatureScheme>) {
+        self.sigalgs = Some(sigalgs);
+    }
+
+    fn set_client_sigalgs(&mut self, sigalgs: Vec<SignatureScheme>) {
+        self.client_sigalgs = Some(sigalgs);
+    }
+
     fn set_default_verify_paths(&mut self) {
         let ProbeResult {
             cert_file,
             cert_dir,
         } = openssl_probe::probe();
+        self.verify_x509_store
+            .load_locations(cert_file.as_deref(), cert_dir.as_deref());
         self.default_cert_file = cert_file;
         self.default_cert_dir = cert_dir;
     }
 
     fn set_default_verify_dir(&mut self) {
         let ProbeResult { cert_dir, .. } = openssl_probe::probe();
+        self.verify_x509_store
+            .load_locations(None, cert_dir.as_deref());
         self.default_cert_dir = cert_dir;
     }
 
     fn set_default_verify_file(&mut self) {
         let ProbeResult { cert_file, .. } = openssl_probe::probe();
+        self.verify_x509_store
+            .load_locations(cert_file.as_deref(), None);
         self.default_cert_file = cert_file;
     }
 
@@ -608,8 +1025,7 @@ impl SslContext {
     }
 
     fn get_verify_callback(&self) -> entry::SSL_verify_cb {
-        // TODO: `SSL_CTX_set_verify` currently rejects non-NULL callback
-        None
+        self.verify_callback
     }
 
     fn set_verify_depth(&mut self, depth: c_int) {
@@ -625,6 +1041,8 @@ impl SslContext {
         certs: Vec<CertificateDer<'static>>,
     ) -> Result<(), error::Error> {
         for c in certs {
+            // the store is for callbacks that call `X509_verify_cert`.
+            self.verify_x509_store.add_cert(&c)?;
             self.verify_roots
                 .add(c)
                 .map_err(error::Error::from_rustls)?;
@@ -655,6 +1073,18 @@ impl SslContext {
         self.cert_callback = callbacks::CertCallbackConfig { cb, context };
     }
 
+    fn set_client_hello_cb(&mut self, cb: entry::SSL_client_hello_cb_fn, context: *mut c_void) {
+        self.client_hello_callback = callbacks::ClientHelloCallbackConfig { cb, context };
+    }
+
+    fn set_cert_verify_callback(
+        &mut self,
+        cb: entry::SSL_CTX_cert_verify_cb_func,
+        context: *mut c_void,
+    ) {
+        self.cert_verify_callback = callbacks::CertVerifyCallbackConfig { cb, context };
+    }
+
     fn stage_certificate_end_entity(&mut self, end: CertificateDer<'static>) {
         self.auth_keys.stage_certificate_end_entity(end)
     }
@@ -667,6 +1097,25 @@ impl SslContext {
         self.auth_keys.commit_private_key(key)
     }
 
+    fn use_cert_and_key(
+        &mut self,
+        end_entity: CertificateDer<'static>,
+        chain: Vec<CertificateDer<'static>>,
+        key: Option<evp_pkey::EvpPkey>,
+        replace: bool,
+    ) -> Result<(), error::Error> {
+        self.auth_keys
+            .use_cert_and_key(end_entity, chain, key, replace)
+    }
+
+    fn set_current_cert(&mut self, next: bool) -> bool {
+        self.auth_keys.set_current(next)
+    }
+
+    fn select_current_cert(&mut self, cert: *mut X509) -> bool {
+        self.auth_keys.select_current(cert)
+    }
+
     fn get_certificate(&self) -> *mut X509 {
         self.auth_keys.borrow_current_cert()
     }
@@ -682,6 +1131,30 @@ impl SslContext {
     fn set_servername_callback_context(&mut self, context: *mut c_void) {
         self.servername_callback.context = context;
     }
+
+    fn set_keylog_callback(&mut self, cb: entry::SSL_CTX_keylog_cb_func) {
+        self.keylog_callback = cb;
+    }
+
+    fn get_keylog_callback(&self) -> entry::SSL_CTX_keylog_cb_func {
+        self.keylog_callback
+    }
+
+    fn set_info_callback(&mut self, cb: entry::SSL_info_callback_func) {
+        self.info_callback = cb;
+    }
+
+    fn get_info_callback(&self) -> entry::SSL_info_callback_func {
+        self.info_callback
+    }
+
+    fn set_msg_callback(&mut self, cb: entry::SSL_CTX_msg_cb_func) {
+        self.msg_callback.cb = cb;
+    }
+
+    fn set_msg_callback_context(&mut self, context: *mut c_void) {
+        self.msg_callback.context = context;
+    }
 }
 
 /// Parse the ALPN wire format (which is used in the openssl API)
@@ -731,31 +1204,64 @@ struct Ssl {
     ex_data: ex_data::ExData,
     versions: EnabledVersions,
     raw_options: u64,
+    raw_mode: u32,
     num_tickets: usize,
     mode: ConnMode,
     verify_mode: VerifyMode,
+    verify_callback: entry::SSL_verify_cb,
+    cert_verify_callback: callbacks::CertVerifyCallbackConfig,
     verify_depth: c_int,
     verify_roots: RootCertStore,
+    verify_x509_store: x509::OwnedX509Store,
     verify_server_name: Option<ServerName<'static>>,
     alpn: Vec<Vec<u8>>,
     alpn_callback: callbacks::AlpnCallbackConfig,
     cert_callback: callbacks::CertCallbackConfig,
+    client_hello_callback: callbacks::ClientHelloCallbackConfig,
     servername_callback: callbacks::ServerNameCallbackConfig,
+    allow_early_data_callback: callbacks::AllowEarlyDataCallbackConfig,
     sni_server_name: Option<ServerName<'static>>,
     server_name: Option<CString>,
+    /// The raw `ClientHello`, for `SSL_client_hello_get0_*`.
+    client_hello: Option<client_hello::ClientHello>,
+    /// Whether the client hello callback asked to be called again.
+    client_hello_retry: bool,
+    /// From `SSL_set_session`; offered for resumption when connecting.
+    client_session: Option<Arc<NotThreadSafe<SslSession>>>,
     bio: Option<bio::Bio>,
     conn: ConnState,
+    /// Plaintext read by `SSL_peek`, which is returned before any more is read.
+    peeked: Vec<u8>,
+    /// A write that did not complete, and must be retried.
+    write_retry: Option<WriteRetry>,
     peer_cert: Option<x509::OwnedX509>,
     peer_cert_chain: Option<x509::OwnedX509Stack>,
     shutdown_flags: ShutdownFlags,
     auth_keys: sign::CertifiedKeySet,
     max_early_data: u32,
+    recv_max_early_data: u32,
+    early_data_state: EarlyDataState,
+    /// Why the last `SSL_set_SSL_CTX` could not be applied; this fails the handshake.
+    ctx_error: Option<error::Error>,
+    /// From `SSL_key_update`; sent by the next `write` or `try_io`.
+    pending_key_update: Option<c_int>,
+    tls12_ciphers: Vec<&'static SslCipher>,
+    tls13_ciphers: Vec<&'static SslCipher>,
+    cipher_stack: cipher_list::OwnedCipherStack,
+    kx_groups: Option<Vec<&'static dyn SupportedKxGroup>>,
+    sigalgs: Option<Vec<SignatureScheme>>,
+    client_sigalgs: Option<Vec<SignatureScheme>>,
+    peer_groups: Vec<NamedGroup>,
+    sig_schemes: Arc<sign::SigSchemeLog>,
+    info_callback: entry::SSL_info_callback_func,
+    fatal_alert_reported: bool,
+    msg_callback: callbacks::MsgCallbackConfig,
 }
 
 #[allow(clippy::large_enum_variant)]
 enum ConnState {
     Nothing,
-    Client(Connection, Arc<verifier::ServerVerifier>),
+    Client(Connection, cache::ClientLogs, Arc<cache::SingleClientCache>),
     Accepting(Acceptor),
     Accepted(Accepted),
     Server(
@@ -772,25 +1278,54 @@ impl Ssl {
             ex_data: ex_data::ExData::default(),
             versions: inner.versions.clone(),
             raw_options: inner.raw_options,
+            raw_mode: inner.raw_mode,
             num_tickets: inner.num_tickets,
             mode: inner.method.mode(),
             verify_mode: inner.verify_mode,
+            verify_callback: inner.verify_callback,
+            cert_verify_callback: inner.cert_verify_callback.clone(),
             verify_depth: inner.verify_depth,
             verify_roots: Self::load_verify_certs(inner)?,
+            verify_x509_store: inner.verify_x509_store.clone(),
             verify_server_name: None,
             alpn: inner.alpn.clone(),
             alpn_callback: inner.alpn_callback.clone(),
             cert_callback: inner.cert_callback.clone(),
+            client_hello_callback: inner.client_hello_callback.clone(),
             servername_callback: inner.servername_callback.clone(),
+            allow_early_data_callback: inner.allow_early_data_callback.clone(),
             sni_server_name: None,
             server_name: None,
+            client_hello: None,
+            client_hello_retry: false,
+            client_session: None,
             bio: None,
             conn: ConnState::Nothing,
+            peeked: Vec::new(),
+            write_retry: None,
             peer_cert: None,
             peer_cert_chain: None,
             shutdown_flags: ShutdownFlags::default(),
             auth_keys: inner.auth_keys.clone(),
             max_early_data: inner.max_early_data,
+            recv_max_early_data: inner.recv_max_early_data,
+            early_data_state: EarlyDataState::None,
+            ctx_error: None,
+            pending_key_update: None,
+            tls12_ciphers: inner.tls12_ciphers.clone(),
+            tls13_ciphers: inner.tls13_ciphers.clone(),
+            cipher_stack: cipher_list::OwnedCipherStack::new(
+                &inner.tls13_ciphers,
+                &inner.tls12_ciphers,
+            ),
+            kx_groups: inner.kx_groups.clone(),
+            sigalgs: inner.sigalgs.clone(),
+            client_sigalgs: inner.client_sigalgs.clone(),
+            peer_groups: vec![],
+            sig_schemes: Arc::default(),
+            info_callback: None,
+            fatal_alert_reported: false,
+            msg_callback: inner.msg_callback.clone(),
         })
     }
 
@@ -807,11 +1342,44 @@ impl Ssl {
     }
 
     fn set_ctx(&mut self, ctx: Arc<NotThreadSafe<SslContext>>) {
-        // there are no docs for `SSL_set_SSL_CTX`.  it seems the only
-        // meaningful reason to use this is key/certificate switching
-        // (eg, based on SNI).  So only bother updating `auth_keys`
+        // there are no docs for `SSL_set_SSL_CTX`.  it is used to switch virtual
+        // host (eg, based on SNI) so we take on the new context's keys, and how it
+        // authenticates and negotiates with clients, issues tickets and accepts
+        // early data.
+        //
+        // the session cache, session id context and ticketer are always read from
+        // `self.ctx`, so they follow the switch without being copied here.
+        //
+        // these stay as they were: the protocol versions, cipher suites, groups
+        // and mode; and the client hello, servername, info and message callbacks
+        // (the first two have already run, as the switch is made from them).
+        if Arc::ptr_eq(&self.ctx, &ctx) {
+            return;
+        }
+
+        let inner = ctx.get();
+        // failing here would leave a half-switched `SSL`, so fail the handshake instead.
+        (self.verify_roots, self.ctx_error) = match Self::load_verify_certs(inner) {
+            Ok(roots) => (roots, None),
+            Err(e) => (RootCertStore::empty(), Some(e)),
+        };
+        self.verify_x509_store = inner.verify_x509_store.clone();
+        self.raw_options = inner.raw_options;
+        self.verify_mode = inner.verify_mode;
+        self.verify_callback = inner.verify_callback;
+        self.cert_verify_callback = inner.cert_verify_callback.clone();
+        self.verify_depth = inner.verify_depth;
+        self.alpn.clone_from(&inner.alpn);
+        self.alpn_callback = inner.alpn_callback.clone();
+        self.cert_callback = inner.cert_callback.clone();
+        self.auth_keys = inner.auth_keys.clone();
+        self.sigalgs.clone_from(&inner.sigalgs);
+        self.client_sigalgs.clone_from(&inner.client_sigalgs);
+        self.num_tickets = inner.num_tickets;
+        self.max_early_data = inner.max_early_data;
+        self.recv_max_early_data = inner.recv_max_early_data;
+        self.allow_early_data_callback = inner.allow_early_data_callback.clone();
         self.ctx = ctx.clone();
-        self.auth_keys = ctx.get().auth_keys.clone();
     }
 
     fn get_options(&self) -> u64 {
@@ -836,6 +1404,16 @@ impl Ssl {
         self.raw_options
     }
 
+    fn set_mode(&mut self, set: u32) -> u32 {
+        self.raw_mode |= set;
+        self.raw_mode
+    }
+
+    fn clear_mode(&mut self, clear: u32) -> u32 {
+        self.raw_mode &= !clear;
+        self.raw_mode
+    }
+
     fn set_min_protocol_version(&mut self, ver: u16) {
         self.versions.min = match ver {
             0 => None,
@@ -885,6 +1463,30 @@ impl Ssl {
         self.mode == ConnMode::Server
     }
 
+    fn set_max_early_data(&mut self, max: u32) {
+        self.max_early_data = max;
+    }
+
+    fn get_max_early_data(&self) -> u32 {
+        self.max_early_data
+    }
+
+    fn set_recv_max_early_data(&mut self, max: u32) {
+        self.recv_max_early_data = max;
+    }
+
+    fn get_recv_max_early_data(&self) -> u32 {
+        self.recv_max_early_data
+    }
+
+    fn set_allow_early_data_cb(
+        &mut self,
+        cb: entry::SSL_allow_early_data_cb_fn,
+        context: *mut c_void,
+    ) {
+        self.allow_early_data_callback = callbacks::AllowEarlyDataCallbackConfig { cb, context };
+    }
+
     fn stage_certificate_end_entity(&mut self, end: CertificateDer<'static>) {
         self.auth_keys.stage_certificate_end_entity(end)
     }
@@ -897,6 +1499,17 @@ impl Ssl {
         self.auth_keys.commit_private_key(key)
     }
 
+    fn use_cert_and_key(
+        &mut self,
+        end_entity: CertificateDer<'static>,
+        chain: Vec<CertificateDer<'static>>,
+        key: Option<evp_pkey::EvpPkey>,
+        replace: bool,
+    ) -> Result<(), error::Error> {
+        self.auth_keys
+            .use_cert_and_key(end_entity, chain, key, replace)
+    }
+
     fn set_verify_hostname(&mut self, hostname: Option<&str>) -> bool {
         match hostname {
             // If name is NULL or the empty string, the list of hostnames is
@@ -919,10 +1532,84 @@ impl Ssl {
         self.verify_mode = mode;
     }
 
+    fn set_verify_callback(&mut self, callback: entry::SSL_verify_cb) {
+        self.verify_callback = callback;
+    }
+
+    fn set_info_callback(&mut self, cb: entry::SSL_info_callback_func) {
+        self.info_callback = cb;
+    }
+
+    /// Our own info callback; unlike the one in effect, this does not
+    /// fall back to the `SSL_CTX`'s.
+    fn get_info_callback(&self) -> entry::SSL_info_callback_func {
+        self.info_callback
+    }
+
+    fn set_msg_callback(&mut self, cb: entry::SSL_CTX_msg_cb_func) {
+        self.msg_callback.cb = cb;
+    }
+
+    fn set_msg_callback_context(&mut self, context: *mut c_void) {
+        self.msg_callback.context = context;
+    }
+
+    /// The protocol version reported with messages to the msg callback.
+    ///
+    /// Before negotiation, this is the highest version we could use.
+    fn msg_callback_version(&self) -> c_int {
+        self.conn()
+            .and_then(|conn| conn.protocol_version())
+            .or(self.versions.max)
+            .map(|version| u16::from(version) as c_int)
+            .unwrap_or(TLS1_3_VERSION)
+    }
+
+    fn invoke_info_callback(&self, where_: c_int, ret: c_int) {
+        let callback = self.info_callback.or(self.ctx.get().info_callback);
+        callbacks::invoke_info_callback(callback, where_, ret);
+    }
+
+    /// The `SSL_ST_*` flag included in handshake loop/exit info callbacks.
+    fn info_callback_role(&self) -> c_int {
+        match self.mode {
+            ConnMode::Server => SSL_ST_ACCEPT,
+            _ => SSL_ST_CONNECT,
+        }
+    }
+
+    fn report_handshake_start(&self) {
+        self.invoke_info_callback(SSL_CB_HANDSHAKE_START, 1);
+        self.invoke_info_callback(self.info_callback_role() | SSL_CB_LOOP, 1);
+    }
+
+    /// Report the alert sent or received for the connection-fatal `err`.
+    ///
+    /// This only happens once, as rustls repeats the same error thereafter.
+    fn report_fatal_alert(&mut self, err: &rustls::Error) {
+        if mem::replace(&mut self.fatal_alert_reported, true) {
+            return;
+        }
+
+        let (where_, desc) = match err {
+            rustls::Error::AlertReceived(desc) => (SSL_CB_READ_ALERT, *desc),
+            err => match sent_alert_for_error(err) {
+                Some(desc) => (SSL_CB_WRITE_ALERT, desc),
+                None => return,
+            },
+        };
+
+        self.invoke_info_callback(where_, alert_to_info_value(SSL3_AL_FATAL, desc));
+    }
+
     fn get_verify_mode(&self) -> VerifyMode {
         self.verify_mode
     }
 
+    fn get_verify_callback(&self) -> entry::SSL_verify_cb {
+        self.verify_callback
+    }
+
     fn set_verify_depth(&mut self, depth: c_int) {
         self.verify_depth = depth;
     }
@@ -931,6 +1618,37 @@ impl Ssl {
         self.verify_depth
     }
 
+    fn set_cipher_list(&mut self, rule_str: &str) -> Result<(), error::Error> {
+        self.tls12_ciphers = cipher_list::parse(rule_str, TLS12_CIPHERS)?;
+        self.cipher_stack =
+            cipher_list::OwnedCipherStack::new(&self.tls13_ciphers, &self.tls12_ciphers);
+        Ok(())
+    }
+
+    fn set_ciphersuites(&mut self, names: &str) -> Result<(), error::Error> {
+        self.tls13_ciphers = cipher_list::parse_ciphersuites(names, TLS13_CIPHERS)?;
+        self.cipher_stack =
+            cipher_list::OwnedCipherStack::new(&self.tls13_ciphers, &self.tls12_ciphers);
+        Ok(())
+    }
+
+    fn get_ciphers(&self) -> *mut stack_st_SSL_CIPHER {
+        self.cipher_stack.pointer()
+    }
+
+    fn set_groups(&mut self, groups: &[NamedGroup]) -> Result<(), error::Error> {
+        self.kx_groups = Some(select_kx_groups(groups)?);
+        Ok(())
+    }
+
+    fn set_sigalgs(&mut self, sigalgs: Vec<SignatureScheme>) {
+        self.sigalgs = Some(sigalgs);
+    }
+
+    fn set_client_sigalgs(&mut self, sigalgs: Vec<SignatureScheme>) {
+        self.client_sigalgs = Some(sigalgs);
+    }
+
     fn set_sni_hostname(&mut self, hostname: &str) -> bool {
         match ServerName::try_from(hostname).ok() {
             Some(server_name) => {
@@ -941,6 +1659,14 @@ impl Ssl {
         }
     }
 
+    fn client_hello(&self) -> Option<&client_hello::ClientHello> {
+        self.client_hello.as_ref()
+    }
+
+    fn client_hello_retry(&self) -> bool {
+        self.client_hello_retry
+    }
+
     fn server_name_pointer(&mut self) -> *const c_char {
         // This does double duty (see `SSL_get_servername`):
         //
@@ -1009,49 +1735,119 @@ impl Ssl {
 
         if matches!(self.conn, ConnState::Nothing) {
             self.init_client_conn()?;
+            self.report_handshake_start();
         }
 
-        self.try_io()
+        self.try_handshake_io()
+    }
+
+    fn verify_callbacks(&self) -> verifier::VerifyCallbacks {
+        verifier::VerifyCallbacks {
+            verify_callback: self.verify_callback,
+            cert_verify_callback: self.cert_verify_callback.clone(),
+            x509_store: self.verify_x509_store.clone(),
+            verify_depth: self.verify_depth,
+        }
     }
 
     fn init_client_conn(&mut self) -> Result<(), error::Error> {
+        if let Some(e) = &self.ctx_error {
+            return Err(e.clone());
+        }
+
         // if absent, use a dummy IP address which disables SNI.
         let sni_server_name = match &self.sni_server_name {
             Some(sni_name) => sni_name.clone(),
             None => ServerName::try_from("0.0.0.0").unwrap(),
         };
 
-        let provider = Arc::new(provider::default_provider());
+        let provider = Arc::new(self.crypto_provider());
         let verifier = Arc::new(verifier::ServerVerifier::new(
             self.verify_roots.clone().into(),
             provider.clone(),
             self.verify_mode,
+            self.verify_callbacks(),
+            self.sigalgs.clone(),
             &self.verify_server_name,
         ));
 
+        // rustls only resumes a session with the verifier and client cert resolver
+        // that made it, so adopt those from any `SSL_set_session` session, or
+        // else from a TLS1.3 session for this server stored in the `SSL_CTX`.
+        // What they record still goes to this connection's own logs.
+        let client_chain = self.auth_keys.current_chain();
+        let offered = self
+            .client_session
+            .as_ref()
+            .and_then(|sess| {
+                let sess = sess.get();
+                Some((sess.client_origin()?.clone(), sess.properties.verify_result))
+            })
+            .filter(|(origin, _)| origin.compatible_with(&verifier, client_chain))
+            .or_else(|| {
+                self.ctx
+                    .get()
+                    .caches
+                    .find_client_origin(&sni_server_name, &verifier, client_chain)
+            });
+
+        let (origin, logs) = match offered {
+            Some((origin, verify_result)) => {
+                // like OpenSSL, a resumed connection reports the session's verify result.
+                let logs = cache::ClientLogs {
+                    verify: Arc::new(verifier::VerifyLog::new(verify_result as c_int)),
+                    sig_schemes: Arc::default(),
+                };
+                origin.record_into(&logs);
+                (origin, logs)
+            }
+            _ => {
+                // Like OpenSSL, `client_sigalgs` restrict how we sign as a client, and
+                // otherwise `sigalgs` apply in both directions.
+                let client_sigalgs = self.client_sigalgs.as_deref().or(self.sigalgs.as_deref());
+                let logs = cache::ClientLogs {
+                    verify: verifier.log().clone(),
+                    sig_schemes: Arc::default(),
+                };
+                let origin = cache::ClientSessionOrigin::new(
+                    verifier,
+                    self.auth_keys
+                        .client_resolver(client_sigalgs, Arc::clone(&logs.sig_schemes)),
+                    logs.clone(),
+                    self.auth_keys.current_chain().map(|chain| chain.to_vec()),
+                );
+                (origin, logs)
+            }
+        };
+
         let versions = self
             .versions
             .reduce_versions(self.ctx.get().method.client_versions)?;
 
+        let random = provider.secure_random;
         let wants_resolver = ClientConfig::builder_with_provider(provider)
             .with_protocol_versions(&versions)
             .map_err(error::Error::from_rustls)?
             .dangerous()
-            .with_custom_certificate_verifier(verifier.clone());
+            .with_custom_certificate_verifier(origin.verifier.clone());
 
-        let mut config = if let Some(resolver) = self.auth_keys.client_resolver() {
-            wants_resolver.with_client_cert_resolver(resolver)
-        } else {
-            wants_resolver.with_no_client_auth()
-        };
+        self.sig_schemes = logs.sig_schemes.clone();
+        let mut config = wants_resolver.with_client_cert_resolver(origin.resolver.clone());
 
         config.alpn_protocols.clone_from(&self.alpn);
-        config.resumption = Resumption::store(self.ctx.get_mut().caches.get_client());
+        config.key_log = callbacks::key_log(self.ctx.get().keylog_callback);
+        config.enable_early_data = self.early_data_state == EarlyDataState::Writing;
+        let cache =
+            self.ctx
+                .get_mut()
+                .caches
+                .get_client(origin, self.client_session.clone(), random);
+        config.resumption = Resumption::store(cache.clone());
 
         let client_conn = ClientConnection::new(Arc::new(config), sni_server_name.clone())
             .map_err(error::Error::from_rustls)?;
 
-        self.conn = ConnState::Client(client_conn.into(), verifier);
+        self.conn = ConnState::Client(client_conn.into(), logs, cache);
         Ok(())
     }
 
@@ -1062,9 +1858,14 @@ impl Ssl {
 
         if matches!(self.conn, ConnState::Nothing) {
             self.conn = ConnState::Accepting(Acceptor::default());
+            self.report_handshake_start();
         }
 
-        self.try_io()
+        if self.client_hello_retry {
+            return self.invoke_accepted_callbacks();
+        }
+
+        self.try_handshake_io()
     }
 
     fn invoke_accepted_callbacks(&mut self) -> Result<(), error::Error> {
@@ -1079,6 +1880,29 @@ impl Ssl {
             .server_name()
             .and_then(|sni| CString::new(sni.as_bytes()).ok());
 
+        self.peer_groups = accepted
+            .client_hello()
+            .named_groups()
+            .map(|groups| groups.to_vec())
+            .unwrap_or_default();
+
+        self.sig_schemes = Arc::new(sign::SigSchemeLog::with_offered(
+            accepted.client_hello().signature_schemes().to_vec(),
+        ));
+
+        self.client_hello = self
+            .bio
+            .as_ref()
+            .and_then(|bio| bio.client_hello())
+            .and_then(client_hello::ClientHello::parse);
+
+        // on retry, we stay in `Accepted` and come back here.
+        let result = self.client_hello_callback.invoke();
+        self.client_hello_retry = matches!(result, Ok(false));
+        if !result? {
+            return Err(error::Error::from_io(ErrorKind::WouldBlock.into()));
+        }
+
         self.servername_callback.invoke()?;
 
         if let Some(alpn_iter) = accepted.client_hello().alpn() {
@@ -1105,25 +1929,46 @@ impl Ssl {
     }
 
     fn init_server_conn(&mut self) -> Result<(), error::Error> {
-        let provider = Arc::new(provider::default_provider());
+        if let Some(e) = &self.ctx_error {
+            return Err(e.clone());
+        }
+
+        let provider = Arc::new(self.crypto_provider());
         let verifier = Arc::new(
             verifier::ClientVerifier::new(
                 self.verify_roots.clone().into(),
                 provider.clone(),
                 self.verify_mode,
+                self.verify_callbacks(),
+                // `client_sigalgs` restrict what we accept from clients.
+                self.client_sigalgs.clone().or(self.sigalgs.clone()),
             )
             .map_err(error::Error::from_rustls)?,
         );
 
-        let resolver = self
-            .auth_keys
-            .server_resolver()
-            .ok_or_else(|| error::Error::bad_data("missing server keys"))?;
-
         let versions = self
             .versions
             .reduce_versions(self.ctx.get().method.server_versions)?;
 
+        // the key we choose depends on whether TLS1.3 will be negotiated.
+        let tls13 = versions
+            .iter()
+            .any(|v| v.version == ProtocolVersion::TLSv1_3)
+            && self
+                .client_hello
+                .as_ref()
+                .is_some_and(|hello| hello.offers_version(u16::from(ProtocolVersion::TLSv1_3)));
+
+        let resolver = self
+            .auth_keys
+            .server_resolver(
+                self.sigalgs.as_deref(),
+                self.sig_schemes.clone(),
+                tls13,
+                &self.tls12_ciphers,
+            )
+            .ok_or_else(|| error::Error::bad_data("missing server keys"))?;
+
         let mut config = ServerConfig::builder_with_provider(provider)
             .with_protocol_versions(&versions)
             .map_err(error::Error::from_rustls)?
@@ -1131,10 +1976,17 @@ impl Ssl {
             .with_cert_resolver(resolver);
 
         config.alpn_protocols = mem::take(&mut self.alpn);
-        config.max_early_data_size = self.max_early_data;
+        config.max_early_data_size = self.server_max_early_data();
+        config.key_log = callbacks::key_log(self.ctx.get().keylog_callback);
 
+        // rustls only accepts early data with stateful resumption.
+        //
+        // Divergence: so connections reading early data cannot resume tickets
+        // issued by other connections, nor can others resume theirs.
         if let Some(ticketer) = &self.ctx.get().ticketer {
-            if (self.raw_options & SSL_OP_NO_TICKET) != SSL_OP_NO_TICKET {
+            if (self.raw_options & SSL_OP_NO_TICKET) != SSL_OP_NO_TICKET
+                && self.early_data_state != EarlyDataState::Reading
+            {
                 config.ticketer = ticketer.clone();
             }
         }
@@ -1157,23 +2009,61 @@ impl Ssl {
         Ok(())
     }
 
+    /// The `max_early_data_size` for a new server connection.
+    ///
+    /// Like OpenSSL, early data is only accepted when the handshake is driven by
+    /// `SSL_read_early_data`, and the allow-early-data callback agrees.
+    ///
+    /// Divergence: rustls uses the same limit for the tickets it issues, so only
+    /// tickets from these connections permit early data.
+    fn server_max_early_data(&self) -> u32 {
+        if self.early_data_state != EarlyDataState::Reading {
+            return 0;
+        }
+        match self.client_offers_early_data() && self.max_early_data > 0 {
+            true if !self.allow_early_data_callback.invoke() => 0,
+            _ => self.max_early_data,
+        }
+    }
+
+    fn client_offers_early_data(&self) -> bool {
+        self.client_hello
+            .as_ref()
+            .is_some_and(|hello| hello.offers_early_data())
+    }
+
+    /// Return the provider, restricted to our configured ciphersuites.
+    fn crypto_provider(&self) -> CryptoProvider {
+        let mut provider = provider::default_provider();
+        provider.cipher_suites = self
+            .tls13_ciphers
+            .iter()
+            .chain(self.tls12_ciphers.iter())
+            .map(|cipher| *cipher.rustls)
+            .collect();
+        if let Some(kx_groups) = &self.kx_groups {
+            provider.kx_groups.clone_from(kx_groups);
+        }
+        provider
+    }
+
     fn conn(&self) -> Option<&Connection> {
         match &self.conn {
-            ConnState::Client(conn, _) | ConnState::Server(conn, _, _) => Some(conn),
+            ConnState::Client(conn, _, _) | ConnState::Server(conn, _, _) => Some(conn),
             _ => None,
         }
     }
 
     fn conn_mut(&mut self) -> Option<&mut Connection> {
         match &mut self.conn {
-            ConnState::Client(conn, _) | ConnState::Server(conn, _, _) => Some(conn),
+            ConnState::Client(conn, _, _) | ConnState::Server(conn, _, _) => Some(conn),
             _ => None,
         }
     }
 
     fn want(&self) -> Want {
         match &self.conn {
-            ConnState::Client(conn, _) | ConnState::Server(conn, _, _) => Want {
+            ConnState::Client(conn, _, _) | ConnState::Server(conn, _, _) => Want {
                 read: conn.wants_read(),
                 write: conn.wants_write(),
             },
@@ -1186,15 +2076,105 @@ impl Ssl {
     }
 
     fn write(&mut self, slice: &[u8]) -> Result<usize, error::Error> {
-        let written = match self.conn_mut() {
-            Some(conn) => conn.writer().write(slice).map_err(error::Error::from_io)?,
+        // Like OpenSSL, a write that did not complete must be retried with the same
+        // data. rustls already has the part it accepted.
+        // A bad retry keeps the record of what was accepted, for a correct one.
+        let mut written = match &self.write_retry {
+            Some(retry)
+                if slice.len() < retry.accepted
+                    || (!self.has_mode(SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER)
+                        && retry.buf != slice.as_ptr() as usize) =>
+            {
+                return Err(error::Error::bad_data("bad write retry"));
+            }
+            Some(retry) => retry.accepted,
             None => 0,
         };
-        self.try_io()?;
-        Ok(written)
+        self.write_retry = None;
+
+        // Like OpenSSL, the key update precedes the data.
+        self.start_key_update()?;
+
+        let result = loop {
+            let accepted = match self.conn_mut() {
+                Some(conn) => conn
+                    .writer()
+                    .write(&slice[written..])
+                    .map_err(error::Error::from_io)?,
+                None => 0,
+            };
+            written += accepted;
+
+            let result = self.try_io();
+            if result.is_err() || written == slice.len() || accepted == 0 {
+                break result;
+            }
+        };
+
+        // Without partial writes, succeed only once all of `slice` is sent.
+        let complete = written == slice.len()
+            && self
+                .conn()
+                .is_some_and(|conn| !conn.is_handshaking() && !conn.wants_write());
+
+        match result {
+            Err(e) if !e.is_would_block() => Err(e),
+            _ if complete => Ok(written),
+            _ if written > 0 && self.has_mode(SSL_MODE_ENABLE_PARTIAL_WRITE) => Ok(written),
+            result => {
+                // nothing accepted leaves nothing to retry.
+                if written > 0 {
+                    self.write_retry = Some(WriteRetry {
+                        buf: slice.as_ptr() as usize,
+                        accepted: written,
+                    });
+                }
+                result?;
+                Err(error::Error::from_io(ErrorKind::WouldBlock.into()))
+            }
+        }
+    }
+
+    fn has_mode(&self, mode: u32) -> bool {
+        self.raw_mode & mode == mode
     }
 
     fn read(&mut self, slice: &mut [u8]) -> Result<usize, error::Error> {
+        if self.peeked.is_empty() {
+            return self.read_conn(slice);
+        }
+
+        let len = cmp::min(slice.len(), self.peeked.len());
+        slice[..len].copy_from_slice(&self.peeked[..len]);
+        self.peeked.drain(..len);
+        Ok(len + self.read_buffered(&mut slice[len..]))
+    }
+
+    fn peek(&mut self, slice: &mut [u8]) -> Result<usize, error::Error> {
+        let wanted = slice.len().saturating_sub(self.peeked.len());
+        if wanted > 0 {
+            let mut buf = vec![0u8; wanted];
+            let read = match self.peeked.is_empty() {
+                true => self.read_conn(&mut buf)?,
+                false => self.read_buffered(&mut buf),
+            };
+            self.peeked.extend_from_slice(&buf[..read]);
+        }
+
+        let len = cmp::min(slice.len(), self.peeked.len());
+        slice[..len].copy_from_slice(&self.peeked[..len]);
+        Ok(len)
+    }
+
+    /// Read plaintext that rustls already has, without doing any IO.
+    fn read_buffered(&mut self, slice: &mut [u8]) -> usize {
+        match self.conn_mut() {
+            Some(conn) if !slice.is_empty() => conn.reader().read(slice).unwrap_or_default(),
+            _ => 0,
+        }
+    }
+
+    fn read_conn(&mut self, slice: &mut [u8]) -> Result<usize, error::Error> {
         let (late_err, read_count) = loop {
             let late_err = self.try_io();
 
@@ -1223,46 +2203,253 @@ impl Ssl {
         }
     }
 
+    fn write_early_data(&mut self, slice: &[u8]) -> Result<usize, error::Error> {
+        match (self.early_data_state, &self.conn) {
+            (EarlyDataState::None, ConnState::Nothing) if !self.is_server() => {
+                let permitted = self.client_session.as_ref().is_some_and(|sess| {
+                    let sess = sess.get();
+                    sess.is_resumable() && sess.properties().max_early_data > 0
+                });
+                if !permitted {
+                    return Err(error::Error::bad_data("session does not permit early data"));
+                }
+
+                self.early_data_state = EarlyDataState::Writing;
+                self.set_client_mode();
+                self.init_client_conn()?;
+                self.report_handshake_start();
+            }
+            (EarlyDataState::Writing, _) => {}
+            _ => return Err(error::Error::bad_data("early data cannot be written now")),
+        }
+
+        let written = match &mut self.conn {
+            ConnState::Client(Connection::Client(client), _, _) => match client.early_data() {
+                Some(mut early_data) => early_data.write(slice).map_err(error::Error::from_io)?,
+                None => return Err(error::Error::bad_data("early data no longer possible")),
+            },
+            _ => unreachable!(),
+        };
+
+        self.flush_tls()?;
+        Ok(written)
+    }
+
+    /// Returns `None` once there is no more early data to read.
+    fn read_early_data(&mut self, slice: &mut [u8]) -> Result<Option<usize>, error::Error> {
+        match (self.early_data_state, &self.conn) {
+            (EarlyDataState::None, ConnState::Nothing) if self.mode != ConnMode::Client => {
+                self.early_data_state = EarlyDataState::Reading;
+            }
+            (EarlyDataState::Reading, _) => {}
+            _ => return Err(error::Error::bad_data("early data cannot be read now")),
+        }
+
+        let result = self.accept();
+
+        let (accepted, read) = match &mut self.conn {
+            ConnState::Server(Connection::Server(server), _, _) => match server.early_data() {
+                Some(mut early_data) => {
+                    (true, early_data.read(slice).map_err(error::Error::from_io)?)
+                }
+                None => (false, 0),
+            },
+            // still waiting for the client hello.
+            _ => {
+                result?;
+                return Err(error::Error::from_io(ErrorKind::WouldBlock.into()));
+            }
+        };
+
+        if read > 0 {
+            return Ok(Some(read));
+        }
+
+        match result {
+            Err(e) if !e.is_would_block() => return Err(e),
+            _ if accepted && self.conn().is_some_and(|conn| conn.is_handshaking()) => {
+                // the client may yet send more early data.
+                return Err(error::Error::from_io(ErrorKind::WouldBlock.into()));
+            }
+            _ => {}
+        }
+
+        self.early_data_state = EarlyDataState::FinishedReading;
+        Ok(None)
+    }
+
+    fn get_early_data_status(&mut self) -> EarlyDataStatus {
+        let attempted = self.early_data_state == EarlyDataState::Writing;
+        let offered = self.client_offers_early_data();
+        match &mut self.conn {
+            ConnState::Client(Connection::Client(client), _, _) if attempted => {
+                match client.is_early_data_accepted() {
+                    true => EarlyDataStatus::Accepted,
+                    false => EarlyDataStatus::Rejected,
+                }
+            }
+            // Divergence: rustls does not say whether it rejected early data.
+            ConnState::Server(Connection::Server(server), _, _) if offered => {
+                match server.early_data() {
+                    Some(_) => EarlyDataStatus::Accepted,
+                    None => EarlyDataStatus::NotSent,
+                }
+            }
+            _ => EarlyDataStatus::NotSent,
+        }
+    }
+
+    /// Write any pending TLS data, without reading.
+    fn flush_tls(&mut self) -> Result<(), error::Error> {
+        let version = self.msg_callback_version();
+        let mut bio = match self.bio.as_mut() {
+            Some(bio) => bio::ObservedBio::new(bio, &self.msg_callback, version),
+            None => return Ok(()),
+        };
+
+        if let ConnState::Client(conn, _, _) | ConnState::Server(conn, _, _) = &mut self.conn {
+            while conn.wants_write() {
+                match conn.write_tls(&mut bio) {
+                    Ok(_) => {}
+                    // the remainder is written by later calls.
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(error::Error::from_io(e)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `try_io`, but having nothing to read is not an error once the handshake
+    /// is complete (eg. if it completed during `SSL_read_early_data`).
+    ///
+    /// Conversely, rustls returns after partial progress (eg. filling the BIO),
+    /// which is a would-block error until the handshake is complete.
+    fn try_handshake_io(&mut self) -> Result<(), error::Error> {
+        let result = self.try_io();
+        let handshaking = match &self.conn {
+            ConnState::Client(conn, _, _) | ConnState::Server(conn, _, _) => conn.is_handshaking(),
+            ConnState::Accepting(_) | ConnState::Accepted(_) => true,
+            ConnState::Nothing => false,
+        };
+
+        match result {
+            Err(e) if e.is_would_block() && !handshaking => Ok(()),
+            Ok(()) if handshaking => Err(error::Error::from_io(ErrorKind::WouldBlock.into())),
+            result => result,
+        }
+    }
+
     fn try_io(&mut self) -> Result<(), error::Error> {
-        let bio = match self.bio.as_mut() {
-            Some(bio) => bio,
+        self.start_key_update()?;
+        let role = self.info_callback_role();
+        let version = self.msg_callback_version();
+        let mut bio = match self.bio.as_mut() {
+            Some(bio) => bio::ObservedBio::new(bio, &self.msg_callback, version),
             None => return Ok(()), // investigate OpenSSL behaviour without a BIO
         };
 
         match &mut self.conn {
-            ConnState::Client(conn, _) | ConnState::Server(conn, _, _) => {
-                match conn.complete_io(bio) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        // obtain underlying TLS protocol error (if any), and let it stamp
-                        // out the one wrapped in io::Error.
-                        if let Some(tls_err) = conn.process_new_packets().err() {
-                            return Err(error::Error::from_rustls(tls_err));
+            ConnState::Client(conn, _, _) | ConnState::Server(conn, _, _) => {
+                let was_handshaking = conn.is_handshaking();
+                let io_result = conn.complete_io(&mut bio);
+                let progressed = bio.count() > 0;
+                let io_state = conn.process_new_packets();
+                let handshaking = conn.is_handshaking();
+
+                match &self.conn {
+                    ConnState::Client(conn, logs, cache) => {
+                        let verify_result = logs.verify.result();
+                        cache.complete_new_sessions(|sess| sess.complete_from(conn, verify_result));
+                    }
+                    ConnState::Server(conn, verifier, cache) => {
+                        let verify_result = verifier.log().result();
+                        cache.complete_new_sessions(|sess| sess.complete_from(conn, verify_result));
+                    }
+                    _ => {}
+                }
+
+                let result = match (io_result, io_state) {
+                    // obtain underlying TLS protocol error (if any), and let it stamp
+                    // out the one wrapped in io::Error.
+                    (_, Err(tls_err)) => {
+                        self.report_fatal_alert(&tls_err);
+                        Err(error::Error::from_rustls(tls_err))
+                    }
+                    (Err(e), Ok(_)) => Err(error::Error::from_io(e)),
+                    (Ok(_), Ok(io_state)) => {
+                        if io_state.peer_has_closed() && !self.shutdown_flags.is_received() {
+                            self.shutdown_flags.set_received();
+                            self.invoke_info_callback(
+                                SSL_CB_READ_ALERT,
+                                alert_to_info_value(SSL3_AL_WARNING, AlertDescription::CloseNotify),
+                            );
                         }
-                        return Err(error::Error::from_io(e));
+                        Ok(())
                     }
                 };
-                let io_state = conn
-                    .process_new_packets()
-                    .map_err(error::Error::from_rustls)?;
-                if io_state.peer_has_closed() {
-                    self.shutdown_flags.set_received();
+
+                if was_handshaking {
+                    if progressed {
+                        self.invoke_info_callback(role | SSL_CB_LOOP, 1);
+                    }
+                    if result.is_ok() && !handshaking {
+                        if let Some(scheme) = self.sig_schemes.chosen() {
+                            self.auth_keys.set_current_used(scheme);
+                        }
+                        self.invoke_info_callback(SSL_CB_HANDSHAKE_DONE, 1);
+                        self.invoke_info_callback(role | SSL_CB_EXIT, 1);
+                    } else {
+                        self.invoke_info_callback(role | SSL_CB_EXIT, -1);
+                    }
                 }
-                Ok(())
+
+                result
             }
             ConnState::Accepting(acceptor) => {
-                if let Err(e) = acceptor.read_tls(bio) {
-                    return Err(error::Error::from_io(e));
+                let accepted = loop {
+                    let read = match acceptor.read_tls(&mut bio) {
+                        Ok(0) => {
+                            // the peer closed before sending a whole client hello.
+                            self.invoke_info_callback(role | SSL_CB_EXIT, -1);
+                            return Err(error::Error::from_io(io::Error::new(
+                                ErrorKind::UnexpectedEof,
+                                "tls handshake eof",
+                            )));
+                        }
+                        Ok(read) => read,
+                        Err(e) => {
+                            self.invoke_info_callback(role | SSL_CB_EXIT, -1);
+                            return Err(error::Error::from_io(e));
+                        }
+                    };
+
+                    match acceptor.accept() {
+                        // the rest of the client hello may be in the BIO.
+                        Ok(None) if read > 0 => continue,
+                        result => break result,
+                    }
                 };
 
-                match acceptor.accept() {
-                    Ok(None) => Ok(()),
+                match accepted {
+                    Ok(None) => {
+                        self.invoke_info_callback(role | SSL_CB_EXIT, -1);
+                        Ok(())
+                    }
                     Ok(Some(accepted)) => {
                         self.conn = ConnState::Accepted(accepted);
-                        self.invoke_accepted_callbacks()
+                        self.invoke_info_callback(role | SSL_CB_LOOP, 1);
+                        let result = self.invoke_accepted_callbacks();
+                        if result.is_err() && matches!(self.conn, ConnState::Accepted(_)) {
+                            // failed before the server connection could report this.
+                            self.invoke_info_callback(role | SSL_CB_EXIT, -1);
+                        }
+                        result
                     }
                     Err((error, mut alert)) => {
-                        alert.write_all(bio).map_err(error::Error::from_io)?;
+                        alert.write_all(&mut bio).map_err(error::Error::from_io)?;
+                        self.report_fatal_alert(&error);
+                        self.invoke_info_callback(role | SSL_CB_EXIT, -1);
                         Err(error::Error::from_rustls(error))
                     }
                 }
@@ -1281,6 +2468,10 @@ impl Ssl {
         if !self.shutdown_flags.is_sent() {
             if let Some(conn) = self.conn_mut() {
                 conn.send_close_notify();
+                self.invoke_info_callback(
+                    SSL_CB_WRITE_ALERT,
+                    alert_to_info_value(SSL3_AL_WARNING, AlertDescription::CloseNotify),
+                );
             };
 
             self.shutdown_flags.set_sent();
@@ -1307,13 +2498,15 @@ impl Ssl {
     }
 
     fn get_pending_plaintext(&mut self) -> usize {
-        self.conn_mut()
-            .as_mut()
-            .and_then(|conn| {
-                let io_state = conn.process_new_packets().ok()?;
-                Some(io_state.plaintext_bytes_to_read())
-            })
-            .unwrap_or_default()
+        self.peeked.len()
+            + self
+                .conn_mut()
+                .as_mut()
+                .and_then(|conn| {
+                    let io_state = conn.process_new_packets().ok()?;
+                    Some(io_state.plaintext_bytes_to_read())
+                })
+                .unwrap_or_default()
     }
 
     fn get_agreed_alpn(&self) -> Option<&[u8]> {
@@ -1378,6 +2571,84 @@ impl Ssl {
             .map(|suite| suite.suite())
     }
 
+    /// Key exchange groups offered by the client.
+    ///
+    /// This is only available to servers.
+    fn get_peer_groups(&self) -> &[NamedGroup] {
+        &self.peer_groups
+    }
+
+    /// Key exchange groups supported by both peers.
+    ///
+    /// Like OpenSSL, these are in the client's preference order unless
+    /// `SSL_OP_CIPHER_SERVER_PREFERENCE` is set.
+    fn get_shared_groups(&self) -> Vec<NamedGroup> {
+        if !self.is_server() {
+            return vec![];
+        }
+
+        let ours = self
+            .crypto_provider()
+            .kx_groups
+            .iter()
+            .map(|group| group.name())
+            .collect::<Vec<_>>();
+
+        let (preferred, supported) = match self.raw_options & SSL_OP_CIPHER_SERVER_PREFERENCE != 0 {
+            true => (&ours[..], &self.peer_groups[..]),
+            false => (&self.peer_groups[..], &ours[..]),
+        };
+
+        preferred
+            .iter()
+            .filter(|group| supported.contains(group))
+            .copied()
+            .collect()
+    }
+
+    /// Signature schemes the peer accepts.
+    ///
+    /// For servers, these are from the `ClientHello`.  For clients, these are
+    /// from the server's `CertificateRequest` (if any).
+    fn get_peer_sigalgs(&self) -> Vec<SignatureScheme> {
+        self.sig_schemes.offered()
+    }
+
+    /// Signature schemes accepted by the peer that we could sign with.
+    ///
+    /// Like OpenSSL, these are in the peer's preference order unless we
+    /// are a server and `SSL_OP_CIPHER_SERVER_PREFERENCE` is set.
+    fn get_shared_sigalgs(&self) -> Vec<SignatureScheme> {
+        let configured = match self.is_server() {
+            true => self.sigalgs.clone(),
+            false => self.client_sigalgs.clone().or(self.sigalgs.clone()),
+        };
+        let ours = configured.unwrap_or_else(|| {
+            self.crypto_provider()
+                .signature_verification_algorithms
+                .supported_schemes()
+        });
+        let peer = self.get_peer_sigalgs();
+
+        let server_preference =
+            self.is_server() && self.raw_options & SSL_OP_CIPHER_SERVER_PREFERENCE != 0;
+        let (preferred, supported) = match server_preference {
+            true => (&ours, &peer),
+            false => (&peer, &ours),
+        };
+
+        preferred
+            .iter()
+            .filter(|scheme| supported.contains(scheme))
+            .copied()
+            .collect()
+    }
+
+    /// The signature scheme we last signed with in a handshake.
+    fn get_sig_scheme(&self) -> Option<SignatureScheme> {
+        self.sig_schemes.chosen()
+    }
+
     fn get_negotiated_key_exchange_group(&self) -> Option<&'static dyn SupportedKxGroup> {
         self.conn()
             .and_then(|conn| conn.negotiated_key_exchange_group())
@@ -1385,21 +2656,80 @@ impl Ssl {
 
     fn get_last_verification_result(&self) -> i64 {
         match &self.conn {
-            ConnState::Client(_, verifier) => verifier.last_result(),
-            ConnState::Server(_, verifier, _) => verifier.last_result(),
+            ConnState::Client(_, logs, _) => logs.verify.result(),
+            ConnState::Server(_, verifier, _) => verifier.log().result(),
             _ => X509_V_ERR_UNSPECIFIED as i64,
         }
     }
 
     fn get_last_verification_sig_scheme(&self) -> Option<SignatureScheme> {
         match &self.conn {
-            ConnState::Client(_, verifier) => verifier.last_sig_scheme(),
-            ConnState::Server(_, verifier, _) => verifier.last_sig_scheme(),
+            ConnState::Client(_, logs, _) => logs.verify.sig_scheme(),
+            ConnState::Server(_, verifier, _) => verifier.log().sig_scheme(),
             _ => None,
         }
     }
 
+    fn export_keying_material(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<(), error::Error> {
+        match self.conn() {
+            // Like OpenSSL, refuse labels that TLS1.2 uses itself.
+            Some(conn)
+                if conn.protocol_version() != Some(ProtocolVersion::TLSv1_3)
+                    && TLS12_RESERVED_EXPORTER_LABELS
+                        .iter()
+                        .any(|reserved| label.starts_with(reserved)) =>
+            {
+                Err(error::Error::bad_data("reserved exporter label"))
+            }
+            Some(conn) => conn
+                .export_keying_material(output, label, context)
+                .map(|_| ())
+                .map_err(error::Error::from_rustls),
+            None => Err(error::Error::bad_data("handshake not complete")),
+        }
+    }
+
+    fn key_update(&mut self, update_type: c_int) -> Result<(), error::Error> {
+        match self.conn() {
+            Some(conn)
+                if conn.protocol_version() == Some(ProtocolVersion::TLSv1_3)
+                    && !conn.is_handshaking() =>
+            {
+                self.pending_key_update = Some(update_type);
+                Ok(())
+            }
+            _ => Err(error::Error::bad_data(
+                "key update needs a completed TLS1.3 handshake",
+            )),
+        }
+    }
+
+    /// Switch to new traffic keys if `SSL_key_update` asked to, queuing the
+    /// `KeyUpdate` message ahead of any later data.
+    fn start_key_update(&mut self) -> Result<(), error::Error> {
+        if self.pending_key_update.take().is_some() {
+            if let Some(conn) = self.conn_mut() {
+                conn.refresh_traffic_keys()
+                    .map_err(error::Error::from_rustls)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_key_update_type(&self) -> Option<c_int> {
+        self.pending_key_update
+    }
+
     fn get_error(&mut self) -> c_int {
+        if self.client_hello_retry {
+            return SSL_ERROR_WANT_CLIENT_HELLO_CB;
+        }
+
         match self.conn_mut() {
             Some(conn) => {
                 if let Err(e) = conn.process_new_packets() {
@@ -1419,7 +2749,14 @@ impl Ssl {
 
                 SSL_ERROR_NONE
             }
-            None => SSL_ERROR_SSL,
+            None => {
+                // eg. `SSL_read_early_data` before the client hello arrives.
+                let accepting = matches!(self.conn, ConnState::Accepting(_));
+                match self.bio.as_ref() {
+                    Some(bio) if accepting && bio.read_would_block() => SSL_ERROR_WANT_READ,
+                    _ => SSL_ERROR_SSL,
+                }
+            }
         }
     }
 
@@ -1451,6 +2788,14 @@ impl Ssl {
         Ok(verify_roots)
     }
 
+    fn set_current_cert(&mut self, next: bool) -> bool {
+        self.auth_keys.set_current(next)
+    }
+
+    fn select_current_cert(&mut self, cert: *mut X509) -> bool {
+        self.auth_keys.select_current(cert)
+    }
+
     fn get_certificate(&self) -> *mut X509 {
         self.auth_keys.borrow_current_cert()
     }
@@ -1488,18 +2833,110 @@ impl Ssl {
     fn get_current_session(&self) -> Option<Arc<NotThreadSafe<SslSession>>> {
         match &self.conn {
             ConnState::Server(_, _, cache) => cache.get_most_recent_session(),
-            // divergence: `SSL_get1_session` etc only work for server SSLs
-            _ => None,
+            ConnState::Client(_, _, cache) => cache
+                .get_most_recent_session()
+                .or_else(|| self.resumed_client_session().cloned()),
+            _ => self.client_session.clone(),
         }
     }
 
     fn borrow_current_session(&self) -> *mut entry::SSL_SESSION {
-        match &self.conn {
-            ConnState::Server(_, _, cache) => cache.borrow_most_recent_session(),
-            // divergence: `SSL_get_session` etc only work for server SSLs
-            _ => ptr::null_mut(),
+        let sess = match &self.conn {
+            ConnState::Server(_, _, cache) => return cache.borrow_most_recent_session(),
+            ConnState::Client(_, _, cache) => match cache.borrow_most_recent_session() {
+                ptr if ptr.is_null() => self.resumed_client_session(),
+                ptr => return ptr,
+            },
+            _ => self.client_session.as_ref(),
+        };
+        sess.map(|sess| Arc::as_ptr(sess) as *mut entry::SSL_SESSION)
+            .unwrap_or_else(ptr::null_mut)
+    }
+
+    /// The session from `SSL_set_session`, if it was resumed.
+    fn resumed_client_session(&self) -> Option<&Arc<NotThreadSafe<SslSession>>> {
+        match self.was_session_reused() {
+            true => self.client_session.as_ref(),
+            false => None,
         }
     }
+
+    fn set_session(&mut self, sess: Option<Arc<NotThreadSafe<SslSession>>>) {
+        self.client_session = sess;
+    }
+}
+
+/// OpenSSL's name for a protocol version, as used by `SSL_SESSION_print`.
+fn protocol_version_name(version: u16) -> &'static str {
+    match ProtocolVersion::from(version) {
+        ProtocolVersion::TLSv1_3 => "TLSv1.3",
+        ProtocolVersion::TLSv1_2 => "TLSv1.2",
+        ProtocolVersion::TLSv1_1 => "TLSv1.1",
+        ProtocolVersion::TLSv1_0 => "TLSv1",
+        ProtocolVersion::SSLv3 => "SSLv3",
+        ProtocolVersion::DTLSv1_0 => "DTLSv1",
+        ProtocolVersion::DTLSv1_2 => "DTLSv1.2",
+        _ => "unknown",
+    }
+}
+
+fn hex_upper(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// Parse a colon-separated list of OpenSSL group names.
+pub fn parse_groups_list(list: &str) -> Result<Vec<NamedGroup>, error::Error> {
+    list.split(':')
+        .map(|name| {
+            constants::named_group_from_name(name)
+                .ok_or_else(|| error::Error::bad_data(&format!("group '{name}' cannot be set")))
+        })
+        .collect()
+}
+
+/// Parse a colon-separated list of OpenSSL signature algorithm names.
+pub fn parse_sigalgs_list(list: &str) -> Result<Vec<SignatureScheme>, error::Error> {
+    let mut sigalgs = vec![];
+
+    for name in list.split(':') {
+        let scheme = constants::sig_scheme_from_name(name).ok_or_else(|| {
+            error::Error::bad_data(&format!("signature algorithm '{name}' cannot be set"))
+        })?;
+
+        if sigalgs.contains(&scheme) {
+            return Err(error::Error::bad_data("duplicate signature algorithm"));
+        }
+        sigalgs.push(scheme);
+    }
+
+    Ok(sigalgs)
+}
+
+/// Resolve `groups` to our supported key exchange groups, retaining their order.
+///
+/// Groups we don't support are skipped, but at least one must remain.
+pub fn select_kx_groups(
+    groups: &[NamedGroup],
+) -> Result<Vec<&'static dyn SupportedKxGroup>, error::Error> {
+    let mut selected = vec![];
+
+    for (i, group) in groups.iter().enumerate() {
+        if groups[..i].contains(group) {
+            return Err(error::Error::bad_data("duplicate group"));
+        }
+
+        if let Some(kx_group) = provider::ALL_KX_GROUPS
+            .iter()
+            .find(|kx_group| kx_group.name() == *group)
+        {
+            selected.push(*kx_group);
+        }
+    }
+
+    match selected.is_empty() {
+        true => Err(error::Error::bad_data("no supported groups")),
+        false => Ok(selected),
+    }
 }
 
 /// Encode rustls's internal representation in the wire format.
@@ -1569,6 +3006,30 @@ enum ConnMode {
     Server,
 }
 
+/// How much of an incomplete write's buffer rustls accepted.
+#[derive(Debug)]
+struct WriteRetry {
+    /// The buffer address, which must be the same for the retry.
+    buf: usize,
+    accepted: usize,
+}
+
+/// Progress through `SSL_write_early_data` or `SSL_read_early_data`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum EarlyDataState {
+    None,
+    Writing,
+    Reading,
+    FinishedReading,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum EarlyDataStatus {
+    NotSent,
+    Rejected,
+    Accepted,
+}
+
 #[repr(i32)]
 enum ShutdownResult {
     Sent = 0,
@@ -1622,7 +3083,7 @@ impl ShutdownFlags {
     }
 }
 
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct VerifyMode(i32);
 
 impl VerifyMode {
@@ -1696,6 +3157,19 @@ impl EnabledVersions {
 }
 
 pub(crate) const SSL_OP_NO_TICKET: u64 = 1 << 14; // See ssl.h
+pub(crate) const SSL_OP_CIPHER_SERVER_PREFERENCE: u64 = 1 << 22; // ditto
+const SSL3_RT_MAX_PLAIN_LENGTH: u32 = 16384; // ditto
+const SSL_MODE_ENABLE_PARTIAL_WRITE: u32 = 0x1; // ditto
+const SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER: u32 = 0x2; // ditto
+const SSL_MODE_AUTO_RETRY: u32 = 0x4; // ditto
+
+const TLS12_RESERVED_EXPORTER_LABELS: &[&[u8]] = &[
+    b"client finished",
+    b"server finished",
+    b"master secret",
+    b"extended master secret",
+    b"key expansion",
+];
 
 #[cfg(test)]
 mod tests {
@@ -1714,4 +3188,111 @@ mod tests {
         assert_eq!(None, parse_alpn(&[1, 1, 1]));
         assert_eq!(None, parse_alpn(&[255]));
     }
+
+    #[test]
+    fn test_groups() {
+        let groups = parse_groups_list("P-384:x25519:prime256v1").unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                NamedGroup::secp384r1,
+                NamedGroup::X25519,
+                NamedGroup::secp256r1
+            ]
+        );
+        assert_eq!(
+            select_kx_groups(&groups)
+                .unwrap()
+                .iter()
+                .map(|group| group.name())
+                .collect::<Vec<_>>(),
+            groups
+        );
+
+        // unknown names
+        assert!(parse_groups_list("").is_err());
+        assert!(parse_groups_list("X25519:brainpoolP256r1").is_err());
+
+        // known to openssl, but unsupported
+        let groups = parse_groups_list("X448:X25519").unwrap();
+        assert_eq!(select_kx_groups(&groups).unwrap().len(), 1);
+        let groups = parse_groups_list("X448:ffdhe2048").unwrap();
+        assert!(select_kx_groups(&groups).is_err());
+
+        // duplicates
+        let groups = parse_groups_list("X25519:P-256:x25519").unwrap();
+        assert!(select_kx_groups(&groups).is_err());
+    }
+
+    #[test]
+    fn test_parse_sigalgs_list() {
+        assert_eq!(
+            parse_sigalgs_list("RSA-PSS+SHA256:ecdsa_secp256r1_sha256:RSA+SHA384:ed25519").unwrap(),
+            vec![
+                SignatureScheme::RSA_PSS_SHA256,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::RSA_PKCS1_SHA384,
+                SignatureScheme::ED25519,
+            ]
+        );
+        assert_eq!(
+            parse_sigalgs_list("PSS+sha512:ECDSA+SHA384").unwrap(),
+            vec![
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureScheme::ECDSA_NISTP384_SHA384
+            ]
+        );
+
+        assert!(parse_sigalgs_list("").is_err());
+        assert!(parse_sigalgs_list("RSA+MD5").is_err());
+        assert!(parse_sigalgs_list("rsa_pss_rsae_sha256:RSA-PSS+SHA256").is_err());
+        assert!(parse_sigalgs_list("ECDSA_SECP256R1_SHA256").is_err());
+    }
+
+    #[test]
+    fn test_session_encoding() {
+        let mut sess = SslSession::new(
+            vec![1; 32],
+            vec![0x22; 100],
+            b"context".to_vec(),
+            cache::TimeBase(1234),
+            300,
+        );
+        sess.properties.version = 0x0303;
+        sess.properties.cipher = 0xc02b;
+        sess.properties.alpn_selected = Some(b"http/1.1".to_vec());
+
+        let encoded = sess.encode();
+        let (decoded, rest) = SslSession::decode(&encoded).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(decoded.get_id(), sess.get_id());
+        assert_eq!(decoded.value, sess.value);
+        assert_eq!(decoded.context, sess.context);
+        assert_eq!(decoded.get_creation_time(), 1234);
+        assert_eq!(decoded.get_time_out(), 300);
+        assert_eq!(decoded.properties.version, 0x0303);
+        assert_eq!(decoded.properties.cipher, 0xc02b);
+        assert_eq!(
+            decoded.properties.alpn_selected.as_deref(),
+            Some(&b"http/1.1"[..])
+        );
+
+        // sessions from OpenSSL are readable, but not resumable
+        let mut asn1 = sess.to_asn1();
+        asn1.master_key = vec![0x33; 48];
+        asn1.ticket = None;
+        let (decoded, _) = SslSession::decode(&asn1.encode()).unwrap();
+        assert_eq!(decoded.properties.master_key, vec![0x33; 48]);
+        assert!(!decoded.is_resumable());
+
+        let sess = SslSession::new(vec![2], vec![3; 10], vec![], cache::TimeBase(5), 6);
+
+        // the previous encoding can still be read
+        let legacy = sess.encode_legacy();
+        let (decoded, rest) = SslSession::decode(&legacy).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(decoded.get_id(), sess.get_id());
+        assert_eq!(decoded.value, sess.value);
+        assert_eq!(decoded.get_time_out(), 6);
+    }
 }
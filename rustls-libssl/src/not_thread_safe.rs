@@ -50,7 +50,7 @@ impl<T: Ord + PartialOrd> Ord for NotThreadSafe<T> {
 
 impl<T: PartialOrd<T> + Ord> PartialOrd for NotThreadSafe<T> {
     fn partial_cmp(&self, other: &NotThreadSafe<T>) -> Option<cmp::Ordering> {
-        Some(self.get().cmp(other.get()))
+        Some(self.cmp(other))
     }
 }
 
@@ -0,0 +1,352 @@
+//! Encodings of `SSL_SESSION` objects.
+//!
+//! [`SessionAsn1`] is OpenSSL's `SSL_SESSION_ASN1` DER layout, as produced
+//! by `i2d_SSL_SESSION`.  [`SessionProperties`] are the parts of a session
+//! that applications can inspect and change.
+
+use std::ffi::CString;
+
+use crate::x509::OwnedX509;
+
+/// The fields of OpenSSL's `SSL_SESSION_ASN1` that we produce or consume.
+///
+/// Integer fields that are zero, and `Option` fields that are `None`,
+/// are omitted from the encoding (as OpenSSL does).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionAsn1 {
+    pub ssl_version: u16,
+    pub cipher: u16,
+    pub session_id: Vec<u8>,
+    pub master_key: Vec<u8>,
+    pub time: u64,
+    pub timeout: u64,
+    /// DER encoding of the peer's end-entity certificate.
+    pub peer: Option<Vec<u8>>,
+    pub session_id_context: Vec<u8>,
+    pub verify_result: i64,
+    pub hostname: Option<Vec<u8>>,
+    pub ticket_lifetime_hint: u64,
+    pub ticket: Option<Vec<u8>>,
+    pub flags: u64,
+    pub ticket_age_add: u32,
+    pub max_early_data: u32,
+    pub alpn_selected: Option<Vec<u8>>,
+    pub ticket_appdata: Option<Vec<u8>>,
+}
+
+impl SessionAsn1 {
+    /// `SSL_SESSION_ASN1_VERSION`
+    const VERSION: u64 = 1;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = vec![];
+        put_integer(&mut body, Self::VERSION.into());
+        put_integer(&mut body, self.ssl_version.into());
+        put_tlv(&mut body, OCTET_STRING, &self.cipher.to_be_bytes());
+        put_tlv(&mut body, OCTET_STRING, &self.session_id);
+        put_tlv(&mut body, OCTET_STRING, &self.master_key);
+        put_explicit_integer(&mut body, 1, self.time.into());
+        put_explicit_integer(&mut body, 2, self.timeout.into());
+        if let Some(peer) = &self.peer {
+            put_tlv(&mut body, explicit(3), peer);
+        }
+        put_explicit_octets(&mut body, 4, Some(&self.session_id_context));
+        put_explicit_integer(&mut body, 5, self.verify_result.into());
+        put_explicit_octets(&mut body, 6, self.hostname.as_deref());
+        put_explicit_integer(&mut body, 9, self.ticket_lifetime_hint.into());
+        put_explicit_octets(&mut body, 10, self.ticket.as_deref());
+        put_explicit_integer(&mut body, 13, self.flags.into());
+        put_explicit_integer(&mut body, 14, self.ticket_age_add.into());
+        put_explicit_integer(&mut body, 15, self.max_early_data.into());
+        put_explicit_octets(&mut body, 16, self.alpn_selected.as_deref());
+        put_explicit_octets(&mut body, 18, self.ticket_appdata.as_deref());
+
+        let mut ret = vec![];
+        put_tlv(&mut ret, SEQUENCE, &body);
+        ret
+    }
+
+    /// Decodes from the front of `slice`.  Returns the remainder.
+    ///
+    /// Fields we do not use (eg, PSK identities or the key exchange group)
+    /// are skipped.
+    pub fn decode(slice: &[u8]) -> Option<(Self, &[u8])> {
+        let mut outer = Reader(slice);
+        let mut body = Reader(outer.expect(SEQUENCE)?);
+
+        if body.integer()? != i128::from(Self::VERSION) {
+            return None;
+        }
+
+        let mut ret = Self {
+            ssl_version: body.integer()?.try_into().ok()?,
+            ..Self::default()
+        };
+
+        let cipher = body.expect(OCTET_STRING)?;
+        ret.cipher = u16::from_be_bytes(cipher.try_into().ok()?);
+        ret.session_id = body.expect(OCTET_STRING)?.to_vec();
+        ret.master_key = body.expect(OCTET_STRING)?.to_vec();
+
+        if ret.session_id.len() > MAX_SESSION_ID_LENGTH {
+            return None;
+        }
+
+        while !body.0.is_empty() {
+            let (tag, content) = body.next()?;
+            match tag {
+                t if t == explicit(1) => ret.time = Reader(content).integer()?.try_into().ok()?,
+                t if t == explicit(2) => {
+                    ret.timeout = Reader(content).integer()?.try_into().ok()?
+                }
+                t if t == explicit(3) => {
+                    // the contents are the certificate's encoding
+                    Reader(content).expect(SEQUENCE)?;
+                    ret.peer = Some(content.to_vec());
+                }
+                t if t == explicit(4) => ret.session_id_context = Reader(content).octets()?,
+                t if t == explicit(5) => {
+                    ret.verify_result = Reader(content).integer()?.try_into().ok()?
+                }
+                t if t == explicit(6) => ret.hostname = Some(Reader(content).octets()?),
+                t if t == explicit(9) => {
+                    ret.ticket_lifetime_hint = Reader(content).integer()?.try_into().ok()?
+                }
+                t if t == explicit(10) => ret.ticket = Some(Reader(content).octets()?),
+                t if t == explicit(13) => ret.flags = Reader(content).integer()?.try_into().ok()?,
+                t if t == explicit(14) => {
+                    ret.ticket_age_add = Reader(content).integer()?.try_into().ok()?
+                }
+                t if t == explicit(15) => {
+                    ret.max_early_data = Reader(content).integer()?.try_into().ok()?
+                }
+                t if t == explicit(16) => ret.alpn_selected = Some(Reader(content).octets()?),
+                t if t == explicit(18) => ret.ticket_appdata = Some(Reader(content).octets()?),
+                // other fields are allowed, but ignored
+                _ if tag & CONTEXT_SPECIFIC == CONTEXT_SPECIFIC => {}
+                _ => return None,
+            }
+        }
+
+        Some((ret, outer.0))
+    }
+}
+
+/// The properties of a session that are exposed by `SSL_SESSION_get*`
+/// and `SSL_SESSION_set*` functions.
+///
+/// These are recorded when the session is made.  Changing them affects the
+/// encoding of the session, but not how rustls resumes it.
+#[derive(Clone, Default)]
+pub struct SessionProperties {
+    pub version: u16,
+    pub cipher: u16,
+    pub master_key: Vec<u8>,
+    pub hostname: Option<CString>,
+    pub alpn_selected: Option<Vec<u8>>,
+    pub peer: Option<OwnedX509>,
+    pub has_ticket: bool,
+    /// The ticket itself, if known: rustls does not expose the tickets it receives.
+    pub ticket: Option<Vec<u8>>,
+    pub ticket_lifetime_hint: u64,
+    pub max_early_data: u32,
+    pub ticket_appdata: Option<Vec<u8>>,
+    /// The result of verifying the peer's certificate, as an `X509_V_*` value.
+    pub verify_result: i64,
+}
+
+impl SessionProperties {
+    pub fn from_asn1(asn1: &SessionAsn1) -> Self {
+        Self {
+            version: asn1.ssl_version,
+            cipher: asn1.cipher,
+            master_key: asn1.master_key.clone(),
+            hostname: asn1
+                .hostname
+                .as_ref()
+                .and_then(|name| CString::new(name.clone()).ok()),
+            alpn_selected: asn1.alpn_selected.clone(),
+            peer: asn1.peer.as_deref().and_then(OwnedX509::parse_der),
+            has_ticket: asn1.ticket.is_some(),
+            ticket: asn1.ticket.clone(),
+            ticket_lifetime_hint: asn1.ticket_lifetime_hint,
+            max_early_data: asn1.max_early_data,
+            ticket_appdata: asn1.ticket_appdata.clone(),
+            verify_result: asn1.verify_result,
+        }
+    }
+
+    /// Fill in the fields of `asn1` that these properties provide.
+    pub fn describe(&self, asn1: &mut SessionAsn1) {
+        asn1.ssl_version = self.version;
+        asn1.cipher = self.cipher;
+        asn1.master_key.clone_from(&self.master_key);
+        asn1.hostname = self.hostname.as_ref().map(|name| name.as_bytes().to_vec());
+        asn1.alpn_selected.clone_from(&self.alpn_selected);
+        asn1.peer = self.peer.as_ref().map(|peer| peer.der_bytes());
+        if self.ticket.is_some() {
+            asn1.ticket.clone_from(&self.ticket);
+        }
+        asn1.ticket_lifetime_hint = self.ticket_lifetime_hint;
+        asn1.max_early_data = self.max_early_data;
+        asn1.ticket_appdata.clone_from(&self.ticket_appdata);
+        asn1.verify_result = self.verify_result;
+    }
+}
+
+/// A minimal DER reader.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Returns the next tag and its contents.
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let mut r = self.0;
+        let tag = take_u8(&mut r)?;
+        let len = match take_u8(&mut r)? {
+            short @ 0..=0x7f => short as usize,
+            long @ 0x81..=0x84 => take(&mut r, (long & 0x7f) as usize)?
+                .iter()
+                .fold(0usize, |acc, byte| (acc << 8) | *byte as usize),
+            _ => return None,
+        };
+        let content = take(&mut r, len)?;
+        self.0 = r;
+        Some((tag, content))
+    }
+
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.next()? {
+            (t, content) if t == tag => Some(content),
+            _ => None,
+        }
+    }
+
+    fn integer(&mut self) -> Option<i128> {
+        let content = self.expect(INTEGER)?;
+        if content.is_empty() || content.len() > 9 {
+            return None;
+        }
+        let init = match content[0] & 0x80 {
+            0 => 0i128,
+            _ => -1i128,
+        };
+        Some(
+            content
+                .iter()
+                .fold(init, |acc, byte| (acc << 8) | *byte as i128),
+        )
+    }
+
+    fn octets(&mut self) -> Option<Vec<u8>> {
+        self.expect(OCTET_STRING).map(|content| content.to_vec())
+    }
+}
+
+fn put_tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    out.push(tag);
+    match content.len() {
+        len @ 0..=0x7f => out.push(len as u8),
+        len => {
+            let bytes = len.to_be_bytes();
+            let skip = bytes.iter().take_while(|b| **b == 0).count();
+            out.push(0x80 | (bytes.len() - skip) as u8);
+            out.extend_from_slice(&bytes[skip..]);
+        }
+    }
+    out.extend_from_slice(content);
+}
+
+fn put_integer(out: &mut Vec<u8>, value: i128) {
+    let bytes = value.to_be_bytes();
+    // strip redundant leading bytes, leaving the sign in the top bit
+    let mut skip = 0;
+    while skip < bytes.len() - 1
+        && ((bytes[skip] == 0x00 && bytes[skip + 1] & 0x80 == 0)
+            || (bytes[skip] == 0xff && bytes[skip + 1] & 0x80 == 0x80))
+    {
+        skip += 1;
+    }
+    put_tlv(out, INTEGER, &bytes[skip..]);
+}
+
+fn put_explicit_integer(out: &mut Vec<u8>, tag: u8, value: i128) {
+    if value != 0 {
+        let mut inner = vec![];
+        put_integer(&mut inner, value);
+        put_tlv(out, explicit(tag), &inner);
+    }
+}
+
+fn put_explicit_octets(out: &mut Vec<u8>, tag: u8, value: Option<&[u8]>) {
+    if let Some(value) = value {
+        let mut inner = vec![];
+        put_tlv(&mut inner, OCTET_STRING, value);
+        put_tlv(out, explicit(tag), &inner);
+    }
+}
+
+const fn explicit(tag: u8) -> u8 {
+    CONTEXT_SPECIFIC | CONSTRUCTED | tag
+}
+
+fn take<'a>(r: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if len > r.len() {
+        return None;
+    }
+    let (front, rest) = r.split_at(len);
+    *r = rest;
+    Some(front)
+}
+
+fn take_u8(r: &mut &[u8]) -> Option<u8> {
+    take(r, 1).map(|b| b[0])
+}
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const SEQUENCE: u8 = 0x30;
+const CONSTRUCTED: u8 = 0x20;
+const CONTEXT_SPECIFIC: u8 = 0x80;
+
+const MAX_SESSION_ID_LENGTH: usize = 32;
+
+pub const SSL_SESS_FLAG_EXTMS: u64 = 0x1;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_openssl_session() {
+        // a TLS1.2 server session from OpenSSL 3.5, with ALPN and session id context
+        let der = hex(
+            "308184020101020203030402c02c0420edba31b6b02b91a58b77221ecba27784\
+                       0ed0881ee759db05b91a56689c0da1fb0430e27184be149f574c11fe60662fd7\
+                       4266baaee00e4af51685c9fa232d1a88224c2c268dd6de2a4671355b8f50d893\
+                       4ed8a10602046ad2e635a20402021c20a4050403637478ad03020101b0040402\
+                       6832b30302011d",
+        );
+        let (sess, rest) = SessionAsn1::decode(&der).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(sess.ssl_version, 0x0303);
+        assert_eq!(sess.cipher, 0xc02c);
+        assert_eq!(sess.session_id.len(), 32);
+        assert_eq!(sess.master_key.len(), 48);
+        assert_eq!(sess.time, 0x6ad2e635);
+        assert_eq!(sess.timeout, 7200);
+        assert_eq!(sess.session_id_context, b"ctx");
+        assert_eq!(sess.flags, SSL_SESS_FLAG_EXTMS);
+        assert_eq!(sess.alpn_selected.as_deref(), Some(&b"h2"[..]));
+        assert_eq!(sess.peer, None);
+
+        // everything but the trailing key exchange group is reproduced
+        assert_eq!(sess.encode(), [&[0x30, 0x7f], &der[3..130]].concat());
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+}
@@ -1,12 +1,13 @@
+use std::collections::BTreeMap;
 use std::ptr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use openssl_sys::{EVP_PKEY, X509};
 use rustls::client::ResolvesClientCert;
 use rustls::pki_types::{CertificateDer, SubjectPublicKeyInfoDer};
 use rustls::server::{ClientHello, ResolvesServerCert};
 use rustls::sign;
-use rustls::{SignatureAlgorithm, SignatureScheme};
+use rustls::{CipherSuite, SignatureAlgorithm, SignatureScheme, SupportedCipherSuite};
 
 use crate::error;
 use crate::evp_pkey::{
@@ -14,6 +15,7 @@ use crate::evp_pkey::{
     rsa_pkcs1_sha512, rsa_pss_sha256, rsa_pss_sha384, rsa_pss_sha512, EvpPkey, EvpScheme,
 };
 use crate::x509::OwnedX509Stack;
+use crate::SslCipher;
 
 /// This matches up to the implied state machine in `SSL_CTX_use_certificate_chain_file`
 /// and `SSL_CTX_use_PrivateKey_file`, and matching man pages.
@@ -27,10 +29,14 @@ pub struct CertifiedKeySet {
     /// May be absent.
     pending_cert_end_entity: Option<CertificateDer<'static>>,
 
-    /// The key and certificate we're currently using.
+    /// Our keys and certificates, at most one for each key type.
     ///
-    /// TODO: support multiple key types, and demultiplex them by type.
-    current_key: Option<OpenSslCertifiedKey>,
+    /// These are keyed by [`key_slot()`].
+    keys: BTreeMap<usize, OpenSslCertifiedKey>,
+
+    /// The slot of the "current" key: the one last added, or chosen
+    /// by `SSL_CTX_set_current_cert`, or used in the last handshake.
+    current: Option<usize>,
 }
 
 impl CertifiedKeySet {
@@ -58,35 +64,193 @@ impl CertifiedKeySet {
             }
         };
 
-        self.current_key = Some(OpenSslCertifiedKey::new(chain, key)?);
+        let slot = key_slot(key.algorithm());
+        self.keys
+            .insert(slot, OpenSslCertifiedKey::new(chain, key)?);
+        self.current = Some(slot);
         Ok(())
     }
 
-    pub fn client_resolver(&self) -> Option<Arc<dyn ResolvesClientCert>> {
-        self.current_key.as_ref().map(|ck| ck.client_resolver())
+    /// For `SSL_CTX_use_cert_and_key`: use `key` with `end_entity` and `chain`.
+    ///
+    /// Unless `replace`, this fails if we already have a key of the same type.
+    /// Without a `key`, the certificates await one, as for `SSL_CTX_use_certificate`.
+    pub fn use_cert_and_key(
+        &mut self,
+        end_entity: CertificateDer<'static>,
+        chain: Vec<CertificateDer<'static>>,
+        key: Option<EvpPkey>,
+        replace: bool,
+    ) -> Result<(), error::Error> {
+        let Some(key) = key else {
+            self.stage_certificate_end_entity(end_entity);
+            self.stage_certificate_chain(chain);
+            return Ok(());
+        };
+
+        if !replace && self.keys.contains_key(&key_slot(key.algorithm())) {
+            return Err(error::Error::bad_data("not replacing certificate"));
+        }
+
+        self.stage_certificate_end_entity(end_entity);
+        self.stage_certificate_chain(chain);
+        self.commit_private_key(key)
+    }
+
+    /// For `SSL_CTX_set_current_cert`: make the first (or next) key current.
+    ///
+    /// Returns false if there is no such key.
+    pub fn set_current(&mut self, next: bool) -> bool {
+        let from = match (next, self.current) {
+            (false, _) => 0,
+            (true, Some(current)) => current + 1,
+            (true, None) => return false,
+        };
+
+        match self.keys.range(from..).next() {
+            Some((slot, _)) => {
+                self.current = Some(*slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// For `SSL_CTX_select_current_cert`: make the key for `cert` current.
+    ///
+    /// Returns false if `cert` is not one of ours.
+    pub fn select_current(&mut self, cert: *mut X509) -> bool {
+        match self.keys.iter().find(|(_, ck)| ck.borrow_cert() == cert) {
+            Some((slot, _)) => {
+                self.current = Some(*slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Like OpenSSL, the key used in a handshake becomes current.
+    ///
+    /// `scheme` is the one we signed with.
+    pub fn set_current_used(&mut self, scheme: SignatureScheme) {
+        if let Some(slot) = scheme_algorithm(scheme).map(key_slot) {
+            if self.keys.contains_key(&slot) {
+                self.current = Some(slot);
+            }
+        }
+    }
+
+    fn current_key(&self) -> Option<&OpenSslCertifiedKey> {
+        self.current.and_then(|slot| self.keys.get(&slot))
     }
 
-    pub fn server_resolver(&self) -> Option<Arc<dyn ResolvesServerCert>> {
-        self.current_key.as_ref().map(|ck| ck.server_resolver())
+    /// Make a client cert resolver, signing only with `sigalgs` if given.
+    ///
+    /// The resolver is returned even if we have no key, so that the schemes
+    /// in the server's `CertificateRequest` are always recorded in `log`.
+    pub fn client_resolver(
+        &self,
+        sigalgs: Option<&[SignatureScheme]>,
+        log: Arc<SigSchemeLog>,
+    ) -> Arc<dyn ResolvesClientCert> {
+        Arc::new(ClientCertResolver {
+            key: self
+                .current_key()
+                .map(|ck| ck.certified_key(sigalgs, log.clone())),
+            log,
+        })
+    }
+
+    /// Make a server cert resolver, signing only with `sigalgs` if given.
+    ///
+    /// This chooses between our keys for each `ClientHello`; `tls13` says
+    /// whether TLS1.3 will be negotiated, and otherwise `tls12_ciphers` are
+    /// those we have enabled.
+    pub fn server_resolver(
+        &self,
+        sigalgs: Option<&[SignatureScheme]>,
+        log: Arc<SigSchemeLog>,
+        tls13: bool,
+        tls12_ciphers: &[&'static SslCipher],
+    ) -> Option<Arc<dyn ResolvesServerCert>> {
+        let current = self.current_key()?.certified_key(sigalgs, log.clone());
+        let others = self
+            .keys
+            .iter()
+            .filter(|(slot, _)| Some(**slot) != self.current)
+            .map(|(_, ck)| ck.certified_key(sigalgs, log.clone()));
+
+        Some(Arc::new(ServerCertResolver {
+            keys: [current].into_iter().chain(others).collect(),
+            allowed_schemes: sigalgs.map(|schemes| schemes.to_vec()),
+            tls13,
+            tls12_suites: tls12_ciphers.iter().map(|cipher| *cipher.rustls).collect(),
+        }))
+    }
+
+    /// The certificate chain a client would present.
+    pub fn current_chain(&self) -> Option<&[CertificateDer<'static>]> {
+        self.current_key().map(|ck| ck.rustls_chain.as_slice())
     }
 
     /// For `SSL_get_certificate`
     pub fn borrow_current_cert(&self) -> *mut X509 {
-        self.current_key
-            .as_ref()
+        self.current_key()
             .map(|ck| ck.borrow_cert())
             .unwrap_or(ptr::null_mut())
     }
 
     /// For `SSL_get_privatekey`
     pub fn borrow_current_key(&self) -> *mut EVP_PKEY {
-        self.current_key
-            .as_ref()
+        self.current_key()
             .map(|ck| ck.borrow_key())
             .unwrap_or(ptr::null_mut())
     }
 }
 
+/// OpenSSL's position for keys of each type (see `SSL_PKEY_*`).
+///
+/// `SSL_CTX_set_current_cert` iterates over keys in this order.
+fn key_slot(algorithm: SignatureAlgorithm) -> usize {
+    match algorithm {
+        SignatureAlgorithm::RSA => 0,
+        SignatureAlgorithm::ECDSA => 2,
+        SignatureAlgorithm::ED25519 => 6,
+        SignatureAlgorithm::ED448 => 7,
+        _ => 8,
+    }
+}
+
+/// The key type needed to sign using `scheme`, if we support it.
+fn scheme_algorithm(scheme: SignatureScheme) -> Option<SignatureAlgorithm> {
+    match scheme {
+        SignatureScheme::RSA_PKCS1_SHA256
+        | SignatureScheme::RSA_PKCS1_SHA384
+        | SignatureScheme::RSA_PKCS1_SHA512
+        | SignatureScheme::RSA_PSS_SHA256
+        | SignatureScheme::RSA_PSS_SHA384
+        | SignatureScheme::RSA_PSS_SHA512 => Some(SignatureAlgorithm::RSA),
+        SignatureScheme::ECDSA_NISTP256_SHA256
+        | SignatureScheme::ECDSA_NISTP384_SHA384
+        | SignatureScheme::ECDSA_NISTP521_SHA512 => Some(SignatureAlgorithm::ECDSA),
+        SignatureScheme::ED25519 => Some(SignatureAlgorithm::ED25519),
+        _ => None,
+    }
+}
+
+/// Whether any of the `enabled` TLS1.2 cipher suites that were also `offered`
+/// can be used with a key of type `algorithm`.
+fn usable_with_tls12_suites(
+    offered: &[CipherSuite],
+    enabled: &[SupportedCipherSuite],
+    algorithm: SignatureAlgorithm,
+) -> bool {
+    enabled
+        .iter()
+        .filter(|suite| offered.contains(&suite.suite()))
+        .any(|suite| suite.usable_for_signature_algorithm(algorithm))
+}
+
 #[derive(Clone, Debug)]
 pub(super) struct OpenSslCertifiedKey {
     key: EvpPkey,
@@ -109,7 +273,7 @@ impl OpenSslCertifiedKey {
     pub(super) fn keys_match(&self) -> bool {
         match sign::CertifiedKey::new(
             self.rustls_chain.clone(),
-            Arc::new(OpenSslKey(self.key.clone())),
+            Arc::new(OpenSslKey::new(self.key.clone(), None, Arc::default())),
         )
         .keys_match()
         {
@@ -131,71 +295,211 @@ impl OpenSslCertifiedKey {
         self.key.borrow_ref()
     }
 
-    fn client_resolver(&self) -> Arc<dyn ResolvesClientCert> {
-        Arc::new(AlwaysResolvesClientCert(Arc::new(sign::CertifiedKey::new(
+    fn certified_key(
+        &self,
+        sigalgs: Option<&[SignatureScheme]>,
+        log: Arc<SigSchemeLog>,
+    ) -> Arc<sign::CertifiedKey> {
+        Arc::new(sign::CertifiedKey::new(
             self.rustls_chain.clone(),
-            Arc::new(OpenSslKey(self.key.clone())),
-        ))))
+            Arc::new(OpenSslKey::new(self.key.clone(), sigalgs, log)),
+        ))
     }
+}
 
-    fn server_resolver(&self) -> Arc<dyn ResolvesServerCert> {
-        Arc::new(AlwaysResolvesServerCert(Arc::new(sign::CertifiedKey::new(
-            self.rustls_chain.clone(),
-            Arc::new(OpenSslKey(self.key.clone())),
-        ))))
+/// Signature schemes seen during a handshake, for `SSL_get_sigalgs` and
+/// `SSL_get_signature_type_nid`.
+#[derive(Debug, Default)]
+pub struct SigSchemeLog {
+    /// Schemes the peer will accept our signature in.
+    offered: RwLock<Vec<SignatureScheme>>,
+
+    /// The scheme we last chose to sign with.
+    chosen: RwLock<Option<SignatureScheme>>,
+
+    /// Where to record instead; see `ClientSessionOrigin::record_into`.
+    redirect: RwLock<Option<Arc<SigSchemeLog>>>,
+}
+
+impl SigSchemeLog {
+    /// Make a log where the peer's schemes are already known (eg, from
+    /// a `ClientHello`).
+    pub fn with_offered(offered: Vec<SignatureScheme>) -> Self {
+        Self {
+            offered: RwLock::new(offered),
+            chosen: RwLock::new(None),
+            redirect: RwLock::new(None),
+        }
+    }
+
+    pub fn offered(&self) -> Vec<SignatureScheme> {
+        self.offered
+            .read()
+            .map(|offered| offered.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn chosen(&self) -> Option<SignatureScheme> {
+        self.chosen.read().ok().map(|scheme| *scheme)?
+    }
+
+    /// Record into `log` from now on.
+    pub fn redirect_to(&self, log: Arc<SigSchemeLog>) {
+        if let Ok(mut redirect) = self.redirect.write() {
+            *redirect = Some(log);
+        }
+    }
+
+    fn redirected(&self) -> Option<Arc<SigSchemeLog>> {
+        self.redirect.read().ok()?.clone()
+    }
+
+    fn set_offered(&self, schemes: &[SignatureScheme]) {
+        if let Some(log) = self.redirected() {
+            return log.set_offered(schemes);
+        }
+
+        if let Ok(mut offered) = self.offered.write() {
+            *offered = schemes.to_vec();
+        }
+    }
+
+    fn set_chosen(&self, scheme: SignatureScheme) {
+        if let Some(log) = self.redirected() {
+            return log.set_chosen(scheme);
+        }
+
+        if let Ok(mut chosen) = self.chosen.write() {
+            *chosen = Some(scheme);
+        }
     }
 }
 
 #[derive(Debug)]
-struct AlwaysResolvesClientCert(Arc<sign::CertifiedKey>);
+struct ClientCertResolver {
+    key: Option<Arc<sign::CertifiedKey>>,
+    log: Arc<SigSchemeLog>,
+}
 
-impl ResolvesClientCert for AlwaysResolvesClientCert {
+impl ResolvesClientCert for ClientCertResolver {
     fn has_certs(&self) -> bool {
-        true
+        self.key.is_some()
     }
 
     fn resolve(
         &self,
         _root_hint_subjects: &[&[u8]],
-        _schemes: &[SignatureScheme],
+        schemes: &[SignatureScheme],
     ) -> Option<Arc<sign::CertifiedKey>> {
-        Some(Arc::clone(&self.0))
+        self.log.set_offered(schemes);
+        self.key.clone()
     }
 }
 
 #[derive(Debug)]
-struct AlwaysResolvesServerCert(Arc<sign::CertifiedKey>);
+struct ServerCertResolver {
+    /// Our keys, with the current one first.
+    keys: Vec<Arc<sign::CertifiedKey>>,
 
-impl ResolvesServerCert for AlwaysResolvesServerCert {
-    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
-        Some(Arc::clone(&self.0))
+    /// Schemes we may sign with, from `SSL_CTX_set1_sigalgs_list` etc.
+    ///
+    /// `None` means no restriction.
+    allowed_schemes: Option<Vec<SignatureScheme>>,
+
+    /// Whether TLS1.3 will be negotiated.
+    ///
+    /// Otherwise, TLS1.2 cipher suites constrain the key type.
+    tls13: bool,
+
+    /// Our enabled TLS1.2 cipher suites.
+    tls12_suites: Vec<SupportedCipherSuite>,
+}
+
+impl ResolvesServerCert for ServerCertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        // Like OpenSSL's `tls_choose_sigalg`, take the client's most-preferred
+        // signature scheme that we have a key for.  Failing that, use the current
+        // key and let the handshake fail in the normal way.
+        client_hello
+            .signature_schemes()
+            .iter()
+            .filter(|scheme| match &self.allowed_schemes {
+                Some(allowed) => allowed.contains(scheme),
+                None => true,
+            })
+            .filter_map(|scheme| scheme_algorithm(*scheme))
+            .filter(|algorithm| {
+                self.tls13
+                    || usable_with_tls12_suites(
+                        client_hello.cipher_suites(),
+                        &self.tls12_suites,
+                        *algorithm,
+                    )
+            })
+            .find_map(|algorithm| {
+                self.keys
+                    .iter()
+                    .find(|key| key.key.algorithm() == algorithm)
+            })
+            .or(self.keys.first())
+            .cloned()
     }
 }
 
 #[derive(Debug)]
-struct OpenSslKey(EvpPkey);
+struct OpenSslKey {
+    key: EvpPkey,
 
-impl sign::SigningKey for OpenSslKey {
-    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
-        match self.0.algorithm() {
+    /// Schemes we may sign with, from `SSL_CTX_set1_sigalgs_list` etc.
+    ///
+    /// `None` means no restriction.
+    allowed_schemes: Option<Vec<SignatureScheme>>,
+
+    log: Arc<SigSchemeLog>,
+}
+
+impl OpenSslKey {
+    fn new(
+        key: EvpPkey,
+        allowed_schemes: Option<&[SignatureScheme]>,
+        log: Arc<SigSchemeLog>,
+    ) -> Self {
+        Self {
+            key,
+            allowed_schemes: allowed_schemes.map(|schemes| schemes.to_vec()),
+            log,
+        }
+    }
+
+    fn choose_signer(&self, offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        let offered = match &self.allowed_schemes {
+            Some(allowed) => offered
+                .iter()
+                .filter(|scheme| allowed.contains(scheme))
+                .copied()
+                .collect(),
+            None => offered.to_vec(),
+        };
+
+        match self.key.algorithm() {
             SignatureAlgorithm::RSA => {
                 if offered.contains(&SignatureScheme::RSA_PSS_SHA512) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: rsa_pss_sha512(),
                         scheme: SignatureScheme::RSA_PSS_SHA512,
                     }));
                 }
                 if offered.contains(&SignatureScheme::RSA_PSS_SHA384) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: rsa_pss_sha384(),
                         scheme: SignatureScheme::RSA_PSS_SHA384,
                     }));
                 }
                 if offered.contains(&SignatureScheme::RSA_PSS_SHA256) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: rsa_pss_sha256(),
                         scheme: SignatureScheme::RSA_PSS_SHA256,
                     }));
@@ -203,21 +507,21 @@ impl sign::SigningKey for OpenSslKey {
 
                 if offered.contains(&SignatureScheme::RSA_PKCS1_SHA512) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: rsa_pkcs1_sha512(),
                         scheme: SignatureScheme::RSA_PKCS1_SHA512,
                     }));
                 }
                 if offered.contains(&SignatureScheme::RSA_PKCS1_SHA384) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: rsa_pkcs1_sha384(),
                         scheme: SignatureScheme::RSA_PKCS1_SHA384,
                     }));
                 }
                 if offered.contains(&SignatureScheme::RSA_PKCS1_SHA256) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: rsa_pkcs1_sha256(),
                         scheme: SignatureScheme::RSA_PKCS1_SHA256,
                     }));
@@ -228,7 +532,7 @@ impl sign::SigningKey for OpenSslKey {
             SignatureAlgorithm::ED25519 => {
                 if offered.contains(&SignatureScheme::ED25519) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: ed25519(),
                         scheme: SignatureScheme::ED25519,
                     }));
@@ -239,21 +543,21 @@ impl sign::SigningKey for OpenSslKey {
             SignatureAlgorithm::ECDSA => {
                 if offered.contains(&SignatureScheme::ECDSA_NISTP256_SHA256) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: ecdsa_sha256(),
                         scheme: SignatureScheme::ECDSA_NISTP256_SHA256,
                     }));
                 }
                 if offered.contains(&SignatureScheme::ECDSA_NISTP384_SHA384) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: ecdsa_sha384(),
                         scheme: SignatureScheme::ECDSA_NISTP384_SHA384,
                     }));
                 }
                 if offered.contains(&SignatureScheme::ECDSA_NISTP521_SHA512) {
                     return Some(Box::new(OpenSslSigner {
-                        pkey: self.0.clone(),
+                        pkey: self.key.clone(),
                         pscheme: ecdsa_sha512(),
                         scheme: SignatureScheme::ECDSA_NISTP521_SHA512,
                     }));
@@ -264,15 +568,23 @@ impl sign::SigningKey for OpenSslKey {
             _ => None,
         }
     }
+}
+
+impl sign::SigningKey for OpenSslKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        let signer = self.choose_signer(offered)?;
+        self.log.set_chosen(signer.scheme());
+        Some(signer)
+    }
 
     fn public_key(&self) -> Option<SubjectPublicKeyInfoDer<'_>> {
         Some(SubjectPublicKeyInfoDer::from(
-            self.0.subject_public_key_info(),
+            self.key.subject_public_key_info(),
         ))
     }
 
     fn algorithm(&self) -> SignatureAlgorithm {
-        self.0.algorithm()
+        self.key.algorithm()
     }
 }
 
@@ -1,9 +1,10 @@
+use core::ffi::c_int;
 use core::sync::atomic::{AtomicI64, Ordering};
 use std::sync::{Arc, RwLock};
 
 use openssl_sys::{
-    X509_V_ERR_CERT_HAS_EXPIRED, X509_V_ERR_CERT_NOT_YET_VALID, X509_V_ERR_CERT_REVOKED,
-    X509_V_ERR_HOSTNAME_MISMATCH, X509_V_ERR_INVALID_PURPOSE,
+    X509_V_ERR_CERT_CHAIN_TOO_LONG, X509_V_ERR_CERT_HAS_EXPIRED, X509_V_ERR_CERT_NOT_YET_VALID,
+    X509_V_ERR_CERT_REVOKED, X509_V_ERR_HOSTNAME_MISMATCH, X509_V_ERR_INVALID_PURPOSE,
     X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY, X509_V_ERR_UNSPECIFIED, X509_V_OK,
 };
 
@@ -16,12 +17,155 @@ use rustls::{
     pki_types::{CertificateDer, ServerName, UnixTime},
     server::danger::{ClientCertVerified, ClientCertVerifier},
     server::{ParsedCertificate, WebPkiClientVerifier},
-    CertificateError, DigitallySignedStruct, DistinguishedName, Error, RootCertStore,
-    SignatureScheme,
+    CertificateError, DigitallySignedStruct, DistinguishedName, Error, PeerMisbehaved,
+    RootCertStore, SignatureScheme,
 };
 
+use crate::callbacks::{invoke_verify_callback, CertVerifyCallbackConfig};
+use crate::entry::SSL_verify_cb;
+use crate::x509::{IssuancePath, OwnedX509Store};
 use crate::VerifyMode;
 
+/// The application's say in verification, from `SSL_CTX_set_verify` and
+/// `SSL_CTX_set_cert_verify_callback`.
+#[derive(Clone, Debug)]
+pub struct VerifyCallbacks {
+    pub verify_callback: SSL_verify_cb,
+
+    pub cert_verify_callback: CertVerifyCallbackConfig,
+
+    /// Trust anchors offered to callbacks that call `X509_verify_cert`.
+    pub x509_store: OwnedX509Store,
+
+    /// Maximum number of CA certificates below the trust anchor, from
+    /// `SSL_CTX_set_verify_depth`.  Negative means no limit.
+    pub verify_depth: c_int,
+}
+
+impl VerifyCallbacks {
+    /// Verify `chain` with `verify`, unless a cert verify callback replaces
+    /// that, and give any verify callback its say.
+    ///
+    /// Returns our verification result, the `X509_V_*` result, and whether
+    /// the chain was accepted.
+    fn verify(
+        &self,
+        chain: &[CertificateDer<'_>],
+        verify: impl FnOnce() -> Result<(), Error>,
+    ) -> (Result<(), Error>, c_int, bool) {
+        match self
+            .cert_verify_callback
+            .invoke(chain, &self.x509_store, self.verify_callback)
+        {
+            // the application entirely replaced our verification
+            Some((openssl_rv, accepted)) => (Ok(()), openssl_rv, accepted),
+            None => {
+                let mut result = verify();
+                let path = IssuancePath::new(chain);
+
+                let too_long = usize::try_from(self.verify_depth)
+                    .ok()
+                    .and_then(|max_depth| path.beyond_depth(max_depth));
+
+                let (openssl_rv, error_depth) = match too_long {
+                    // like OpenSSL, this is found (while building the chain)
+                    // before any other problem.
+                    Some(error_depth) => {
+                        result = result.and(Err(Error::InvalidCertificate(
+                            CertificateError::ApplicationVerificationFailure,
+                        )));
+                        (X509_V_ERR_CERT_CHAIN_TOO_LONG, error_depth)
+                    }
+                    None => {
+                        let openssl_rv = translate_verify_result(&result);
+                        (openssl_rv, error_depth(&path, openssl_rv))
+                    }
+                };
+
+                let (openssl_rv, accepted) = invoke_verify_callback(
+                    self.verify_callback,
+                    chain,
+                    &self.x509_store,
+                    openssl_rv,
+                    error_depth,
+                );
+                (result, openssl_rv, accepted)
+            }
+        }
+    }
+
+    fn same(&self, other: &Self) -> bool {
+        self.verify_callback.map(|cb| cb as usize) == other.verify_callback.map(|cb| cb as usize)
+            && self.cert_verify_callback.cb.map(|cb| cb as usize)
+                == other.cert_verify_callback.cb.map(|cb| cb as usize)
+            && self.cert_verify_callback.context == other.cert_verify_callback.context
+            && self.x509_store.pointer() == other.x509_store.pointer()
+            && self.verify_depth == other.verify_depth
+    }
+}
+
+/// What verification of a peer found, for `SSL_get_verify_result` and
+/// `SSL_get_peer_signature_type_nid`.
+#[derive(Debug)]
+pub struct VerifyLog {
+    result: AtomicI64,
+    sig_scheme: RwLock<Option<SignatureScheme>>,
+    /// Where to record instead; see `ClientSessionOrigin::record_into`.
+    redirect: RwLock<Option<Arc<VerifyLog>>>,
+}
+
+impl VerifyLog {
+    pub fn new(initial_result: c_int) -> Self {
+        Self {
+            result: AtomicI64::new(initial_result as i64),
+            sig_scheme: RwLock::new(None),
+            redirect: RwLock::new(None),
+        }
+    }
+
+    pub fn result(&self) -> i64 {
+        self.result.load(Ordering::Acquire)
+    }
+
+    pub fn sig_scheme(&self) -> Option<SignatureScheme> {
+        self.sig_scheme.read().ok().map(|scheme| *scheme)?
+    }
+
+    /// Record into `log` from now on.
+    pub fn redirect_to(&self, log: Arc<VerifyLog>) {
+        if let Ok(mut redirect) = self.redirect.write() {
+            *redirect = Some(log);
+        }
+    }
+
+    fn redirected(&self) -> Option<Arc<VerifyLog>> {
+        self.redirect.read().ok()?.clone()
+    }
+
+    fn set_result(&self, result: c_int) {
+        match self.redirected() {
+            Some(log) => log.set_result(result),
+            None => self.result.store(result as i64, Ordering::Release),
+        }
+    }
+
+    fn set_sig_scheme(&self, scheme: SignatureScheme) {
+        if let Some(log) = self.redirected() {
+            return log.set_sig_scheme(scheme);
+        }
+
+        if let Ok(mut sig_scheme) = self.sig_scheme.write() {
+            *sig_scheme = Some(scheme);
+        }
+    }
+}
+
+impl Default for VerifyLog {
+    fn default() -> Self {
+        Self::new(X509_V_ERR_UNSPECIFIED)
+    }
+}
+
 /// This is a verifier that implements the selection of bad ideas from OpenSSL:
 ///
 /// - that the SNI name and verified certificate server name are unrelated
@@ -40,9 +184,14 @@ pub struct ServerVerifier {
 
     mode: VerifyMode,
 
-    last_result: AtomicI64,
+    callbacks: VerifyCallbacks,
+
+    /// Signature schemes we accept, from `SSL_CTX_set1_sigalgs_list` etc.
+    ///
+    /// `None` means all those supported by `provider`.
+    sigalgs: Option<Vec<SignatureScheme>>,
 
-    last_sig_scheme: RwLock<Option<SignatureScheme>>,
+    log: Arc<VerifyLog>,
 }
 
 impl ServerVerifier {
@@ -50,6 +199,8 @@ impl ServerVerifier {
         root_store: Arc<RootCertStore>,
         provider: Arc<CryptoProvider>,
         mode: VerifyMode,
+        callbacks: VerifyCallbacks,
+        sigalgs: Option<Vec<SignatureScheme>>,
         hostname: &Option<ServerName<'static>>,
     ) -> Self {
         Self {
@@ -57,17 +208,23 @@ impl ServerVerifier {
             provider,
             verify_hostname: hostname.clone(),
             mode,
-            last_result: AtomicI64::new(X509_V_ERR_UNSPECIFIED as i64),
-            last_sig_scheme: RwLock::new(None),
+            callbacks,
+            sigalgs,
+            log: Arc::default(),
         }
     }
 
-    pub fn last_result(&self) -> i64 {
-        self.last_result.load(Ordering::Acquire)
+    pub fn log(&self) -> &Arc<VerifyLog> {
+        &self.log
     }
 
-    pub fn last_sig_scheme(&self) -> Option<SignatureScheme> {
-        self.last_sig_scheme.read().ok().map(|scheme| *scheme)?
+    /// Whether `other` would reach the same verification decisions as `self`.
+    pub fn same_policy(&self, other: &Self) -> bool {
+        self.root_store.roots == other.root_store.roots
+            && self.verify_hostname == other.verify_hostname
+            && self.mode == other.mode
+            && self.callbacks.same(&other.callbacks)
+            && self.sigalgs == other.sigalgs
     }
 
     fn verify_server_cert_inner(
@@ -92,12 +249,6 @@ impl ServerVerifier {
 
         Ok(())
     }
-
-    fn update_sig_scheme(&self, scheme: SignatureScheme) {
-        if let Ok(mut last_scheme) = self.last_sig_scheme.write() {
-            *last_scheme = Some(scheme);
-        }
-    }
 }
 
 impl ServerCertVerifier for ServerVerifier {
@@ -109,16 +260,20 @@ impl ServerCertVerifier for ServerVerifier {
         _ocsp_response: &[u8],
         now: UnixTime,
     ) -> Result<ServerCertVerified, Error> {
-        let result = self.verify_server_cert_inner(end_entity, intermediates, now);
+        let chain = peer_chain(end_entity, intermediates);
 
-        let openssl_rv = translate_verify_result(&result);
-        self.last_result.store(openssl_rv as i64, Ordering::Release);
+        let (result, openssl_rv, accepted) = self.callbacks.verify(&chain, || {
+            self.verify_server_cert_inner(end_entity, intermediates, now)
+        });
+        self.log.set_result(openssl_rv);
 
-        // Call it success if it succeeded, or the `mode` says not to care.
-        if openssl_rv == X509_V_OK || !self.mode.client_must_verify_server() {
+        // Call it success if it was accepted, or the `mode` says not to care.
+        if accepted || !self.mode.client_must_verify_server() {
             Ok(ServerCertVerified::assertion())
         } else {
-            Err(result.unwrap_err())
+            Err(result.err().unwrap_or(Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )))
         }
     }
 
@@ -128,7 +283,8 @@ impl ServerCertVerifier for ServerVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        self.update_sig_scheme(dss.scheme);
+        self.log.set_sig_scheme(dss.scheme);
+        check_scheme_allowed(dss.scheme, &self.sigalgs)?;
         verify_tls12_signature(
             message,
             cert,
@@ -143,7 +299,8 @@ impl ServerCertVerifier for ServerVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        self.update_sig_scheme(dss.scheme);
+        self.log.set_sig_scheme(dss.scheme);
+        check_scheme_allowed(dss.scheme, &self.sigalgs)?;
         verify_tls13_signature(
             message,
             cert,
@@ -153,9 +310,12 @@ impl ServerCertVerifier for ServerVerifier {
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        self.provider
-            .signature_verification_algorithms
-            .supported_schemes()
+        allowed_schemes(
+            self.provider
+                .signature_verification_algorithms
+                .supported_schemes(),
+            &self.sigalgs,
+        )
     }
 }
 
@@ -163,8 +323,9 @@ impl ServerCertVerifier for ServerVerifier {
 pub struct ClientVerifier {
     parent: Arc<dyn ClientCertVerifier>,
     mode: VerifyMode,
-    last_result: AtomicI64,
-    last_sig_scheme: RwLock<Option<SignatureScheme>>,
+    callbacks: VerifyCallbacks,
+    sigalgs: Option<Vec<SignatureScheme>>,
+    log: Arc<VerifyLog>,
 }
 
 impl ClientVerifier {
@@ -172,6 +333,8 @@ impl ClientVerifier {
         root_store: Arc<RootCertStore>,
         provider: Arc<CryptoProvider>,
         mode: VerifyMode,
+        callbacks: VerifyCallbacks,
+        sigalgs: Option<Vec<SignatureScheme>>,
     ) -> Result<Self, Error> {
         let (parent, initial_result) = if !mode.server_must_attempt_client_auth() {
             (Ok(WebPkiClientVerifier::no_client_auth()), X509_V_OK)
@@ -193,23 +356,14 @@ impl ClientVerifier {
         Ok(Self {
             parent,
             mode,
-            last_result: AtomicI64::new(initial_result as i64),
-            last_sig_scheme: RwLock::new(None),
+            callbacks,
+            sigalgs,
+            log: Arc::new(VerifyLog::new(initial_result)),
         })
     }
 
-    pub fn last_result(&self) -> i64 {
-        self.last_result.load(Ordering::Acquire)
-    }
-
-    pub fn last_sig_scheme(&self) -> Option<SignatureScheme> {
-        self.last_sig_scheme.read().ok().map(|scheme| *scheme)?
-    }
-
-    fn update_sig_scheme(&self, scheme: SignatureScheme) {
-        if let Ok(mut last_scheme) = self.last_sig_scheme.write() {
-            *last_scheme = Some(scheme);
-        }
+    pub fn log(&self) -> &Arc<VerifyLog> {
+        &self.log
     }
 }
 
@@ -228,19 +382,22 @@ impl ClientCertVerifier for ClientVerifier {
         intermediates: &[CertificateDer<'_>],
         now: UnixTime,
     ) -> Result<ClientCertVerified, Error> {
-        let result = self
-            .parent
-            .verify_client_cert(end_entity, intermediates, now)
-            .map(|_| ());
+        let chain = peer_chain(end_entity, intermediates);
 
-        let openssl_rv = translate_verify_result(&result);
-        self.last_result.store(openssl_rv as i64, Ordering::Release);
+        let (result, openssl_rv, accepted) = self.callbacks.verify(&chain, || {
+            self.parent
+                .verify_client_cert(end_entity, intermediates, now)
+                .map(|_| ())
+        });
+        self.log.set_result(openssl_rv);
 
-        // Call it success if it succeeded, or the `mode` says not to care.
-        if openssl_rv == X509_V_OK || !self.mode.server_must_verify_client() {
+        // Call it success if it was accepted, or the `mode` says not to care.
+        if accepted || !self.mode.server_must_verify_client() {
             Ok(ClientCertVerified::assertion())
         } else {
-            Err(result.unwrap_err())
+            Err(result.err().unwrap_or(Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )))
         }
     }
 
@@ -250,7 +407,8 @@ impl ClientCertVerifier for ClientVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        self.update_sig_scheme(dss.scheme);
+        self.log.set_sig_scheme(dss.scheme);
+        check_scheme_allowed(dss.scheme, &self.sigalgs)?;
         self.parent.verify_tls12_signature(message, cert, dss)
     }
 
@@ -260,12 +418,13 @@ impl ClientCertVerifier for ClientVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        self.update_sig_scheme(dss.scheme);
+        self.log.set_sig_scheme(dss.scheme);
+        check_scheme_allowed(dss.scheme, &self.sigalgs)?;
         self.parent.verify_tls13_signature(message, cert, dss)
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        self.parent.supported_verify_schemes()
+        allowed_schemes(self.parent.supported_verify_schemes(), &self.sigalgs)
     }
 
     fn root_hint_subjects(&self) -> &[DistinguishedName] {
@@ -273,6 +432,55 @@ impl ClientCertVerifier for ClientVerifier {
     }
 }
 
+fn peer_chain<'a>(
+    end_entity: &CertificateDer<'a>,
+    intermediates: &[CertificateDer<'a>],
+) -> Vec<CertificateDer<'a>> {
+    let mut chain = Vec::with_capacity(1 + intermediates.len());
+    chain.push(end_entity.clone());
+    chain.extend_from_slice(intermediates);
+    chain
+}
+
+/// Restrict `supported` to those in `allowed`, in the order of `allowed`.
+fn allowed_schemes(
+    supported: Vec<SignatureScheme>,
+    allowed: &Option<Vec<SignatureScheme>>,
+) -> Vec<SignatureScheme> {
+    match allowed {
+        Some(allowed) => allowed
+            .iter()
+            .filter(|scheme| supported.contains(scheme))
+            .copied()
+            .collect(),
+        None => supported,
+    }
+}
+
+fn check_scheme_allowed(
+    scheme: SignatureScheme,
+    allowed: &Option<Vec<SignatureScheme>>,
+) -> Result<(), Error> {
+    match allowed {
+        Some(allowed) if !allowed.contains(&scheme) => Err(Error::PeerMisbehaved(
+            PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The index into the chain of the certificate that `openssl_rv` is about.
+fn error_depth(path: &IssuancePath, openssl_rv: c_int) -> usize {
+    let found = match openssl_rv {
+        X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY => Some(path.top()),
+        X509_V_ERR_CERT_HAS_EXPIRED => path.first_expired(),
+        X509_V_ERR_CERT_NOT_YET_VALID => path.first_not_yet_valid(),
+        // otherwise, the end-entity certificate is the best guess.
+        _ => None,
+    };
+    found.unwrap_or_default()
+}
+
 fn translate_verify_result(result: &Result<(), Error>) -> i32 {
     match result {
         Ok(()) => X509_V_OK,
@@ -1,15 +1,20 @@
-use core::ffi::{c_int, c_long, c_void};
-use core::{ptr, slice};
-use std::path::PathBuf;
+use core::ffi::{c_char, c_int, c_long, c_void};
+use core::{fmt, mem, ptr, slice};
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
 use openssl_sys::{
     d2i_X509, i2d_X509, stack_st_X509, OPENSSL_free, OPENSSL_sk_new_null, OPENSSL_sk_num,
-    OPENSSL_sk_push, OPENSSL_sk_value, X509_STORE_free, X509_STORE_new, X509_free, OPENSSL_STACK,
-    X509, X509_STORE,
+    OPENSSL_sk_push, OPENSSL_sk_value, X509_STORE_CTX_free, X509_STORE_CTX_get0_chain,
+    X509_STORE_CTX_get_error, X509_STORE_CTX_init, X509_STORE_CTX_new, X509_STORE_CTX_set_error,
+    X509_STORE_add_cert, X509_STORE_free, X509_STORE_new, X509_check_issued, X509_free,
+    X509_getm_notAfter, X509_getm_notBefore, ASN1_TIME, OPENSSL_STACK, X509, X509_STORE,
+    X509_STORE_CTX, X509_V_OK,
 };
 use rustls::pki_types::CertificateDer;
 
+use crate::entry::SSL_verify_cb;
 use crate::error::Error;
 
 /// Safe, owning wrapper around an OpenSSL `STACK_OF(X509)` object.
@@ -211,6 +216,11 @@ impl OwnedX509 {
         self.raw
     }
 
+    /// Whether `self` names `subject`'s issuer.
+    fn issued(&self, subject: &OwnedX509) -> bool {
+        unsafe { X509_check_issued(self.raw, subject.raw) == X509_V_OK }
+    }
+
     /// Give out a new reference.
     ///
     /// See `SSL_get1_peer_certificate`.
@@ -224,6 +234,99 @@ impl OwnedX509 {
     }
 }
 
+/// The issuance path of a certificate chain sent by a peer.
+///
+/// This starts with the end-entity certificate, and follows issuers among
+/// the rest of the chain (whatever order they were sent in) until reaching
+/// a self-issued certificate, or one whose issuer was not sent.
+pub struct IssuancePath {
+    certs: Vec<OwnedX509>,
+
+    /// Indices into `certs`, starting with 0.
+    path: Vec<usize>,
+}
+
+impl IssuancePath {
+    /// Follow the path through `chain`, which starts with the end-entity certificate.
+    ///
+    /// Certificates from the first that cannot be parsed onwards are ignored.
+    pub fn new(chain: &[CertificateDer<'_>]) -> Self {
+        let certs = chain
+            .iter()
+            .map_while(|cert| OwnedX509::parse_der(cert.as_ref()))
+            .collect::<Vec<_>>();
+
+        let mut path = Vec::with_capacity(certs.len());
+        if !certs.is_empty() {
+            path.push(0);
+        }
+
+        while let Some(&current) = path.last() {
+            if certs[current].issued(&certs[current]) {
+                break;
+            }
+
+            match (0..certs.len())
+                .find(|index| !path.contains(index) && certs[*index].issued(&certs[current]))
+            {
+                Some(issuer) => path.push(issuer),
+                None => break,
+            }
+        }
+
+        Self { certs, path }
+    }
+
+    /// The index into the chain of the last certificate on the path.
+    pub fn top(&self) -> usize {
+        self.path.last().copied().unwrap_or_default()
+    }
+
+    /// The index into the chain of the first certificate beyond `max_depth`
+    /// CA certificates, if the path is longer than `SSL_CTX_set_verify_depth`
+    /// allows.
+    ///
+    /// A self-issued certificate at the top of the path is taken to be the
+    /// trust anchor, which does not count.
+    pub fn beyond_depth(&self, max_depth: usize) -> Option<usize> {
+        let top = *self.path.last()?;
+        let anchored = self.path.len() > 1 && self.certs[top].issued(&self.certs[top]);
+        let intermediates = self.path.len() - 1 - anchored as usize;
+
+        match intermediates > max_depth {
+            true => Some(self.path[max_depth + 1]),
+            false => None,
+        }
+    }
+
+    /// The index into the chain of the first certificate on the path that has expired.
+    pub fn first_expired(&self) -> Option<usize> {
+        self.path.iter().copied().find(|&index| {
+            let not_after = unsafe { X509_getm_notAfter(self.certs[index].raw) };
+            unsafe { X509_cmp_current_time(not_after) < 0 }
+        })
+    }
+
+    /// The index into the chain of the first certificate on the path that is
+    /// not yet valid.
+    pub fn first_not_yet_valid(&self) -> Option<usize> {
+        self.path.iter().copied().find(|&index| {
+            let not_before = unsafe { X509_getm_notBefore(self.certs[index].raw) };
+            unsafe { X509_cmp_current_time(not_before) > 0 }
+        })
+    }
+}
+
+// X509 refcounting is atomic, and an `SslSession` only shares its peer
+// certificate for reading.
+unsafe impl Send for OwnedX509 {}
+
+impl Clone for OwnedX509 {
+    fn clone(&self) -> Self {
+        Self::new_incref(self.raw)
+    }
+}
+
 impl Drop for OwnedX509 {
     fn drop(&mut self) {
         unsafe {
@@ -245,8 +348,50 @@ impl OwnedX509Store {
     pub fn pointer(&self) -> *mut X509_STORE {
         self.raw
     }
+
+    /// Trust `cert`, for callers of `X509_verify_cert`.
+    pub fn add_cert(&self, cert: &CertificateDer<'_>) -> Result<(), Error> {
+        let cert = OwnedX509::parse_der(cert.as_ref())
+            .ok_or_else(|| Error::bad_data("cannot parse certificate"))?;
+        // nb. this takes its own reference, and ignores duplicates.
+        match unsafe { X509_STORE_add_cert(self.raw, cert.borrow_ref()) } {
+            1 => Ok(()),
+            _ => Err(Error::bad_data("X509_STORE_add_cert")),
+        }
+    }
+
+    /// Trust the certificates in `file` and the hashed directory `dir`.
+    pub fn load_locations(&self, file: Option<&Path>, dir: Option<&Path>) {
+        let to_cstring = |path: &Path| path.to_str().and_then(|path| CString::new(path).ok());
+        let (file, dir) = (file.and_then(to_cstring), dir.and_then(to_cstring));
+        if file.is_none() && dir.is_none() {
+            return;
+        }
+
+        let as_ptr = |path: &Option<CString>| path.as_ref().map_or(ptr::null(), |p| p.as_ptr());
+        if unsafe { X509_STORE_load_locations(self.raw, as_ptr(&file), as_ptr(&dir)) } != 1 {
+            log::trace!("X509_STORE_load_locations failed for {file:?} {dir:?}");
+        }
+    }
+}
+
+impl Clone for OwnedX509Store {
+    fn clone(&self) -> Self {
+        unsafe { X509_STORE_up_ref(self.raw) };
+        Self { raw: self.raw }
+    }
 }
 
+impl fmt::Debug for OwnedX509Store {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedX509Store").finish_non_exhaustive()
+    }
+}
+
+// `X509_STORE` is internally locked, and refcounting is atomic.
+unsafe impl Send for OwnedX509Store {}
+unsafe impl Sync for OwnedX509Store {}
+
 impl Default for OwnedX509Store {
     fn default() -> Self {
         Self {
@@ -263,6 +408,129 @@ impl Drop for OwnedX509Store {
     }
 }
 
+/// Safe, owning wrapper around an OpenSSL `X509_STORE_CTX` object.
+///
+/// This is only used to present a certificate chain to callbacks that
+/// expect one: no verification is done by OpenSSL using this object.
+pub struct OwnedX509StoreCtx {
+    raw: *mut X509_STORE_CTX,
+    // nb. `X509_STORE_CTX_init` does not take a reference on these,
+    // so we must keep them alive as long as `raw`.
+    store: OwnedX509Store,
+    leaf: OwnedX509,
+    untrusted: OwnedX509Stack,
+}
+
+impl OwnedX509StoreCtx {
+    /// Make one for the given `chain`, which starts with the end-entity certificate.
+    ///
+    /// As in OpenSSL, the whole chain (including the end-entity) is the
+    /// untrusted set, and `store` supplies the trust anchors if the
+    /// callback calls `X509_verify_cert`.
+    pub fn new(chain: &[CertificateDer<'_>], store: &OwnedX509Store) -> Result<Self, Error> {
+        let mut parsed = Vec::with_capacity(chain.len());
+        for cert in chain {
+            parsed.push(
+                OwnedX509::parse_der(cert.as_ref())
+                    .ok_or_else(|| Error::bad_data("cannot parse certificate"))?,
+            );
+        }
+
+        let leaf = match parsed.first() {
+            Some(leaf) => OwnedX509::new_incref(leaf.borrow_ref()),
+            None => return Err(Error::bad_data("empty certificate chain")),
+        };
+
+        let mut untrusted = OwnedX509Stack::empty();
+        for cert in &parsed {
+            untrusted.push(cert);
+        }
+
+        let raw = unsafe { X509_STORE_CTX_new() };
+        if raw.is_null() {
+            return Err(Error::bad_data("X509_STORE_CTX_new"));
+        }
+
+        let ret = Self {
+            raw,
+            store: store.clone(),
+            leaf,
+            untrusted,
+        };
+
+        let rc = unsafe {
+            X509_STORE_CTX_init(
+                ret.raw,
+                ret.store.pointer(),
+                ret.leaf.borrow_ref(),
+                ret.untrusted.pointer(),
+            )
+        };
+        if rc != 1 {
+            return Err(Error::bad_data("X509_STORE_CTX_init"));
+        }
+
+        Ok(ret)
+    }
+
+    /// Install the whole chain as the verified chain, so
+    /// `X509_STORE_CTX_get0_chain` works as it does during verification.
+    ///
+    /// `X509_verify_cert` refuses to run after this.
+    pub fn set_verified_chain(&mut self) {
+        let verified = OwnedX509Stack::new_copy(self.untrusted.pointer());
+        // `verified` is donated to `raw`.
+        unsafe { X509_STORE_CTX_set0_verified_chain(self.raw, verified.pointer()) };
+        mem::forget(verified);
+    }
+
+    pub fn pointer(&self) -> *mut X509_STORE_CTX {
+        self.raw
+    }
+
+    pub fn error(&self) -> c_int {
+        unsafe { X509_STORE_CTX_get_error(self.raw) }
+    }
+
+    pub fn set_error(&mut self, error: c_int) {
+        unsafe { X509_STORE_CTX_set_error(self.raw, error) };
+    }
+
+    /// Set the current depth and certificate.
+    ///
+    /// `depth` indexes the chain given to `new()`, which must have been
+    /// installed by `set_verified_chain()`.
+    pub fn set_current(&mut self, depth: usize) {
+        unsafe {
+            let chain = X509_STORE_CTX_get0_chain(self.raw);
+            let cert = OPENSSL_sk_value(chain as *const OPENSSL_STACK, depth as c_int);
+            X509_STORE_CTX_set_error_depth(self.raw, depth as c_int);
+            X509_STORE_CTX_set_current_cert(self.raw, cert as *mut X509);
+        }
+    }
+
+    pub fn set_ex_data(&mut self, idx: c_int, data: *mut c_void) {
+        unsafe { X509_STORE_CTX_set_ex_data(self.raw, idx, data) };
+    }
+
+    /// Set the callback used by `X509_verify_cert`, if it is called on this object.
+    ///
+    /// `None` keeps the default: `X509_verify_cert` does not expect a NULL callback.
+    pub fn set_verify_callback(&mut self, callback: SSL_verify_cb) {
+        if callback.is_some() {
+            unsafe { X509_STORE_CTX_set_verify_cb(self.raw, callback) };
+        }
+    }
+}
+
+impl Drop for OwnedX509StoreCtx {
+    fn drop(&mut self) {
+        unsafe {
+            X509_STORE_CTX_free(self.raw);
+        }
+    }
+}
+
 pub(crate) fn load_certs<'a>(
     file_names: impl Iterator<Item = PathBuf>,
 ) -> Result<Vec<CertificateDer<'a>>, Error> {
@@ -294,4 +562,17 @@ extern "C" {
     );
     fn OPENSSL_sk_dup(st: *const OPENSSL_STACK) -> *mut OPENSSL_STACK;
     fn X509_up_ref(x: *mut X509) -> c_int;
+    fn X509_cmp_current_time(s: *const ASN1_TIME) -> c_int;
+    fn X509_STORE_up_ref(store: *mut X509_STORE) -> c_int;
+    fn X509_STORE_load_locations(
+        store: *mut X509_STORE,
+        file: *const c_char,
+        dir: *const c_char,
+    ) -> c_int;
+    fn X509_STORE_CTX_set0_verified_chain(ctx: *mut X509_STORE_CTX, sk: *mut stack_st_X509);
+    fn X509_STORE_CTX_set_error_depth(ctx: *mut X509_STORE_CTX, depth: c_int);
+    fn X509_STORE_CTX_set_current_cert(ctx: *mut X509_STORE_CTX, x: *mut X509);
+    fn X509_STORE_CTX_set_verify_cb(ctx: *mut X509_STORE_CTX, verify_cb: SSL_verify_cb);
+    fn X509_STORE_CTX_set_ex_data(ctx: *mut X509_STORE_CTX, idx: c_int, data: *mut c_void)
+        -> c_int;
 }
@@ -54,6 +54,36 @@ static const char *supported_cmds[] = {
 
     "Options",
     CUSTOM_PREFIX "Options",
+
+    "-cipher",
+    CUSTOM_PREFIX "cipher",
+    "CipherString",
+    CUSTOM_PREFIX "CipherString",
+
+    "-ciphersuites",
+    CUSTOM_PREFIX "ciphersuites",
+    "Ciphersuites",
+    CUSTOM_PREFIX "Ciphersuites",
+
+    "-groups",
+    CUSTOM_PREFIX "groups",
+    "Groups",
+    CUSTOM_PREFIX "Groups",
+
+    "-curves",
+    CUSTOM_PREFIX "curves",
+    "Curves",
+    CUSTOM_PREFIX "Curves",
+
+    "-sigalgs",
+    CUSTOM_PREFIX "sigalgs",
+    "SignatureAlgorithms",
+    CUSTOM_PREFIX "SignatureAlgorithms",
+
+    "-client_sigalgs",
+    CUSTOM_PREFIX "client_sigalgs",
+    "ClientSignatureAlgorithms",
+    CUSTOM_PREFIX "ClientSignatureAlgorithms",
 };
 
 #define NUM_SUPPORTED_CMDS (sizeof(supported_cmds) / sizeof(supported_cmds[0]))